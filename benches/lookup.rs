@@ -0,0 +1,42 @@
+// Compares Rotor::encode_backward (HashMap inverse) against
+// Rotor::encode_backward_array (array inverse).
+//
+//     cargo bench --bench lookup
+//
+// Plain std timing rather than criterion so it runs without extra deps.
+use enigma_machine::Rotor;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const LOOKUPS: usize = 10_000_000;
+
+fn time_lookups(rotor: &mut Rotor, lookup: fn(&Rotor, char) -> char) -> Duration {
+    let start = Instant::now();
+    let mut c = 'A';
+    for i in 0..LOOKUPS {
+        // Turn the rotor as a long message would so the offset keeps changing
+        rotor.position = i % 26;
+        c = lookup(black_box(rotor), black_box(c));
+    }
+    black_box(c);
+    start.elapsed()
+}
+
+fn main() {
+    let (_, wiring, notches) = Rotor::catalog_entry("I").unwrap();
+    let mut rotor = Rotor::new(wiring, notches);
+    rotor.ring_setting = 5;
+
+    // Warm up both paths before measuring
+    time_lookups(&mut rotor, Rotor::encode_backward);
+    time_lookups(&mut rotor, Rotor::encode_backward_array);
+
+    let hashmap = time_lookups(&mut rotor, Rotor::encode_backward);
+    let array = time_lookups(&mut rotor, Rotor::encode_backward_array);
+
+    let per_lookup = |elapsed: Duration| elapsed.as_nanos() as f64 / LOOKUPS as f64;
+    println!("{} lookups", LOOKUPS);
+    println!("HashMap: {:>10.2?} ({:.2} ns/lookup)", hashmap, per_lookup(hashmap));
+    println!("array:   {:>10.2?} ({:.2} ns/lookup)", array, per_lookup(array));
+    println!("speedup: {:.2}x", hashmap.as_secs_f64() / array.as_secs_f64());
+}
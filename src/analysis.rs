@@ -0,0 +1,480 @@
+// Statistics over ciphertext and plaintext, used by the analysis panels
+
+use crate::{Alphabet, EnigmaKey, EnigmaMachine, Rng};
+
+// The k most frequent runs of n consecutive letters, most frequent first.
+// Non-letters are skipped, so n-grams can span them. Ties are broken
+// alphabetically so the result is stable.
+pub fn top_ngrams(text: &str, n: usize, k: usize) -> Vec<(String, usize)> {
+    let letters: Vec<char> = text.chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let mut counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    if n > 0 {
+        for window in letters.windows(n) {
+            *counts.entry(window.iter().collect()).or_insert(0) += 1;
+        }
+    }
+
+    let mut ngrams: Vec<(String, usize)> = counts.into_iter().collect();
+    ngrams.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ngrams.truncate(k);
+    ngrams
+}
+
+// Small embedded wordlists for scoring candidate decodings. Words shorter
+// than three letters turn up by chance too often to be useful.
+pub const GERMAN_WORDS: &[&str] = &[
+    "DER", "DIE", "DAS", "UND", "VON", "NACH", "EINS", "ZWEI", "DREI", "VIER", "FUENF",
+    "SECHS", "SIEBEN", "ACHT", "NEUN", "NULL", "ANGRIFF", "FEIND", "KOMMANDO", "BEFEHL",
+    "WETTER", "BERICHT", "MELDUNG", "HEUTE", "NORD", "SUED", "WEST", "DIVISION", "ARMEE",
+    "FLOTTE", "HAFEN", "SCHIFF", "KONVOI", "QUADRAT", "KURS", "STELLUNG", "FUEHRER",
+    "UHR", "BEI", "MIT", "AUF", "NICHT", "KEINE",
+];
+
+pub const ENGLISH_WORDS: &[&str] = &[
+    "THE", "AND", "FOR", "ARE", "NOT", "YOU", "ALL", "FROM", "WITH", "THIS", "THAT",
+    "HAVE", "WILL", "ATTACK", "DAWN", "ENEMY", "MESSAGE", "REPORT", "WEATHER", "NORTH",
+    "SOUTH", "EAST", "WEST", "STOP", "CONVOY", "HARBOUR", "SHIP", "ORDER", "POSITION",
+];
+
+// Counts dictionary words found in the text. Enigma output has no spaces, so
+// words are matched anywhere in the letters; more hits means more likely plaintext.
+pub fn word_hit_score(text: &str, dict: &[&str]) -> usize {
+    let letters: String = text.chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    dict.iter()
+        .filter(|word| word.len() >= 3)
+        .map(|word| letters.matches(word).count())
+        .sum()
+}
+
+// Character positions where two texts differ. Where one text is longer, its
+// extra characters all count as mismatches.
+pub fn mismatch_indices(actual: &str, expected: &str) -> Vec<usize> {
+    let actual: Vec<char> = actual.chars().collect();
+    let expected: Vec<char> = expected.chars().collect();
+    (0..actual.len().max(expected.len()))
+        .filter(|&i| actual.get(i) != expected.get(i))
+        .collect()
+}
+
+// Lengths of the cycles of a permutation of the letter indices, shortest
+// first. Following A -> perm[A] -> ... until it comes back to A is one cycle.
+pub fn cycle_structure(perm: &[u8; 26]) -> Vec<usize> {
+    let mut seen = [false; 26];
+    let mut lengths = Vec::new();
+    for start in 0..26 {
+        let mut length = 0;
+        let mut i = start;
+        while !seen[i] {
+            seen[i] = true;
+            i = perm[i] as usize;
+            length += 1;
+        }
+        if length > 0 {
+            lengths.push(length);
+        }
+    }
+    lengths.sort();
+    lengths
+}
+
+// Written the way Rejewski's catalogue listed them, e.g. "(3)(3)(4)(4)"
+pub fn format_cycles(lengths: &[usize]) -> String {
+    lengths.iter().map(|length| format!("({})", length)).collect()
+}
+
+// The products Rejewski read off doubled message keys: a letter enciphered
+// at keypress n, then its ciphertext enciphered again at keypress n + 3.
+// Returns AD, BE and CF for the machine's next six keypresses. Each is a
+// product of two involutions, so its cycles come in pairs of equal length.
+pub fn indicator_permutations(machine: &EnigmaMachine) -> [[u8; 26]; 3] {
+    let mut machine = machine.clone();
+    let mappings: Vec<[char; 26]> = (0..6).map(|_| {
+        machine.step();
+        machine.current_mapping()
+    }).collect();
+    [0, 1, 2].map(|n| Alphabet::LETTERS.map(|c| {
+        let first = mappings[n][Alphabet::char_to_index(c)];
+        Alphabet::char_to_index(mappings[n + 3][Alphabet::char_to_index(first)]) as u8
+    }))
+}
+
+// How often each letter occurs, A first. Non-letters are skipped.
+pub fn letter_counts(text: &str) -> [usize; 26] {
+    let mut counts = [0; 26];
+    for c in text.chars().filter(|c| c.is_ascii_alphabetic()) {
+        counts[Alphabet::char_to_index(c.to_ascii_uppercase())] += 1;
+    }
+    counts
+}
+
+// The chance that two letters drawn from the text are the same: 1.0 for a
+// single repeated letter, about 0.066 for English and 0.038 for letters
+// drawn uniformly at random
+pub fn index_of_coincidence(counts: &[usize; 26]) -> f64 {
+    let total: usize = counts.iter().sum();
+    if total < 2 {
+        return 0.0;
+    }
+    let pairs: usize = counts.iter().map(|&n| n * n.saturating_sub(1)).sum();
+    pairs as f64 / (total * (total - 1)) as f64
+}
+
+// Letter counts of a plaintext and of its encipherment, for showing how the
+// rotors flatten even the most lopsided input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flattening {
+    pub input: [usize; 26],
+    pub output: [usize; 26],
+}
+
+// Enciphers `plaintext` on a copy of the machine, counting as it goes
+// rather than building the ciphertext, so long inputs stay cheap
+pub fn flattening(machine: &EnigmaMachine, plaintext: &str) -> Flattening {
+    let mut machine = machine.clone();
+    let mut result = Flattening { input: [0; 26], output: [0; 26] };
+    for c in plaintext.chars().filter(|c| c.is_ascii_alphabetic()) {
+        let c = c.to_ascii_uppercase();
+        result.input[Alphabet::char_to_index(c)] += 1;
+        result.output[Alphabet::char_to_index(machine.encode_char(c))] += 1;
+    }
+    result
+}
+
+// True if both machines encipher the same `sample_len` probe letters the
+// same way, starting from where each one is now. Copies are used, so neither
+// machine moves. The probe is fixed pseudo-random text; a sample long enough
+// to carry the middle rotor over a few times is strong evidence the two
+// configurations are the same cipher.
+pub fn equivalent(a: &EnigmaMachine, b: &EnigmaMachine, sample_len: usize) -> bool {
+    let mut rng = Rng::new(0x454E_4947_4D41);
+    let (mut a, mut b) = (a.clone(), b.clone());
+    (0..sample_len)
+        .map(|_| Alphabet::index_to_char(rng.below(26)))
+        .all(|c| a.encode_char(c) == b.encode_char(c))
+}
+
+// Slips an operator could make with an otherwise correct key, for showing
+// how completely a decode falls apart when the setting is nearly right
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Perturbation {
+    PositionOffByOne,
+    RingOffByOne,
+    SwappedRotors,
+    MissingPlug,
+}
+
+impl Perturbation {
+    pub const ALL: [Perturbation; 4] = [
+        Perturbation::PositionOffByOne,
+        Perturbation::RingOffByOne,
+        Perturbation::SwappedRotors,
+        Perturbation::MissingPlug,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Perturbation::PositionOffByOne => "Right rotor one position on",
+            Perturbation::RingOffByOne => "Right ring one letter on",
+            Perturbation::SwappedRotors => "Two right rotors swapped",
+            Perturbation::MissingPlug => "First plug cable left out",
+        }
+    }
+
+    // A copy of the key with the slip made, or None if it cannot be made
+    // (no cables, or the rotors to swap are the same)
+    pub fn apply(&self, key: &EnigmaKey) -> Option<EnigmaKey> {
+        let mut key = key.clone();
+        let next = |c: char| Alphabet::index_to_char(Alphabet::char_to_index(c) + 1);
+        match self {
+            Perturbation::PositionOffByOne => {
+                let last = key.positions.last_mut()?;
+                *last = next(*last);
+            }
+            Perturbation::RingOffByOne => {
+                let last = key.ring_settings.last_mut()?;
+                *last = next(*last);
+            }
+            Perturbation::SwappedRotors => {
+                let count = key.rotors.len();
+                if count < 2 || key.rotors[count - 1] == key.rotors[count - 2] {
+                    return None;
+                }
+                key.rotors.swap(count - 1, count - 2);
+            }
+            Perturbation::MissingPlug => {
+                if key.plugboard.is_empty() {
+                    return None;
+                }
+                key.plugboard.remove(0);
+            }
+        }
+        Some(key)
+    }
+}
+
+// A decode under one slip, and how many of its letters still match the
+// correct decode
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrongDecode {
+    pub perturbation: Perturbation,
+    pub output: String,
+    pub matching: usize,
+}
+
+// The text run through the key, and through each slip that applies
+pub fn perturbed_decodes(key: &EnigmaKey, text: &str) -> Result<(String, Vec<WrongDecode>), String> {
+    let decode = |key: &EnigmaKey| -> Result<String, String> {
+        let mut output = String::new();
+        key.build()?.encode_decode(text.to_string(), &mut output);
+        Ok(output)
+    };
+    let correct = decode(key)?;
+    let mut decodes = Vec::new();
+    for perturbation in Perturbation::ALL {
+        if let Some(wrong) = perturbation.apply(key) {
+            let output = decode(&wrong)?;
+            let matching = output.chars().zip(correct.chars()).filter(|(a, b)| a == b).count();
+            decodes.push(WrongDecode { perturbation, output, matching });
+        }
+    }
+    Ok((correct, decodes))
+}
+
+// Start positions and ring settings, left to right as on a key sheet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub positions: Vec<char>,
+    pub ring_settings: Vec<char>,
+}
+
+// Finds every start consistent with a known plaintext/ciphertext pair, given
+// the template's wheel order, reflector and plugboard. Only the core offsets
+// (position minus ring) change the wiring, and only the windows of the two
+// fastest rotors decide when they carry, so the slower rotors are reported
+// with ring A: their ring setting can't be told apart from their position.
+// The search covers 26^(rotors + 2) starts, which is meant for three rotors.
+pub fn recover_start(plain: &str, cipher: &str, template: &EnigmaMachine) -> Vec<Setting> {
+    let letters = |text: &str| -> Vec<char> {
+        text.chars().filter(|c| c.is_ascii_alphabetic()).map(|c| c.to_ascii_uppercase()).collect()
+    };
+    let (plain, cipher) = (letters(plain), letters(cipher));
+    if plain.is_empty() || plain.len() != cipher.len() {
+        return Vec::new();
+    }
+
+    let mut machine = template.clone();
+    let rotor_count = machine.rotors.len();
+    let combinations = 26usize.pow(rotor_count as u32);
+    let offsets_of = |index: usize| -> Vec<usize> {
+        (0..rotor_count).map(|i| index / 26usize.pow(i as u32) % 26).collect()
+    };
+    let index_of = |machine: &EnigmaMachine| -> usize {
+        machine.rotors.iter().rev()
+            .fold(0, |index, rotor| index * 26 + (26 + rotor.position - rotor.ring_setting) % 26)
+    };
+
+    // Whether the first letter enciphers correctly, by core offsets after the
+    // first keystroke. Rejects almost every start without stepping further.
+    let first_letter_matches: Vec<bool> = (0..combinations)
+        .map(|index| {
+            for (rotor, offset) in machine.rotors.iter_mut().zip(offsets_of(index)) {
+                rotor.position = offset;
+                rotor.ring_setting = 0;
+            }
+            machine.encipher(plain[0]) == cipher[0]
+        })
+        .collect();
+
+    let set_start = |machine: &mut EnigmaMachine, offsets: &[usize], windows: (usize, usize)| {
+        for (i, (rotor, &offset)) in machine.rotors.iter_mut().zip(offsets).enumerate() {
+            let window = match i {
+                0 => windows.0,
+                1 => windows.1,
+                _ => offset,
+            };
+            rotor.position = window;
+            rotor.ring_setting = (26 + window - offset) % 26;
+        }
+        machine.reflector_position = template.reflector_position;
+    };
+
+    let mut settings = Vec::new();
+    let middle_windows = if rotor_count >= 2 { 26 } else { 1 };
+    for index in 0..combinations {
+        let offsets = offsets_of(index);
+        for window_0 in 0..26 {
+            for window_1 in 0..middle_windows {
+                set_start(&mut machine, &offsets, (window_0, window_1));
+                machine.step();
+                if !first_letter_matches[index_of(&machine)] {
+                    continue;
+                }
+
+                set_start(&mut machine, &offsets, (window_0, window_1));
+                if plain.iter().zip(&cipher).all(|(&p, &c)| machine.encode_char(p) == c) {
+                    set_start(&mut machine, &offsets, (window_0, window_1));
+                    settings.push(Setting {
+                        positions: machine.rotors.iter().rev().map(|rotor| Alphabet::index_to_char(rotor.position)).collect(),
+                        ring_settings: machine.rotors.iter().rev().map(|rotor| Alphabet::index_to_char(rotor.ring_setting)).collect(),
+                    });
+                }
+            }
+        }
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_key_sheet_row, EnigmaKey};
+
+    #[test]
+    fn enciphering_flattens_a_single_letter() {
+        let result = flattening(&EnigmaMachine::default(), &"A".repeat(1000));
+        let input_max = *result.input.iter().max().unwrap();
+        let output_max = *result.output.iter().max().unwrap();
+        assert_eq!(input_max, 1000);
+        assert!(output_max * 10 < input_max, "largest output bucket {}", output_max);
+        assert_eq!(result.output.iter().sum::<usize>(), 1000);
+        // A never enciphers to itself
+        assert_eq!(result.output[0], 0);
+
+        assert_eq!(index_of_coincidence(&result.input), 1.0);
+        assert!(index_of_coincidence(&result.output) < 0.05);
+        assert_eq!(letter_counts("Ab, a!")[..2], [2, 1]);
+    }
+
+    #[test]
+    fn m4_with_beta_at_a_reduces_to_m3() {
+        let m4 = parse_key_sheet_row("Walzenlage Beta II IV I Ringstellung 01 01 01 22 Grundstellung A J N A \
+            Umkehrwalze B-thin Steckerverbindungen AT BL DF GJ HM NW OP QY RZ VX").unwrap().build().unwrap();
+        let m3 = parse_key_sheet_row("Walzenlage II IV I Ringstellung 01 01 22 Grundstellung J N A \
+            Umkehrwalze B Steckerverbindungen AT BL DF GJ HM NW OP QY RZ VX").unwrap().build().unwrap();
+        assert!(equivalent(&m4, &m3, 2000));
+
+        // Beta turned one letter on is a different cipher
+        let mut turned = m4.clone();
+        turned.rotors[3].position = 1;
+        assert!(!equivalent(&turned, &m3, 2000));
+        // Nothing was moved by the comparison
+        assert_eq!(m3.rotors.iter().map(|rotor| rotor.window_letter()).collect::<String>(), "ANJ");
+    }
+
+    #[test]
+    fn one_position_off_garbles_most_letters() {
+        let key = parse_key_sheet_row("Walzenlage II IV V Ringstellung 02 21 12 Grundstellung B L A Umkehrwalze B \
+            Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX").unwrap();
+        let plaintext = "AUFKLXABTEILUNGXVONXKURTINOWAXKURTINOWAXNORDWESTLXSEBEZ";
+        let mut ciphertext = String::new();
+        key.build().unwrap().encode_decode(plaintext.to_string(), &mut ciphertext);
+
+        let (correct, decodes) = perturbed_decodes(&key, &ciphertext).unwrap();
+        assert_eq!(correct, plaintext);
+        assert_eq!(decodes.len(), Perturbation::ALL.len());
+
+        let off_by_one = decodes.iter().find(|decode| decode.perturbation == Perturbation::PositionOffByOne).unwrap();
+        assert_eq!(off_by_one.output.len(), plaintext.len());
+        // Chance agreement is about one letter in 26
+        assert!(off_by_one.matching < plaintext.len() / 5, "{} of {} letters still match: {}", off_by_one.matching, plaintext.len(), off_by_one.output);
+
+        let mut unplugged = key.clone();
+        unplugged.plugboard.clear();
+        assert_eq!(Perturbation::MissingPlug.apply(&unplugged), None);
+    }
+
+    #[test]
+    fn finds_repeated_trigram() {
+        let text = "the cat, the dog; THE end";
+        let trigrams = top_ngrams(text, 3, 2);
+        assert_eq!(trigrams[0], ("THE".to_string(), 3));
+        assert_eq!(trigrams.len(), 2);
+
+        let bigrams = top_ngrams(text, 2, 3);
+        assert_eq!(bigrams, vec![
+            ("HE".to_string(), 3),
+            ("TH".to_string(), 3),
+            ("AT".to_string(), 1),
+        ]);
+    }
+
+    #[test]
+    fn scores_known_words() {
+        assert_eq!(word_hit_score("Attack the convoy at dawn", ENGLISH_WORDS), 4);
+        assert_eq!(word_hit_score("ANGRIFFUNDFEINDNACHNORD", GERMAN_WORDS), 5);
+        assert_eq!(word_hit_score("QXZJVKWPQ", ENGLISH_WORDS), 0);
+        // Words too short to be meaningful are ignored
+        assert_eq!(word_hit_score("AN AT", &["AN", "AT"]), 0);
+    }
+
+    #[test]
+    fn finds_mismatched_positions() {
+        assert_eq!(mismatch_indices("ANGRIFF", "ANGRIFF"), vec![]);
+        assert_eq!(mismatch_indices("ANGXIFE", "ANGRIFF"), vec![3, 6]);
+        // The longer text's extra characters are mismatches too
+        assert_eq!(mismatch_indices("ANGRIFFX", "ANGRIF"), vec![6, 7]);
+        assert_eq!(mismatch_indices("", "AB"), vec![0, 1]);
+    }
+
+    #[test]
+    fn finds_cycles_of_a_known_permutation() {
+        let mut perm: [u8; 26] = std::array::from_fn(|i| i as u8);
+        assert_eq!(cycle_structure(&perm), vec![1; 26]);
+
+        // (A B C)(D E F G)(H I), everything else fixed
+        for cycle in [&[0u8, 1, 2][..], &[3, 4, 5, 6], &[7, 8]] {
+            for (i, &letter) in cycle.iter().enumerate() {
+                perm[letter as usize] = cycle[(i + 1) % cycle.len()];
+            }
+        }
+        let lengths = cycle_structure(&perm);
+        assert_eq!(lengths, [vec![1; 17], vec![2, 3, 4]].concat());
+        assert!(format_cycles(&lengths).ends_with("(1)(2)(3)(4)"));
+
+        let shift: [u8; 26] = std::array::from_fn(|i| ((i + 1) % 26) as u8);
+        assert_eq!(cycle_structure(&shift), vec![26]);
+    }
+
+    #[test]
+    fn indicator_cycles_come_in_pairs() {
+        let key = parse_key_sheet_row("Walzenlage II I III Ringstellung 01 01 01 Steckerverbindungen AB CD EF").unwrap();
+        for perm in indicator_permutations(&key.build().unwrap()) {
+            let lengths = cycle_structure(&perm);
+            assert_eq!(lengths.iter().sum::<usize>(), 26);
+            for length in &lengths {
+                assert_eq!(lengths.iter().filter(|&l| l == length).count() % 2, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn short_or_empty_text_has_no_ngrams() {
+        assert!(top_ngrams("AB", 3, 5).is_empty());
+        assert!(top_ngrams("", 2, 5).is_empty());
+        assert!(top_ngrams("ABC", 0, 5).is_empty());
+    }
+
+    #[test]
+    fn recovers_the_only_consistent_start() {
+        let key = EnigmaKey {
+            positions: vec!['M', 'D', 'K'],
+            ring_settings: vec!['A', 'S', 'F'],
+            ..parse_key_sheet_row("Walzenlage III II I Ringstellung A A A Steckerverbindungen AB CD").unwrap()
+        };
+        let plain = "WETTERBERICHTFUERDIENORDSEEHEUTEKLAR";
+        let mut cipher = String::new();
+        key.build().unwrap().encode_decode(plain.to_string(), &mut cipher);
+
+        let template = parse_key_sheet_row("Walzenlage III II I Ringstellung A A A Steckerverbindungen AB CD").unwrap();
+        let settings = recover_start(plain, &cipher, &template.build().unwrap());
+        assert_eq!(settings, vec![Setting { positions: key.positions, ring_settings: key.ring_settings }]);
+    }
+}
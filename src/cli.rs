@@ -0,0 +1,236 @@
+// Command-line file encoding: `--in message.txt --out cipher.txt` plus
+// optional settings flags, applied on top of the default demo key. `--repl`
+// takes the same settings flags and enciphers lines typed at a prompt.
+// `--gen-vectors` prints golden test vectors, or checks them against a file.
+
+use crate::key_sheet::random_key;
+use crate::{parse_key_sheet_row, parse_setting_letter, Alphabet, EnigmaKey, EnigmaMachine, Plugboard, Reflector, Rotor, Rng, PRESETS, sanitize};
+use std::io::{BufRead, Write};
+
+pub const USAGE: &str = "Usage: enigma_machine --in <file> --out <file> [--key <key sheet row>] \
+[--rotors \"I II III\"] [--rings \"01 01 01\"] [--positions AAA] [--plugboard \"AB CD\"] [--reflector B]
+       enigma_machine --repl [settings as above]
+       enigma_machine --gen-vectors [--check <file>]";
+
+pub const REPL_HELP: &str = "Type a line to encipher it; the rotors keep turning from one line to the next.
+  :pos XYZ     set the rotor positions, left to right
+  :reset       back to the positions the session started with
+  :key         show the current key
+  :key <row>   switch to a key sheet row
+  :help        show this list
+  :quit        leave (end of input does too)";
+
+pub struct FileJob {
+    pub input: std::path::PathBuf,
+    pub output: std::path::PathBuf,
+    pub key: EnigmaKey,
+}
+
+// Settings are given as in a key sheet row ("01 13 22", "A M V"), or as a
+// run of letters ("AMV")
+fn setting_letters(value: &str) -> Result<Vec<char>, String> {
+    if !value.contains(char::is_whitespace) && value.len() > 1 && value.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok(value.to_uppercase().chars().collect());
+    }
+    value.split_whitespace().map(parse_setting_letter).collect()
+}
+
+pub fn parse_args(args: &[String]) -> Result<FileJob, String> {
+    let (input, output, key) = parse_flags(args)?;
+    Ok(FileJob {
+        input: input.ok_or_else(|| format!("Missing --in.\n{}", USAGE))?,
+        output: output.ok_or_else(|| format!("Missing --out.\n{}", USAGE))?,
+        key,
+    })
+}
+
+// The key for `--repl`, from the settings flags that follow it
+pub fn parse_repl_args(args: &[String]) -> Result<EnigmaKey, String> {
+    match parse_flags(args)? {
+        (None, None, key) => Ok(key),
+        _ => Err(format!("--in and --out do not apply to --repl.\n{}", USAGE)),
+    }
+}
+
+type Flags = (Option<std::path::PathBuf>, Option<std::path::PathBuf>, EnigmaKey);
+
+fn parse_flags(args: &[String]) -> Result<Flags, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut key = parse_key_sheet_row(PRESETS[0].1)?;
+    let mut rotors = None;
+    let mut rings = None;
+    let mut positions = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("Missing value for {}.\n{}", flag, USAGE))?;
+        match flag.as_str() {
+            "--in" => input = Some(std::path::PathBuf::from(value)),
+            "--out" => output = Some(std::path::PathBuf::from(value)),
+            "--key" => key = parse_key_sheet_row(value)?,
+            "--rotors" => rotors = Some(value),
+            "--rings" => rings = Some(value),
+            "--positions" => positions = Some(value),
+            "--plugboard" => key.plugboard = Plugboard::parse_pairs(&value.to_uppercase())?,
+            "--reflector" => {
+                key.reflector = Reflector::by_name(value)
+                    .map(|reflector| reflector.name.to_string())
+                    .ok_or_else(|| format!("Unknown reflector '{}'.", value))?;
+            }
+            _ => return Err(format!("Unknown option '{}'.\n{}", flag, USAGE)),
+        }
+    }
+
+    // A different rotor order starts from rings and positions at A unless
+    // those are given as well
+    if let Some(value) = rotors {
+        key.rotors = value.split_whitespace()
+            .map(|name| Rotor::catalog_entry(name)
+                .map(|(name, _, _)| name.to_string())
+                .ok_or_else(|| format!("Unknown rotor '{}'.", name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        key.ring_settings = vec!['A'; key.rotors.len()];
+        key.positions = vec!['A'; key.rotors.len()];
+    }
+    if let Some(value) = rings {
+        key.ring_settings = setting_letters(value)?;
+    }
+    if let Some(value) = positions {
+        key.positions = setting_letters(value)?;
+    }
+
+    Ok((input, output, key))
+}
+
+// Streams the input file through the configured machine into the output
+// file; returns the number of letters written
+pub fn run(job: &FileJob) -> Result<usize, String> {
+    let mut machine = job.key.build()?;
+    let input = std::fs::File::open(&job.input)
+        .map_err(|e| format!("Could not open {}: {}", job.input.display(), e))?;
+    let output = std::fs::File::create(&job.output)
+        .map_err(|e| format!("Could not create {}: {}", job.output.display(), e))?;
+
+    machine.encode_stream(std::io::BufReader::new(input), std::io::BufWriter::new(output))
+        .map_err(|e| format!("{}: {}", job.input.display(), e))
+}
+
+// The golden set is always drawn from the same seed, so a change in any
+// vector means the cipher changed
+pub const VECTOR_SEED: u64 = 1940;
+pub const VECTOR_COUNT: usize = 16;
+
+// `count` random daily keys, each with a random plaintext and its
+// ciphertext, as blocks of `key:`, `plain:` and `cipher:` lines
+pub fn generate_vectors(seed: u64, count: usize) -> Result<String, String> {
+    let mut rng = Rng::new(seed);
+    let mut vectors = format!("# enigma_machine test vectors, seed {}\n", seed);
+    for _ in 0..count {
+        let key = random_key(&mut rng);
+        let length = 20 + rng.below(61);
+        let plain: String = (0..length).map(|_| Alphabet::index_to_char(rng.below(26))).collect();
+        let mut cipher = String::new();
+        key.build()?.encode_decode(plain.clone(), &mut cipher);
+        vectors += &format!("\nkey: {}\nplain: {}\ncipher: {}\n", key, plain, cipher);
+    }
+    Ok(vectors)
+}
+
+// Compares freshly generated vectors with a golden file, naming the first
+// line that differs
+pub fn check_vectors(golden: &str, generated: &str) -> Result<usize, String> {
+    let mut golden_lines = golden.lines();
+    let mut generated_lines = generated.lines();
+    for number in 1.. {
+        match (golden_lines.next(), generated_lines.next()) {
+            (None, None) => break,
+            (expected, actual) if expected == actual => {}
+            (expected, actual) => {
+                return Err(format!(
+                    "Line {} differs.\n  golden:    {}\n  generated: {}",
+                    number,
+                    expected.unwrap_or("(end of file)"),
+                    actual.unwrap_or("(end of output)"),
+                ));
+            }
+        }
+    }
+    Ok(generated.lines().filter(|line| line.starts_with("key: ")).count())
+}
+
+// An interactive session: one machine configured up front, stepping on from
+// line to line until the settings are changed with a command
+pub struct Repl {
+    key: EnigmaKey,
+    machine: EnigmaMachine,
+}
+
+impl Repl {
+    pub fn new(key: EnigmaKey) -> Result<Self, String> {
+        let machine = key.build()?;
+        Ok(Self { key, machine })
+    }
+
+    // The current key with the rotors where they are now
+    pub fn current_key(&self) -> EnigmaKey {
+        let mut key = self.key.clone();
+        key.positions = self.machine.rotors.iter().rev().map(|rotor| rotor.window_letter()).collect();
+        key
+    }
+
+    // Handles one line of input: a command, or text to encipher. Returns
+    // what to print, or None when the session should end.
+    pub fn handle(&mut self, line: &str) -> Option<Result<String, String>> {
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        Some(match command {
+            ":quit" | ":q" => return None,
+            ":help" => Ok(REPL_HELP.to_string()),
+            ":reset" => self.key.build().map(|machine| {
+                self.machine = machine;
+                format!("Positions reset to {}.", self.key.positions.iter().collect::<String>())
+            }),
+            ":pos" => self.set_positions(argument),
+            ":key" if argument.is_empty() => Ok(self.current_key().to_string()),
+            ":key" => parse_key_sheet_row(argument).and_then(|key| {
+                *self = Self::new(key)?;
+                Ok(format!("Key set: {}", self.key))
+            }),
+            _ if command.starts_with(':') => Err(format!("Unknown command '{}'. Type :help for the list.", command)),
+            _ => Ok(sanitize(line).0.chars()
+                .map(|c| self.machine.encode_char(c))
+                .collect()),
+        })
+    }
+
+    fn set_positions(&mut self, value: &str) -> Result<String, String> {
+        let letters = setting_letters(value)?;
+        // Typed left to right; the machine keeps its rotors right to left
+        self.machine.set_positions(&letters.iter().rev().copied().collect::<Vec<_>>())?;
+        Ok(format!("Positions set to {}.", letters.iter().collect::<String>()))
+    }
+}
+
+// Reads commands and text until `:quit` or end of input, writing a prompt
+// before each line. Errors in a line are printed and the session goes on.
+pub fn run_repl<R: BufRead, W: Write>(repl: &mut Repl, input: R, mut output: W) -> Result<(), String> {
+    let write_error = |e: std::io::Error| format!("Could not write output: {}", e);
+    let mut lines = input.lines();
+    loop {
+        write!(output, "> ").and_then(|_| output.flush()).map_err(write_error)?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|e| format!("Could not read input: {}", e))?;
+        match repl.handle(&line) {
+            Some(Ok(text)) => writeln!(output, "{}", text),
+            Some(Err(error)) => writeln!(output, "Error: {}", error),
+            None => return Ok(()),
+        }
+        .map_err(write_error)?;
+    }
+}
@@ -0,0 +1,124 @@
+// Printable monthly key sheets in the style of the Heer and Luftwaffe
+// codebooks: one row per day, the last day at the top so used rows could be
+// cut off and burnt
+
+use crate::{Alphabet, EnigmaKey, Plugboard, Rng, Rotor};
+
+const HEADINGS: [&str; 5] = ["Datum", "Walzenlage", "Ringstellung", "Steckerverbindungen", "Kenngruppen"];
+
+// Ten cables, as issued from 1939
+const PLUGS: usize = 10;
+
+// A daily key as the codebook compilers drew it: three different rotors
+// from I-V, random rings and plugs, reflector B. The Grundstellung is
+// random too, though sheets after 1940 no longer printed it.
+pub fn random_key(rng: &mut Rng) -> EnigmaKey {
+    let mut rotors: Vec<String> = Rotor::CATALOG[..5].iter().map(|(name, _, _)| name.to_string()).collect();
+    rng.shuffle(&mut rotors);
+    rotors.truncate(3);
+
+    EnigmaKey {
+        reflector: "B".to_string(),
+        rotors,
+        ring_settings: (0..3).map(|_| Alphabet::index_to_char(rng.below(26))).collect(),
+        positions: (0..3).map(|_| Alphabet::index_to_char(rng.below(26))).collect(),
+        plugboard: Plugboard::random_pairs(rng, PLUGS),
+    }
+}
+
+// Four trigrams per day that identify which key a message belongs to, a
+// small table standing in for the naval Kenngruppenbuch. They are not part
+// of the machine setting, so they are derived from the key itself and the
+// same key always prints the same groups.
+pub fn kenngruppen(key: &EnigmaKey) -> Vec<String> {
+    // FNV-1a over the key sheet row
+    let seed = key.to_string().bytes()
+        .fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3));
+    let mut rng = Rng::new(seed);
+    (0..4)
+        .map(|_| (0..3).map(|_| Alphabet::index_to_char(rng.below(26))).collect::<String>())
+        .collect()
+}
+
+// An ASCII table with a row per day, day 1 being the first key. Ring
+// settings are printed as numbers, as on the original sheets.
+pub fn render_key_sheet(days: &[EnigmaKey]) -> String {
+    let rows: Vec<[String; 5]> = days.iter().enumerate().rev()
+        .map(|(day, key)| [
+            (day + 1).to_string(),
+            key.rotors.join(" "),
+            key.ring_settings.iter().map(|&c| format!("{:02}", Alphabet::char_to_number(c))).collect::<Vec<_>>().join(" "),
+            key.plugboard.iter().map(|(a, b)| format!("{}{}", a, b)).collect::<Vec<_>>().join(" "),
+            kenngruppen(key).join(" "),
+        ])
+        .collect();
+
+    let mut widths = HEADINGS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let rule: String = widths.iter().map(|&width| format!("+{}", "-".repeat(width + 2))).collect::<String>() + "+\n";
+    let line = |cells: &[String]| -> String {
+        cells.iter().zip(&widths)
+            .enumerate()
+            // Day numbers are right-aligned, everything else left
+            .map(|(i, (cell, &width))| if i == 0 { format!("| {:>width$} ", cell) } else { format!("| {:<width$} ", cell) })
+            .collect::<String>() + "|\n"
+    };
+
+    let mut sheet = rule.clone();
+    sheet += &line(&HEADINGS.map(str::to_string));
+    sheet += &rule;
+    for row in &rows {
+        sheet += &line(row);
+    }
+    sheet += &rule;
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_key_sheet_row, validate_physical};
+
+    #[test]
+    fn renders_header_and_rows_last_day_first() {
+        let first = parse_key_sheet_row("Walzenlage II IV V Ringstellung 06 22 14 Steckerverbindungen AD CN ET FL GI JV KZ PU QY WX").unwrap();
+        let second = parse_key_sheet_row("Walzenlage I II III Ringstellung 01 01 01 Steckerverbindungen AB").unwrap();
+        let sheet = render_key_sheet(&[first.clone(), second]);
+        let lines: Vec<&str> = sheet.lines().collect();
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "+-------+------------+--------------+-------------------------------+-----------------+");
+        assert_eq!(lines[1], "| Datum | Walzenlage | Ringstellung | Steckerverbindungen           | Kenngruppen     |");
+        assert_eq!(lines[2], lines[0]);
+        assert!(lines[3].starts_with("|     2 | I II III   | 01 01 01     | AB                            | "));
+        assert_eq!(
+            lines[4],
+            format!("|     1 | II IV V    | 06 22 14     | AD CN ET FL GI JV KZ PU QY WX | {} |", kenngruppen(&first).join(" ")),
+        );
+        assert_eq!(lines[5], lines[0]);
+    }
+
+    #[test]
+    fn kenngruppen_are_four_trigrams_fixed_by_the_key() {
+        let key = random_key(&mut Rng::new(1));
+        let groups = kenngruppen(&key);
+        assert_eq!(groups.len(), 4);
+        assert!(groups.iter().all(|group| group.len() == 3 && group.chars().all(|c| c.is_ascii_uppercase())));
+        assert_eq!(groups, kenngruppen(&key.clone()));
+    }
+
+    #[test]
+    fn random_keys_are_physically_possible() {
+        let mut rng = Rng::new(31);
+        for _ in 0..100 {
+            let key = random_key(&mut rng);
+            assert_eq!(validate_physical(&key), vec![]);
+            assert!(key.build().is_ok());
+        }
+    }
+}
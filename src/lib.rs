@@ -0,0 +1,2830 @@
+pub mod analysis;
+pub mod cli;
+pub mod key_sheet;
+pub mod transmission;
+pub mod walkthrough;
+
+pub struct Alphabet;
+
+impl Alphabet {
+    pub const LETTERS: &'static [char; 26] = &[
+        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M',
+        'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
+    ];
+
+    pub fn char_to_index(c: char) -> usize {
+        c as usize - 'A' as usize
+    }
+
+    pub fn index_to_char(index: usize) -> char {
+        Alphabet::LETTERS[index % 26]
+    }
+
+    // Key sheets number the letters 01-26, so A is 1 and Z is 26
+    pub fn char_to_number(c: char) -> usize {
+        Alphabet::char_to_index(c) + 1
+    }
+
+    pub fn number_to_char(number: usize) -> Option<char> {
+        (1..=26).contains(&number).then(|| Alphabet::index_to_char(number - 1))
+    }
+}
+
+#[derive(Clone)]
+pub struct Rotor {
+    wiring: Vec<char>,
+    reverse_lookup: std::collections::HashMap<char, usize>,
+    reverse_wiring: [usize; 26],
+    pub notches: Vec<char>,
+    pub position: usize,
+    pub ring_setting: usize,
+    // Stators and the M4's Greek rotor are fixed in place
+    pub steps: bool,
+    // For a surviving machine whose ring is engraved out of step with the
+    // standard alphabet: the engraved letter sits this many letters after the
+    // standard one. Only what the window shows changes; unlike the ring
+    // setting it never reaches the wiring.
+    pub calibration: usize,
+}
+
+impl Rotor {
+    // (name, wiring, notches) of the standard Wehrmacht and Kriegsmarine rotors
+    pub const CATALOG: &'static [(&'static str, &'static str, &'static str)] = &[
+        ("I", "EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q"),
+        ("II", "AJDKSIRUXBLHWTMCQGZNPYFVOE", "E"),
+        ("III", "BDFHJLCPRTXVZNYEIWGAKMUSQO", "V"),
+        ("IV", "ESOVPZJAYQUIRHXLNFTGKDCMWB", "J"),
+        ("V", "VZBRGITYUPSDNHLXAWMJQOFECK", "Z"),
+        ("VI", "JPGVOUMFYQBENHZRDKASXLICTW", "ZM"),
+        ("VII", "NZJHGRCXMYSWBOUFAIVLPEKQDT", "ZM"),
+        ("VIII", "FKQHTLXOCBJSPDZRAMEWNIUYGV", "ZM"),
+        ("Beta", "LEYJVCNIXWPBQMDRTAKZGFUHOS", ""),
+        ("Gamma", "FSOKANUERHMBTIYCWLQPZXVGJD", ""),
+    ];
+
+    pub fn catalog_entry(name: &str) -> Option<(&'static str, &'static str, &'static str)> {
+        Rotor::CATALOG.iter().copied().find(|(entry, _, _)| entry.eq_ignore_ascii_case(name))
+    }
+
+    pub fn new(wiring: &str, notches: &str) -> Self {
+        let wiring_array: Vec<char> = wiring.chars().collect();
+        // Reverse lookup for rotor makes encode_backward O(1) instead of O(n)
+        let reverse_lookup: std::collections::HashMap<char, usize> = wiring_array.iter().enumerate()
+            .map(|(i, &c)| (c, i))
+            .collect();
+        // The same inverse as a plain array, indexed by contact number
+        let mut reverse_wiring = [0; 26];
+        for (i, &c) in wiring_array.iter().enumerate() {
+            reverse_wiring[Alphabet::char_to_index(c)] = i;
+        }
+
+        Self {
+            wiring: wiring_array,
+            reverse_lookup,
+            reverse_wiring,
+            notches: notches.chars().collect(),
+            position: 0,
+            ring_setting: 0,
+            steps: true,
+            calibration: 0,
+        }
+    }
+
+    // The wiring as letters, contact A first
+    pub fn wiring(&self) -> String {
+        self.wiring.iter().collect()
+    }
+
+    // The catalog name of a rotor with this wiring, if it is a standard one
+    pub fn catalog_name(&self) -> Option<&'static str> {
+        Rotor::CATALOG.iter()
+            .find(|(_, wiring, _)| wiring.chars().eq(self.wiring.iter().copied()))
+            .map(|&(name, _, _)| name)
+    }
+
+    // The ring setting turns the wiring core against the lettered ring, so it
+    // offsets the wiring in the opposite direction to the position
+    fn offset(&self) -> usize {
+        (26 + self.position - self.ring_setting) % 26
+    }
+
+    // `position` is what the operator sees through the window: the letter on
+    // the ring. The wiring core sits `ring_setting` letters behind it.
+    pub fn window_letter(&self) -> char {
+        Alphabet::index_to_char(self.position)
+    }
+
+    // The letter a calibrated unit shows in its window
+    pub fn engraved_letter(&self) -> char {
+        Alphabet::index_to_char((self.position + self.calibration) % 26)
+    }
+
+    // Turns the rotor until the calibrated window shows `letter`
+    pub fn set_engraved_position(&mut self, letter: char) {
+        self.position = (26 + Alphabet::char_to_index(letter) - self.calibration % 26) % 26;
+    }
+
+    pub fn core_letter(&self) -> char {
+        Alphabet::index_to_char(self.offset())
+    }
+
+    // The signal enters the contact shifted by the rotor's offset and leaves
+    // through a contact shifted back by the same amount
+    pub fn encode_forward(&self, input: char) -> char {
+        let index = Alphabet::char_to_index(input);
+        let shifted_index = (index + self.offset()) % 26;
+        let output = Alphabet::char_to_index(self.wiring[shifted_index]);
+        Alphabet::index_to_char(26 + output - self.offset())
+    }
+        
+    pub fn encode_backward(&self, input: char) -> char {
+        let shifted_input = Alphabet::index_to_char(Alphabet::char_to_index(input) + self.offset());
+        let index = *self.reverse_lookup.get(&shifted_input).expect("Invalid character in reverse lookup");
+        let shifted_index = (26 + index - self.offset()) % 26;
+        Alphabet::index_to_char(shifted_index)
+    }
+
+    // encode_backward through the array inverse instead of the HashMap.
+    // benches/lookup.rs compares the two.
+    pub fn encode_backward_array(&self, input: char) -> char {
+        let shifted_index = (Alphabet::char_to_index(input) + self.offset()) % 26;
+        let index = self.reverse_wiring[shifted_index];
+        Alphabet::index_to_char(26 + index - self.offset())
+    }
+    
+    pub fn rotate(&mut self) -> bool {
+        self.position = (self.position + 1) % 26;
+        self.at_notch()
+    }
+
+    pub fn at_notch(&self) -> bool {
+        self.notches.contains(&Alphabet::index_to_char(self.position))
+    }
+
+    // Accepts a single notch letter or a comma-separated set, e.g. "Z, M"
+    pub fn parse_notches(input: &str) -> Result<Vec<char>, String> {
+        let mut notches = Vec::new();
+        for part in input.split(',') {
+            let part = part.trim().to_uppercase();
+            let mut chars = part.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => {
+                    if !notches.contains(&c) {
+                        notches.push(c);
+                    }
+                }
+                _ => return Err(format!("Invalid notch '{}': Notches must be single letters A-Z.", part)),
+            }
+        }
+        Ok(notches)
+    }
+
+    pub fn set_position(&mut self, pos: char) {
+        self.position = Alphabet::char_to_index(pos);
+    }
+
+    pub fn set_ring_setting(&mut self, ring: char) {
+        self.ring_setting = Alphabet::char_to_index(ring);
+    }
+}
+
+#[derive(Clone)]
+pub struct Plugboard {
+    swaps: std::collections::HashMap<char, char>,
+}
+
+impl Plugboard {
+    // Each cable joins two different letters, and a letter takes at most one
+    // cable, so the board is always an involution
+    pub fn new(pairs: &[(char, char)]) -> Result<Self, String> {
+        let mut swaps = std::collections::HashMap::new();
+        for &(a, b) in pairs {
+            if !a.is_ascii_uppercase() || !b.is_ascii_uppercase() {
+                return Err(format!("Invalid plugboard configuration: '{}{}' must join two letters A-Z.", a, b));
+            }
+            if a == b || swaps.contains_key(&a) || swaps.contains_key(&b) {
+                return Err(format!("Invalid plugboard configuration: duplicate letters or invalid pair '{}{}'.", a, b));
+            }
+            swaps.insert(a, b);
+            swaps.insert(b, a);
+        }
+        Ok(Self { swaps })
+    }
+
+    pub fn swap(&self, input: char) -> char {
+        *self.swaps.get(&input).unwrap_or(&input)
+    }
+
+    // Each connection once, lowest letter first, sorted
+    pub fn pairs(&self) -> Vec<(char, char)> {
+        let mut pairs: Vec<(char, char)> = self.swaps.iter()
+            .filter(|(a, b)| a < b)
+            .map(|(&a, &b)| (a, b))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    // Pulls out the cable plugged into `letter`, if there is one
+    pub fn remove_pair(&mut self, letter: char) -> bool {
+        match self.swaps.remove(&letter) {
+            Some(other) => {
+                self.swaps.remove(&other);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Parses whitespace separated pairs, e.g. "AB CD"
+    pub fn parse_pairs(input: &str) -> Result<Vec<(char, char)>, String> {
+        let mut plugboard_pairs = Vec::new();
+        let mut letter_set = std::collections::HashSet::new();
+
+        for pair_str in input.split_whitespace() {
+            // Each pair should be exactly 2 letters long
+            let chars: Vec<char> = pair_str.chars().map(|c| c.to_ascii_uppercase()).collect();
+            if let [a, b] = chars[..] {
+                if !a.is_ascii_uppercase() || !b.is_ascii_uppercase() {
+                    return Err(format!("Invalid plugboard configuration: '{}' must join two letters A-Z.", pair_str));
+                }
+                let pair = (a, b);
+
+                // Check for duplicate or invalid pairs
+                if pair.0 == pair.1 || letter_set.contains(&pair.0) || letter_set.contains(&pair.1) {
+                    return Err(format!("Invalid plugboard configuration: duplicate letters or invalid pair '{}{}'.", pair.0, pair.1));
+                }
+                plugboard_pairs.push(pair);
+                letter_set.insert(pair.0);
+                letter_set.insert(pair.1);
+            } else {
+                return Err(format!("Invalid input: Plugboard pairs must be exactly 2 letters. '{}' is invalid.", pair_str));
+            }
+        }
+
+        Ok(plugboard_pairs)
+    }
+
+    // Picks `count` non-overlapping pairs, each written lowest letter first and
+    // sorted, so they read the same way they would be typed into the input
+    pub fn random_pairs(rng: &mut Rng, count: usize) -> Vec<(char, char)> {
+        let mut letters = *Alphabet::LETTERS;
+        rng.shuffle(&mut letters);
+
+        let mut pairs: Vec<(char, char)> = letters
+            .chunks(2)
+            .take(count.min(13))
+            .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+}
+
+#[derive(Clone)]
+pub struct EnigmaMachine {
+    pub rotors: Vec<Rotor>,
+    reflector: [char; 26],
+    pub plugboard: Plugboard,
+    // Not historical: turning this off makes the rotors step like an odometer
+    pub double_stepping: bool,
+    // Only the commercial K / Swiss-K reflectors could be set to a position
+    pub rotatable_reflector: bool,
+    pub reflector_position: usize,
+    // Diagnostic only: skipping the reflector breaks reciprocity entirely
+    pub bypass_reflector: bool,
+    // Debugging aid: the plugboard passes every letter straight through but
+    // keeps its pairs for when it is switched back on
+    pub bypass_plugboard: bool,
+    // Zählwerk machines (A28, G31) drive the wheels through gears instead of
+    // pawls: each wheel carries into the next whenever it leaves one of its
+    // many notches, the reflector included, and the drive can be turned back
+    pub gear_stepping: bool,
+    // Keypresses since the machine was set up, as the Zählwerk counter shows
+    pub key_count: u64,
+    // Entry wheel (Eintrittswalze): the rotor contact each key is wired to,
+    // by letter index. Military machines were wired straight through.
+    pub entry_wheel: [u8; 26],
+}
+
+impl EnigmaMachine {
+    pub fn new(rotor_configurations: Vec<(&str, &str)>, reflector_wiring: &str, plugboard_pairs: &[(char, char)]) -> Result<Self, String> {
+        let reflector: [char; 26] = reflector_wiring.chars().collect::<Vec<_>>().try_into()
+            .map_err(|_| format!("Invalid reflector wiring: expected 26 letters, got {}.", reflector_wiring.chars().count()))?;
+        if let Some(c) = reflector.iter().find(|c| !c.is_ascii_uppercase()) {
+            return Err(format!("Invalid reflector wiring: '{}' is not a letter A-Z.", c));
+        }
+        let diagnosis = diagnose_reflector(&reflector);
+        if !diagnosis.is_valid() {
+            return Err(format!("Invalid reflector wiring: {}", diagnosis.problems().join(" ")));
+        }
+
+        // Every component has to wire the same alphabet as the reflector; a
+        // shorter rotor would index past its wiring mid-message
+        for (i, (wiring, _)) in rotor_configurations.iter().enumerate() {
+            let contacts = wiring.chars().count();
+            if contacts != reflector.len() {
+                return Err(format!(
+                    "Rotor {} has {} contacts but the reflector has {}; every component must use the same alphabet.",
+                    i + 1, contacts, reflector.len(),
+                ));
+            }
+            // Rotor::new builds the inverse wiring and would panic on
+            // anything else
+            if !is_alphabet_permutation(wiring) {
+                return Err(format!("Rotor {} wiring must use each letter A-Z exactly once.", i + 1));
+            }
+        }
+        let rotors = rotor_configurations
+            .into_iter()
+            .map(|(wiring, notches)| Rotor::new(wiring, notches))
+            .collect();
+
+        let plugboard = Plugboard::new(plugboard_pairs)?;
+
+        Ok(Self {
+            rotors,
+            reflector,
+            plugboard,
+            double_stepping: true,
+            rotatable_reflector: false,
+            reflector_position: 0,
+            bypass_reflector: false,
+            bypass_plugboard: false,
+            gear_stepping: false,
+            key_count: 0,
+            entry_wheel: Self::STRAIGHT_ENTRY_WHEEL,
+        })
+    }
+
+    pub const STRAIGHT_ENTRY_WHEEL: [u8; 26] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+    ];
+
+    // Commercial machines wired the keys in keyboard order, Q to contact A,
+    // W to B and so on along QWERTZUIOASDFGHJKPYXCVBNML
+    pub const QWERTZ_ENTRY_WHEEL: [u8; 26] = [
+        9, 22, 20, 11, 2, 12, 13, 14, 7, 15, 16, 25, 24, 23, 8, 17, 0, 3, 10, 4, 6, 21, 1, 19, 18, 5,
+    ];
+
+    fn stecker(&self, c: char) -> char {
+        if self.bypass_plugboard {
+            c
+        } else {
+            self.plugboard.swap(c)
+        }
+    }
+
+    fn enter(&self, c: char) -> char {
+        Alphabet::index_to_char(self.entry_wheel[Alphabet::char_to_index(c)] as usize)
+    }
+
+    fn exit(&self, c: char) -> char {
+        let contact = Alphabet::char_to_index(c) as u8;
+        Alphabet::index_to_char(self.entry_wheel.iter().position(|&wire| wire == contact).expect("the entry wheel is a permutation"))
+    }
+
+    // The key sheet row this machine was set up from, as far as it can be
+    // told from the wiring: None for rotors or reflectors not in the catalogs
+    pub fn to_key(&self) -> Option<EnigmaKey> {
+        let reflector = Reflector::all().iter()
+            .map(|&id| Reflector::by_id(id))
+            .find(|reflector| reflector.wiring.is_some_and(|wiring| wiring.chars().eq(self.reflector.iter().copied())))?;
+        let rotors = self.rotors.iter().rev()
+            .map(|rotor| rotor.catalog_name().map(str::to_string))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(EnigmaKey {
+            reflector: reflector.name.to_string(),
+            rotors,
+            ring_settings: self.rotors.iter().rev().map(|rotor| Alphabet::index_to_char(rotor.ring_setting)).collect(),
+            positions: self.rotors.iter().rev().map(|rotor| rotor.window_letter()).collect(),
+            plugboard: self.plugboard.pairs(),
+        })
+    }
+
+    // Window letters in the same order as `rotors`
+    pub fn set_positions(&mut self, positions: &[char]) -> Result<(), String> {
+        self.check_positions(positions)?;
+        for (rotor, &position) in self.rotors.iter_mut().zip(positions) {
+            rotor.set_position(position);
+        }
+        Ok(())
+    }
+
+    // What set_positions accepts, checked without turning any rotor
+    fn check_positions(&self, positions: &[char]) -> Result<(), String> {
+        if positions.len() != self.rotors.len() {
+            return Err(format!("Invalid input: Expected {} positions, got {}.", self.rotors.len(), positions.len()));
+        }
+        if let Some(c) = positions.iter().find(|c| !c.is_ascii_uppercase()) {
+            return Err(format!("Invalid input: {} is not an alphabetic character.", c));
+        }
+        Ok(())
+    }
+
+    // Ringstellung AAA, leaving the window letters where they are
+    pub fn clear_ring_settings(&mut self) {
+        for rotor in &mut self.rotors {
+            rotor.ring_setting = 0;
+        }
+    }
+
+    pub fn calibrations(&self) -> Vec<usize> {
+        self.rotors.iter().map(|rotor| rotor.calibration).collect()
+    }
+
+    // Carries a unit's calibration over to rebuilt rotors, slot by slot;
+    // slots without an entry stay standard
+    pub fn set_calibrations(&mut self, calibrations: &[usize]) {
+        for (i, rotor) in self.rotors.iter_mut().enumerate() {
+            rotor.calibration = calibrations.get(i).copied().unwrap_or(0);
+        }
+    }
+
+    // The operator procedure for a message key: set the rotors to the ground
+    // setting (Grundstellung), encipher the chosen message key to get the
+    // indicator that is transmitted, then turn the rotors to the message key
+    // for the body. Returns the indicator. Both settings are checked before
+    // any rotor moves, so an error leaves the machine where it was.
+    pub fn encipher_message_key(&mut self, ground: &[char], message_key: &[char]) -> Result<String, String> {
+        self.check_positions(ground)?;
+        self.check_positions(message_key)?;
+        self.set_positions(ground)?;
+        let indicator: String = message_key.iter().map(|&c| self.encode_char(c)).collect();
+        self.set_positions(message_key)?;
+        Ok(indicator)
+    }
+
+    // The receiving side: deciphers the indicator at the ground setting and
+    // leaves the rotors at the recovered message key
+    pub fn recover_message_key(&mut self, ground: &[char], indicator: &[char]) -> Result<Vec<char>, String> {
+        self.check_positions(ground)?;
+        self.check_positions(indicator)?;
+        self.set_positions(ground)?;
+        let message_key: Vec<char> = indicator.iter().map(|&c| self.encode_char(c)).collect();
+        self.set_positions(&message_key)?;
+        Ok(message_key)
+    }
+
+    pub fn reflector_wiring(&self) -> [char; 26] {
+        self.reflector
+    }
+
+    // Only a valid reflector is accepted, so no letter can encipher to itself
+    pub fn set_reflector(&mut self, wiring: [char; 26]) -> Result<(), String> {
+        let diagnosis = diagnose_reflector(&wiring);
+        if !diagnosis.is_valid() {
+            return Err(format!("Invalid reflector wiring: {}", diagnosis.problems().join(" ")));
+        }
+        self.reflector = wiring;
+        Ok(())
+    }
+
+    // One keypress worth of stepping, without enciphering anything: gear
+    // drive, pawls with the double step, or a plain odometer, whichever is
+    // active. Every keypress goes through here.
+    pub fn step(&mut self) {
+        self.key_count = self.key_count.wrapping_add(1);
+        if self.gear_stepping {
+            self.gear_step(1);
+            return;
+        }
+
+        // Fixed rotors are skipped, so the first rotor that steps is the fast one
+        let mut stepping: Vec<&mut Rotor> = self.rotors.iter_mut().filter(|rotor| rotor.steps).collect();
+        // The pawls all move at once, so which rotors turn depends only on
+        // the notches showing before the keypress
+        let at_notch: Vec<bool> = stepping.iter().map(|rotor| rotor.at_notch()).collect();
+        let mut turns = vec![false; stepping.len()];
+        if let Some(fast) = turns.first_mut() {
+            *fast = true;
+        }
+        for i in 1..stepping.len() {
+            if self.double_stepping {
+                // The pawl for rotor i drops into the notch of rotor i - 1 and
+                // pushes both along. For the middle rotor that means a second
+                // step straight after the one that brought it to its notch.
+                if at_notch[i - 1] {
+                    turns[i] = true;
+                    turns[i - 1] = true;
+                }
+            } else {
+                // Odometer: a rotor carries only as it turns past its notch
+                turns[i] = turns[i - 1] && at_notch[i - 1];
+            }
+        }
+        for (rotor, turn) in stepping.iter_mut().zip(turns) {
+            if turn {
+                rotor.rotate();
+            }
+        }
+    }
+
+    // Odometer stepping through the gear train, forward (1) or back (25). A
+    // wheel carries when it leaves a notch going forward, so going back it
+    // carries when it arrives at one.
+    fn gear_step(&mut self, delta: usize) {
+        let forward = delta == 1;
+        let mut carry = true;
+        for rotor in self.rotors.iter_mut().filter(|rotor| rotor.steps) {
+            if !carry {
+                return;
+            }
+            carry = forward && rotor.at_notch();
+            rotor.position = (rotor.position + delta) % 26;
+            carry |= !forward && rotor.at_notch();
+        }
+        if carry && self.rotatable_reflector {
+            self.reflector_position = (self.reflector_position + delta) % 26;
+        }
+    }
+
+    // Undoes one keystroke. Only the gear drive is reversible: pawls can't
+    // be pushed back, and a double step can't be told apart from two steps.
+    pub fn step_back(&mut self) -> Result<(), String> {
+        if !self.gear_stepping {
+            return Err("Only gear-driven (Zählwerk) stepping can be turned back.".to_string());
+        }
+        self.gear_step(25);
+        self.key_count = self.key_count.wrapping_sub(1);
+        Ok(())
+    }
+
+    // Steps the rotors n times, as n keystrokes would. Stepping is periodic,
+    // so once the period is known only the remainder of it is run. Brent's
+    // cycle detection finds the period along the way with a single saved
+    // state, so a jump shorter than the period is just n steps.
+    pub fn advance(&mut self, n: usize) {
+        let key_count = self.key_count.wrapping_add(n as u64);
+        let mut saved = Vec::with_capacity(self.rotors.len() + 1);
+        self.save_stepping_state(&mut saved);
+        let (mut power, mut period) = (1, 0);
+        let mut step = 0;
+
+        while step < n {
+            self.step();
+            step += 1;
+            period += 1;
+            if self.in_stepping_state(&saved) {
+                for _ in 0..(n - step) % period {
+                    self.step();
+                }
+                break;
+            }
+            if period == power {
+                self.save_stepping_state(&mut saved);
+                power *= 2;
+                period = 0;
+            }
+        }
+        self.key_count = key_count;
+    }
+
+    // Everything the next step depends on: the rotor and reflector positions
+    fn save_stepping_state(&self, state: &mut Vec<usize>) {
+        state.clear();
+        state.extend(self.rotors.iter().map(|rotor| rotor.position));
+        state.push(self.reflector_position);
+    }
+
+    fn in_stepping_state(&self, state: &[usize]) -> bool {
+        self.rotors.iter().map(|rotor| rotor.position).chain([self.reflector_position]).eq(state.iter().copied())
+    }
+
+    // Enciphers one uppercase letter. The keypress steps the rotors before the
+    // current flows, so stepping comes first.
+    pub fn encode_char(&mut self, input_char: char) -> char {
+        self.step();
+        self.encipher(input_char)
+    }
+
+    // The signal path for one letter at the current positions, without stepping
+    pub fn encipher(&self, input_char: char) -> char {
+        let mut encoded_char = self.enter(self.stecker(input_char)); // Plugboard swap before encoding
+
+        // Forward through the rotors
+        for rotor in &self.rotors {
+            encoded_char = rotor.encode_forward(encoded_char);
+        }
+
+        encoded_char = self.reflect(encoded_char);
+
+        // Through the rotors in reverse order
+        for rotor in self.rotors.iter().rev() {
+            encoded_char = rotor.encode_backward(encoded_char);
+        }
+
+        self.stecker(self.exit(encoded_char)) // Plugboard swap back after decoding
+    }
+
+    // Reflector, offset by its position on the way in and out
+    fn reflect(&self, input_char: char) -> char {
+        if self.bypass_reflector {
+            return input_char;
+        }
+        let index = (Alphabet::char_to_index(input_char) + self.reflector_position) % 26;
+        Alphabet::index_to_char(26 + Alphabet::char_to_index(self.reflector[index]) - self.reflector_position)
+    }
+
+    // Like encipher, but records the letter after every stage: the key, the
+    // plugboard, each rotor forward, the reflector, each rotor backward and
+    // finally the lamp, so 2 * rotors + 4 letters in all
+    pub fn trace(&self, input_char: char) -> Vec<char> {
+        // The entry wheel shares the plugboard's stage: with the usual
+        // straight wiring it changes nothing
+        let mut trace = vec![input_char, self.enter(self.stecker(input_char))];
+        for rotor in &self.rotors {
+            trace.push(rotor.encode_forward(*trace.last().unwrap()));
+        }
+        trace.push(self.reflect(*trace.last().unwrap()));
+        for rotor in self.rotors.iter().rev() {
+            trace.push(rotor.encode_backward(*trace.last().unwrap()));
+        }
+        trace.push(self.stecker(self.exit(*trace.last().unwrap())));
+        trace
+    }
+
+    // A keypress with its full signal path
+    pub fn trace_char(&mut self, input_char: char) -> Vec<char> {
+        self.step();
+        self.trace(input_char)
+    }
+
+    // What each key A-Z would light at the current positions, without stepping
+    pub fn current_mapping(&self) -> [char; 26] {
+        Alphabet::LETTERS.map(|c| self.encipher(c))
+    }
+
+    // The mapping with the windows at `positions` (0-25, machine order),
+    // worked out on a copy so this machine stays where it is
+    pub fn mapping_at(&self, positions: &[usize]) -> [char; 26] {
+        let mut preview = self.clone();
+        for (rotor, &position) in preview.rotors.iter_mut().zip(positions) {
+            rotor.position = position % 26;
+        }
+        preview.current_mapping()
+    }
+
+    // A correctly wired reflector has no fixed points, so no letter can ever
+    // encipher to itself; this checks that at the current positions
+    pub fn has_no_fixed_point(&self) -> bool {
+        self.current_mapping().iter().zip(Alphabet::LETTERS).all(|(&out, &c)| out != c)
+    }
+
+    pub fn encode_decode(&mut self, input: String, output: &mut String) {
+        output.clear();
+
+        let (letters, _) = sanitize(&input);
+        for input_char in letters.chars() {
+            let encoded_char = self.encode_char(input_char);
+            output.push(encoded_char);
+        }
+    }
+
+    // Letters are enciphered in upper case; everything else is handled as
+    // the policy says. Only RejectNonLetters can fail, and it fails before
+    // any rotor has stepped.
+    pub fn encode_with_policy(&mut self, input: &str, policy: FilterPolicy) -> Result<String, String> {
+        self.encode_with_casing(input, policy, Casing::UpperOnly)
+    }
+
+    // encode_with_policy, with the output letters cased as asked. MatchInput
+    // gives each output letter the case of the letter typed for it.
+    pub fn encode_with_casing(&mut self, input: &str, policy: FilterPolicy, casing: Casing) -> Result<String, String> {
+        check_policy(input, policy)?;
+        let mut output = String::new();
+        for c in input.chars() {
+            self.encode_with(c, policy, casing, &mut output);
+        }
+        Ok(output)
+    }
+
+    // One character of encode_with_casing, pushed onto `output`. The letters
+    // are the ones sanitize keeps, so ß enciphers as two; a character with
+    // none is kept or dropped as the policy says.
+    fn encode_with(&mut self, c: char, policy: FilterPolicy, casing: Casing, output: &mut String) {
+        let mut letters = cipher_letters(c).peekable();
+        if letters.peek().is_none() {
+            if policy.keeps(c) {
+                output.push(c);
+            }
+            return;
+        }
+        for letter in letters {
+            let encoded = self.encode_char(letter);
+            output.push(match casing {
+                Casing::UpperOnly => encoded,
+                Casing::LowerOnly => encoded.to_ascii_lowercase(),
+                Casing::MatchInput if c.is_lowercase() => encoded.to_ascii_lowercase(),
+                Casing::MatchInput => encoded,
+            });
+        }
+    }
+
+    // Same filtering as encode_decode, but reads and writes in chunks so a
+    // large file never has to fit in memory. Returns the number of letters
+    // written; input that is not valid UTF-8 is an error.
+    pub fn encode_stream<R: std::io::Read, W: std::io::Write>(&mut self, mut reader: R, mut writer: W) -> Result<usize, String> {
+        let mut buffer = [0u8; 8192];
+        // Bytes of a character split across two reads wait here for the rest
+        let mut pending: Vec<u8> = Vec::new();
+        let mut encoded = String::new();
+        let mut count = 0;
+
+        loop {
+            let read = reader.read(&mut buffer).map_err(|e| format!("Could not read input: {}", e))?;
+            if read == 0 {
+                break;
+            }
+            pending.extend_from_slice(&buffer[..read]);
+
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => return Err("Input is not valid UTF-8.".to_string()),
+            };
+
+            encoded.clear();
+            let text = std::str::from_utf8(&pending[..valid]).unwrap();
+            for input_char in sanitize(text).0.chars() {
+                encoded.push(self.encode_char(input_char));
+            }
+            writer.write_all(encoded.as_bytes()).map_err(|e| format!("Could not write output: {}", e))?;
+            count += encoded.len();
+            pending.drain(..valid);
+        }
+
+        if !pending.is_empty() {
+            return Err("Input is not valid UTF-8.".to_string());
+        }
+        writer.flush().map_err(|e| format!("Could not write output: {}", e))?;
+        Ok(count)
+    }
+}
+
+impl Default for EnigmaMachine {
+    // The demo configuration the GUI starts with
+    fn default() -> Self {
+        EnigmaMachine::new(
+            vec![
+                ("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q"),
+                ("AJDKSIRUXBLHWTMCQGZNPYFVOE", "E"),
+                ("BDFHJLCPRTXVZNYEIWGAKMUSQO", "V"),
+            ],
+            Reflector::by_id(ReflectorId::B).wiring.unwrap(),
+            &[
+                ('A', 'B'), ('C', 'D'), // Default plugboard configuration
+            ],
+        )
+        .expect("the demo plugboard is valid")
+    }
+}
+
+// What happens to spaces, digits and punctuation in a message. The machine
+// itself only has letter keys.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterPolicy {
+    DropNonLetters,
+    PassThroughNonLetters,
+    RejectNonLetters,
+    // Drop everything but line breaks, so paragraphs survive the round trip
+    KeepLineBreaks,
+    KeepLineBreaksAndSpaces,
+}
+
+impl FilterPolicy {
+    pub const ALL: [FilterPolicy; 5] = [
+        FilterPolicy::DropNonLetters,
+        FilterPolicy::PassThroughNonLetters,
+        FilterPolicy::RejectNonLetters,
+        FilterPolicy::KeepLineBreaks,
+        FilterPolicy::KeepLineBreaksAndSpaces,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterPolicy::DropNonLetters => "Drop",
+            FilterPolicy::PassThroughNonLetters => "Pass through",
+            FilterPolicy::RejectNonLetters => "Reject",
+            FilterPolicy::KeepLineBreaks => "Keep line breaks",
+            FilterPolicy::KeepLineBreaksAndSpaces => "Keep line breaks and spaces",
+        }
+    }
+
+    // Whether a non-letter is copied to the output unchanged. A Windows
+    // line break is "\r\n", so the '\r' goes with it.
+    pub fn keeps(&self, c: char) -> bool {
+        match self {
+            FilterPolicy::PassThroughNonLetters => true,
+            FilterPolicy::KeepLineBreaks => c == '\n' || c == '\r',
+            FilterPolicy::KeepLineBreaksAndSpaces => c == '\n' || c == '\r' || c == ' ',
+            FilterPolicy::DropNonLetters | FilterPolicy::RejectNonLetters => false,
+        }
+    }
+}
+
+// The letters the machine enciphers, in upper case, and every character left
+// out with its index in the input (counting characters, from 0). A letter
+// that upper-cases to several, like ß to SS, is kept as all of them.
+pub fn sanitize(input: &str) -> (String, Vec<(usize, char)>) {
+    let mut letters = String::new();
+    let mut dropped = Vec::new();
+    for (index, c) in input.chars().enumerate() {
+        let before = letters.len();
+        letters.extend(cipher_letters(c));
+        if letters.len() == before {
+            dropped.push((index, c));
+        }
+    }
+    (letters, dropped)
+}
+
+// The letters sanitize keeps for one character; none for a non-letter
+fn cipher_letters(c: char) -> impl Iterator<Item = char> {
+    c.to_uppercase().filter(|c| c.is_ascii_alphabetic())
+}
+
+// A one-line summary of what sanitize left out, positions counted from 1.
+// Long lists are cut short.
+pub fn describe_dropped(dropped: &[(usize, char)]) -> Option<String> {
+    const SHOWN: usize = 12;
+    if dropped.is_empty() {
+        return None;
+    }
+    let mut positions: Vec<String> = dropped.iter().take(SHOWN).map(|(index, _)| (index + 1).to_string()).collect();
+    if dropped.len() > SHOWN {
+        positions.push(format!("and {} more", dropped.len() - SHOWN));
+    }
+    let noun = if dropped.len() == 1 { "non-letter" } else { "non-letters" };
+    let position = if dropped.len() == 1 { "position" } else { "positions" };
+    Some(format!("Dropped {} {} at {} {}", dropped.len(), noun, position, positions.join(", ")))
+}
+
+// Only RejectNonLetters can fail, and it fails before any rotor has stepped
+fn check_policy(input: &str, policy: FilterPolicy) -> Result<(), String> {
+    match sanitize(input).1.first() {
+        Some((_, c)) if policy == FilterPolicy::RejectNonLetters => Err(format!("Invalid input: {:?} is not a letter.", c)),
+        _ => Ok(()),
+    }
+}
+
+// encode_with_casing done a piece at a time, so a long message can be spread
+// over several frames, or run on another thread and cancelled between pieces
+#[derive(Clone)]
+pub struct ChunkedEncode {
+    pub machine: EnigmaMachine,
+    input: Vec<char>,
+    done: usize,
+    pub output: String,
+    policy: FilterPolicy,
+    casing: Casing,
+}
+
+impl ChunkedEncode {
+    pub fn new(machine: EnigmaMachine, input: &str, policy: FilterPolicy, casing: Casing) -> Result<Self, String> {
+        check_policy(input, policy)?;
+        Ok(Self { machine, input: input.chars().collect(), done: 0, output: String::new(), policy, casing })
+    }
+
+    // Encodes up to `chars` more characters; true once the whole input is done
+    pub fn run(&mut self, chars: usize) -> bool {
+        let end = self.input.len().min(self.done.saturating_add(chars));
+        for &c in &self.input[self.done..end] {
+            self.machine.encode_with(c, self.policy, self.casing, &mut self.output);
+        }
+        self.done = end;
+        self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done == self.input.len()
+    }
+
+    // Characters of input handled so far, out of `len`
+    pub fn done(&self) -> usize {
+        self.done
+    }
+
+    pub fn len(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+}
+
+// How output letters are written. The machine itself only knows upper case.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Casing {
+    UpperOnly,
+    LowerOnly,
+    MatchInput,
+}
+
+impl Casing {
+    pub const ALL: [Casing; 3] = [Casing::UpperOnly, Casing::LowerOnly, Casing::MatchInput];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Casing::UpperOnly => "Upper case",
+            Casing::LowerOnly => "Lower case",
+            Casing::MatchInput => "Match input",
+        }
+    }
+}
+
+// Which way rotor slots are listed. The machine keeps its rotors entry side
+// first (rotors[0] is the fast right-hand rotor); key sheets and many
+// simulators print them left to right, reflector side first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RotorOrder {
+    EntryFirst,
+    ReflectorFirst,
+}
+
+impl RotorOrder {
+    pub const ALL: [RotorOrder; 2] = [RotorOrder::EntryFirst, RotorOrder::ReflectorFirst];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RotorOrder::EntryFirst => "Right to left (entry side first)",
+            RotorOrder::ReflectorFirst => "Left to right (reflector side first)",
+        }
+    }
+
+    // Machine indices of `count` rotors in the order they are listed
+    pub fn indices(&self, count: usize) -> Vec<usize> {
+        match self {
+            RotorOrder::EntryFirst => (0..count).collect(),
+            RotorOrder::ReflectorFirst => (0..count).rev().collect(),
+        }
+    }
+
+    // Converts listed settings to machine order or back; the mapping is its
+    // own inverse
+    pub fn arrange<T: Clone>(&self, items: &[T]) -> Vec<T> {
+        self.indices(items.len()).into_iter().map(|i| items[i].clone()).collect()
+    }
+}
+
+// Word breaks as operators wrote them. The machine has no space key, so a
+// space went in as a filler letter, most often X. Decoding can put the spaces
+// back, though a filler that was a real letter comes back as a space too.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpaceConvention {
+    None,
+    X,
+    J,
+}
+
+impl SpaceConvention {
+    pub const ALL: [SpaceConvention; 3] = [SpaceConvention::None, SpaceConvention::X, SpaceConvention::J];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpaceConvention::None => "Leave spaces to the policy",
+            SpaceConvention::X => "Space as X",
+            SpaceConvention::J => "Space as J",
+        }
+    }
+
+    pub fn filler(&self) -> Option<char> {
+        match self {
+            SpaceConvention::None => None,
+            SpaceConvention::X => Some('X'),
+            SpaceConvention::J => Some('J'),
+        }
+    }
+
+    // Each run of spaces becomes one filler letter; line breaks and other
+    // characters are left for the filter policy
+    pub fn apply(&self, text: &str) -> String {
+        let Some(filler) = self.filler() else {
+            return text.to_string();
+        };
+        let mut converted = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == ' ' {
+                while chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+                converted.push(filler);
+            } else {
+                converted.push(c);
+            }
+        }
+        converted
+    }
+
+    pub fn restore(&self, letters: &str) -> String {
+        match self.filler() {
+            Some(filler) => letters.chars().map(|c| if c.eq_ignore_ascii_case(&filler) { ' ' } else { c }).collect(),
+            None => letters.to_string(),
+        }
+    }
+}
+
+// Figure shift: the machine only has letters, so digits, spaces and a few
+// symbols travel as letters after a shift letter, the way teleprinter codes
+// switch between letters and figures. The shift letter toggles the mode; a
+// literal shift letter is sent doubled.
+pub const FIGURE_SHIFT: char = 'Y';
+
+// (figure, letter) with the digits on the top keyboard row, Q = 1 to P = 0
+pub const FIGURES: &[(char, char)] = &[
+    ('1', 'Q'), ('2', 'W'), ('3', 'E'), ('4', 'R'), ('5', 'T'),
+    ('6', 'Z'), ('7', 'U'), ('8', 'I'), ('9', 'O'), ('0', 'P'),
+    (' ', 'X'), ('.', 'A'), (',', 'S'), ('?', 'D'), ('-', 'F'),
+    ('/', 'G'), ('(', 'H'), (')', 'J'), (':', 'K'),
+];
+
+// Plaintext to the letters the machine enciphers. Lower case is sent as
+// upper case; characters without a figure are an error.
+pub fn figure_shift_encode(text: &str) -> Result<String, String> {
+    let mut letters = String::new();
+    let mut figures = false;
+
+    for c in text.chars().map(|c| c.to_ascii_uppercase()) {
+        if c.is_ascii_uppercase() {
+            if figures {
+                letters.push(FIGURE_SHIFT);
+                figures = false;
+            }
+            letters.push(c);
+            if c == FIGURE_SHIFT {
+                letters.push(FIGURE_SHIFT);
+            }
+        } else {
+            let &(_, letter) = FIGURES.iter()
+                .find(|&&(figure, _)| figure == c)
+                .ok_or_else(|| format!("Invalid input: {:?} has no figure-shift code.", c))?;
+            if !figures {
+                letters.push(FIGURE_SHIFT);
+                figures = true;
+            }
+            letters.push(letter);
+        }
+    }
+
+    Ok(letters)
+}
+
+// Reverses figure_shift_encode. Letters with no figure are kept as letters.
+pub fn figure_shift_decode(letters: &str) -> String {
+    let mut text = String::new();
+    let mut figures = false;
+    let mut chars = letters.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == FIGURE_SHIFT {
+            if !figures && chars.peek() == Some(&FIGURE_SHIFT) {
+                chars.next();
+                text.push(FIGURE_SHIFT);
+            } else {
+                figures = !figures;
+            }
+        } else if figures {
+            text.push(FIGURES.iter().find(|&&(_, letter)| letter == c).map_or(c, |&(figure, _)| figure));
+        } else {
+            text.push(c);
+        }
+    }
+
+    text
+}
+
+// Ciphertext as it is usually copied down: letter groups split by spaces or
+// line breaks. Returns the letters run together, and the group size if every
+// group but a shorter last one has the same length. Continuous text has no
+// group size.
+pub fn normalize_ciphertext(text: &str) -> (String, Option<usize>) {
+    let groups: Vec<&str> = text.split_whitespace().collect();
+    let group_size = match groups.split_last() {
+        Some((last, rest)) if !rest.is_empty() => {
+            let size = rest[0].chars().count();
+            (rest.iter().all(|group| group.chars().count() == size) && last.chars().count() <= size).then_some(size)
+        }
+        _ => None,
+    };
+    (groups.concat(), group_size)
+}
+
+// The machine families the simulator can switch between
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Variant {
+    EnigmaI,
+    EnigmaK,
+    Typex,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::EnigmaI, Variant::EnigmaK, Variant::Typex];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::EnigmaI => "Enigma I",
+            Variant::EnigmaK => "Enigma K (commercial)",
+            Variant::Typex => "Typex (experimental)",
+        }
+    }
+
+    pub fn build(&self) -> EnigmaMachine {
+        match self {
+            Variant::EnigmaI => EnigmaMachine::default(),
+            Variant::EnigmaK => EnigmaMachine::enigma_k(),
+            Variant::Typex => EnigmaMachine::typex(),
+        }
+    }
+
+    // Whether the reflector could be turned to any of 26 positions
+    pub fn rotatable_reflector(&self) -> bool {
+        matches!(self, Variant::EnigmaK)
+    }
+}
+
+impl EnigmaMachine {
+    // Commercial Enigma K rotors I, II and III, listed from the entry side
+    // as III, II, I, with the reflector that could be set in any position
+    const ENIGMA_K_ROTORS: [(&'static str, &'static str); 3] = [
+        ("CJGDPSHKTURAWZXFMYNQOBVLIE", "N"),
+        ("SLVGBTFXJQOHEWIRZYAMKPCNDU", "E"),
+        ("LPGSZMHAEOQKVXRFYBUTNICJDW", "Y"),
+    ];
+    const ENIGMA_K_REFLECTOR: &'static str = "IMETCGFRAYSQBZXWLHKDVUPOJN";
+
+    // The commercial machine: keyboard-order entry wheel, a rotatable
+    // reflector and no plugboard cables
+    pub fn enigma_k() -> Self {
+        let mut machine = EnigmaMachine::new(Self::ENIGMA_K_ROTORS.to_vec(), Self::ENIGMA_K_REFLECTOR, &[])
+            .expect("an empty plugboard is valid");
+        machine.entry_wheel = Self::QWERTZ_ENTRY_WHEEL;
+        machine.rotatable_reflector = true;
+        machine
+    }
+
+    // Listed from the entry side: stators A and B, then the fast, middle and
+    // slow rotors. Typex service wirings were never published, so these are an
+    // illustrative set with five notches per rotor like the originals.
+    const TYPEX_ROTORS: [(&'static str, &'static str); 5] = [
+        ("FKTYWCQXGZAOIVJHPLBDMRNUES", "CLOUY"),
+        ("XSGFZYWIUVQKBPTDLNOHAJCREM", "AIKMO"),
+        ("GJZTSIFDEWRBYLUMAXVPKHOQNC", "DINRX"),
+        ("ISBGFQJATNVMCEODYXLKUZHRWP", "BEJOY"),
+        ("NVCQMEBAGOIZLFWXRPHUDYSTJK", "GIKNS"),
+    ];
+    const TYPEX_REFLECTOR: &'static str = "FRZGPADWQOMYKUJEIBVXNSHTLC";
+
+    // Five rotor Typex with the two entry rotors held still. The three
+    // moving rotors step like an odometer: each carries into the next when
+    // it turns past any of its notches, without the Enigma's double step of
+    // the middle rotor.
+    pub fn typex() -> Self {
+        let mut machine = EnigmaMachine::new(Self::TYPEX_ROTORS.to_vec(), Self::TYPEX_REFLECTOR, &[])
+            .expect("an empty plugboard is valid");
+        machine.rotors[0].steps = false;
+        machine.rotors[1].steps = false;
+        machine.double_stepping = false;
+        machine
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReflectorId {
+    A,
+    B,
+    C,
+    BThin,
+    CThin,
+    D,
+}
+
+// A reflector (Umkehrwalze) the simulator knows about. UKW-D was rewired in
+// the field, so it has no fixed wiring and is set up by hand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reflector {
+    pub id: ReflectorId,
+    pub name: &'static str,
+    pub wiring: Option<&'static str>,
+}
+
+impl Reflector {
+    const CATALOG: &'static [Reflector] = &[
+        Reflector { id: ReflectorId::A, name: "A", wiring: Some("EJMZALYXVBWFCRQUONTSPIKHGD") },
+        Reflector { id: ReflectorId::B, name: "B", wiring: Some("YRUHQSLDPXNGOKMIEBFZCWVJAT") },
+        Reflector { id: ReflectorId::C, name: "C", wiring: Some("FVPJIAOYEDRZXWGCTKUQSBNMHL") },
+        Reflector { id: ReflectorId::BThin, name: "B-thin", wiring: Some("ENKQAUYWJICOPBLMDXZVFTHRGS") },
+        Reflector { id: ReflectorId::CThin, name: "C-thin", wiring: Some("RDOBJNTKVEHMLFCWZAXGYIPSUQ") },
+        Reflector { id: ReflectorId::D, name: "D", wiring: None },
+    ];
+
+    pub fn all() -> &'static [ReflectorId] {
+        &[ReflectorId::A, ReflectorId::B, ReflectorId::C, ReflectorId::BThin, ReflectorId::CThin, ReflectorId::D]
+    }
+
+    pub fn by_id(id: ReflectorId) -> Reflector {
+        *Reflector::CATALOG.iter().find(|reflector| reflector.id == id).unwrap()
+    }
+
+    // Case-insensitive, as reflectors are named on key sheets
+    pub fn by_name(name: &str) -> Option<Reflector> {
+        Reflector::CATALOG.iter().copied().find(|reflector| reflector.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn user_defined(&self) -> bool {
+        self.wiring.is_none()
+    }
+}
+
+// What is wrong with a reflector wiring, with a nearby valid one to offer
+// instead. A reflector must be an involution without fixed points: every
+// letter paired with exactly one other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectorDiagnosis {
+    // Letters wired to themselves
+    pub fixed_points: Vec<char>,
+    // (a, b, c): a maps to b but b maps back to c instead of a
+    pub asymmetric: Vec<(char, char, char)>,
+    // Keeps every correctly paired letter and re-pairs the rest
+    pub suggestion: [char; 26],
+}
+
+impl ReflectorDiagnosis {
+    pub fn is_valid(&self) -> bool {
+        self.fixed_points.is_empty() && self.asymmetric.is_empty()
+    }
+
+    // One readable line per problem, e.g. "C maps to D but D maps to E."
+    pub fn problems(&self) -> Vec<String> {
+        let fixed = self.fixed_points.iter().map(|c| format!("{} maps to itself.", c));
+        let asymmetric = self.asymmetric.iter().map(|(a, b, c)| format!("{} maps to {} but {} maps to {}.", a, b, b, c));
+        fixed.chain(asymmetric).collect()
+    }
+}
+
+pub fn diagnose_reflector(wiring: &[char; 26]) -> ReflectorDiagnosis {
+    let target = |c: char| wiring[Alphabet::char_to_index(c)];
+
+    let fixed_points = Alphabet::LETTERS.iter().copied().filter(|&c| target(c) == c).collect();
+    let asymmetric = Alphabet::LETTERS.iter().copied()
+        .filter(|&a| target(a) != a && target(target(a)) != a)
+        .map(|a| (a, target(a), target(target(a))))
+        .collect();
+
+    // Keep the pairs that are already mutual, then pair each remaining letter
+    // with its own target while that is still free, then the leftovers in order
+    let mut suggestion = [None; 26];
+    for &a in Alphabet::LETTERS {
+        let b = target(a);
+        if b != a && target(b) == a {
+            suggestion[Alphabet::char_to_index(a)] = Some(b);
+        }
+    }
+    for &a in Alphabet::LETTERS {
+        let b = target(a);
+        let (i, j) = (Alphabet::char_to_index(a), Alphabet::char_to_index(b));
+        if suggestion[i].is_none() && suggestion[j].is_none() && a != b {
+            suggestion[i] = Some(b);
+            suggestion[j] = Some(a);
+        }
+    }
+    let leftovers: Vec<char> = Alphabet::LETTERS.iter().copied()
+        .filter(|&c| suggestion[Alphabet::char_to_index(c)].is_none())
+        .collect();
+    for pair in leftovers.chunks(2) {
+        suggestion[Alphabet::char_to_index(pair[0])] = Some(pair[1]);
+        suggestion[Alphabet::char_to_index(pair[1])] = Some(pair[0]);
+    }
+
+    ReflectorDiagnosis {
+        fixed_points,
+        asymmetric,
+        suggestion: suggestion.map(|c| c.unwrap()),
+    }
+}
+
+// Whether `wiring` holds each of the letters A-Z exactly once
+fn is_alphabet_permutation(wiring: &str) -> bool {
+    let mut letters: Vec<char> = wiring.chars().collect();
+    letters.sort_unstable();
+    letters == Alphabet::LETTERS
+}
+
+// Reads a reflector wiring written as the 26 letters A maps to, B maps to, ...
+pub fn parse_reflector_wiring(input: &str) -> Result<[char; 26], String> {
+    let letters: Vec<char> = input.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+    if let Some(c) = letters.iter().find(|c| !c.is_ascii_alphabetic()) {
+        return Err(format!("Invalid reflector wiring: '{}' is not a letter.", c));
+    }
+    letters.try_into()
+        .map_err(|letters: Vec<char>| format!("Invalid reflector wiring: expected 26 letters, got {}.", letters.len()))
+}
+
+// Reflector wirings loaded ahead of time so the active one can be switched
+// without retyping, as operators did with a set of rewired UKW-D plugs.
+// Every entry is checked when it is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct ReflectorBank {
+    entries: Vec<(String, [char; 26])>,
+    active: Option<usize>,
+}
+
+impl ReflectorBank {
+    // Loading under an existing name replaces that entry. Returns its index.
+    pub fn load(&mut self, name: &str, wiring: &str) -> Result<usize, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Give the reflector a name.".to_string());
+        }
+        let wiring = parse_reflector_wiring(wiring)?;
+        let diagnosis = diagnose_reflector(&wiring);
+        if !diagnosis.is_valid() {
+            return Err(format!("Invalid reflector wiring: {}", diagnosis.problems().join(" ")));
+        }
+        match self.entries.iter().position(|(existing, _)| existing == name) {
+            Some(index) => {
+                self.entries[index].1 = wiring;
+                Ok(index)
+            }
+            None => {
+                self.entries.push((name.to_string(), wiring));
+                Ok(self.entries.len() - 1)
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &[(String, [char; 26])] {
+        &self.entries
+    }
+
+    pub fn active(&self) -> Option<usize> {
+        self.active
+    }
+
+    // Puts entry `index` into the machine
+    pub fn activate(&mut self, index: usize, machine: &mut EnigmaMachine) -> Result<(), String> {
+        let (name, wiring) = self.entries.get(index).ok_or_else(|| format!("No reflector {} in the bank.", index + 1))?;
+        machine.set_reflector(*wiring).map_err(|e| format!("{}: {}", name, e))?;
+        self.active = Some(index);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.entries.len() {
+            self.entries.remove(index);
+            self.active = match self.active {
+                Some(active) if active == index => None,
+                Some(active) if active > index => Some(active - 1),
+                active => active,
+            };
+        }
+    }
+}
+
+// A complete machine setting as printed on a key sheet. Rotors, rings and
+// positions are listed left to right, the way operators read them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnigmaKey {
+    pub reflector: String,
+    pub rotors: Vec<String>,
+    pub ring_settings: Vec<char>,
+    pub positions: Vec<char>,
+    pub plugboard: Vec<(char, char)>,
+}
+
+// Writes the key as a key sheet row that parse_key_sheet_row reads back
+impl std::fmt::Display for EnigmaKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let numbers = |letters: &[char]| -> String {
+            letters.iter().map(|&c| format!("{:02}", Alphabet::char_to_number(c))).collect::<Vec<_>>().join(" ")
+        };
+        let plugs: Vec<String> = self.plugboard.iter().map(|(a, b)| format!("{}{}", a, b)).collect();
+        write!(
+            f,
+            "Walzenlage {} Ringstellung {} Umkehrwalze {} Grundstellung {} Steckerverbindungen {}",
+            self.rotors.join(" "),
+            numbers(&self.ring_settings),
+            self.reflector,
+            numbers(&self.positions),
+            plugs.join(" "),
+        )
+    }
+}
+
+// The daily part of a key, written the same way however the key was typed:
+// catalog spellings, letters for rings, and each cable low letter first in
+// alphabetical order. Positions are left out, as they change with every
+// message and every keypress.
+#[derive(serde::Serialize)]
+struct CanonicalKey {
+    reflector: String,
+    rotors: Vec<String>,
+    ring_settings: String,
+    plugboard: Vec<(char, char)>,
+}
+
+impl EnigmaKey {
+    fn canonical(&self) -> CanonicalKey {
+        let mut plugboard: Vec<(char, char)> = self.plugboard.iter()
+            .map(|&(a, b)| (a.to_ascii_uppercase(), b.to_ascii_uppercase()))
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        plugboard.sort();
+        CanonicalKey {
+            reflector: Reflector::by_name(&self.reflector).map_or_else(|| self.reflector.clone(), |reflector| reflector.name.to_string()),
+            rotors: self.rotors.iter()
+                .map(|name| Rotor::catalog_entry(name).map_or_else(|| name.clone(), |(name, _, _)| name.to_string()))
+                .collect(),
+            ring_settings: self.ring_settings.iter().map(char::to_ascii_uppercase).collect(),
+            plugboard,
+        }
+    }
+
+    // A short hash of the key, so two operators can check they hold the same
+    // setting by reading out eight characters instead of the whole row.
+    // Not a secret: with few enough candidate keys it can be searched.
+    pub fn fingerprint(&self) -> String {
+        use sha1::Digest;
+        let canonical = ron::to_string(&self.canonical()).expect("a key always serializes");
+        sha1::Sha1::digest(canonical.as_bytes())[..4].iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // The key packed small enough for a QR code: a version byte, the
+    // reflector and rotor count in one byte, catalog index, ring and position
+    // for each rotor left to right, then the number of cables and each cable
+    // as two 5-bit letters. A three-rotor key with ten cables takes 25 bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let reflector = Reflector::CATALOG.iter()
+            .position(|reflector| reflector.name.eq_ignore_ascii_case(&self.reflector))
+            .ok_or_else(|| format!("Unknown reflector '{}'.", self.reflector))?;
+        if self.ring_settings.len() != self.rotors.len() || self.positions.len() != self.rotors.len() {
+            return Err(format!("Expected {} ring settings and positions.", self.rotors.len()));
+        }
+        if self.rotors.len() > 15 || self.plugboard.len() > 13 {
+            return Err("Too many rotors or plugs to pack.".to_string());
+        }
+        let letter = |c: char| {
+            c.is_ascii_alphabetic().then(|| Alphabet::char_to_index(c.to_ascii_uppercase()) as u8)
+                .ok_or_else(|| format!("'{}' is not a letter.", c))
+        };
+
+        let mut bytes = vec![KEY_FORMAT_VERSION, (reflector as u8) << 4 | self.rotors.len() as u8];
+        for ((name, &ring), &position) in self.rotors.iter().zip(&self.ring_settings).zip(&self.positions) {
+            let rotor = Rotor::CATALOG.iter()
+                .position(|(entry, _, _)| entry.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("Unknown rotor '{}'.", name))?;
+            bytes.extend([rotor as u8, letter(ring)?, letter(position)?]);
+        }
+
+        bytes.push(self.plugboard.len() as u8);
+        let mut bits = BitWriter::default();
+        for &(a, b) in &self.plugboard {
+            bits.push(letter(a)?);
+            bits.push(letter(b)?);
+        }
+        bytes.extend(bits.bytes);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let truncated = || "Key data is cut short.".to_string();
+        let letter = |index: u8| {
+            (index < 26).then(|| Alphabet::index_to_char(index as usize))
+                .ok_or_else(|| format!("{} is not a letter index.", index))
+        };
+
+        let (&version, rest) = bytes.split_first().ok_or_else(truncated)?;
+        if version != KEY_FORMAT_VERSION {
+            return Err(format!("Unknown key format version {}.", version));
+        }
+        let (&header, mut rest) = rest.split_first().ok_or_else(truncated)?;
+        let reflector = Reflector::CATALOG.get((header >> 4) as usize)
+            .ok_or_else(|| format!("Unknown reflector index {}.", header >> 4))?;
+
+        let mut key = EnigmaKey {
+            reflector: reflector.name.to_string(),
+            rotors: Vec::new(),
+            ring_settings: Vec::new(),
+            positions: Vec::new(),
+            plugboard: Vec::new(),
+        };
+        for _ in 0..header & 0x0f {
+            let [rotor, ring, position] = *rest.first_chunk::<3>().ok_or_else(truncated)?;
+            let (name, _, _) = Rotor::CATALOG.get(rotor as usize)
+                .ok_or_else(|| format!("Unknown rotor index {}.", rotor))?;
+            key.rotors.push(name.to_string());
+            key.ring_settings.push(letter(ring)?);
+            key.positions.push(letter(position)?);
+            rest = &rest[3..];
+        }
+
+        let (&cables, rest) = rest.split_first().ok_or_else(truncated)?;
+        let mut bits = BitReader { bytes: rest, bit: 0 };
+        for _ in 0..cables {
+            let a = bits.next().ok_or_else(truncated)?;
+            let b = bits.next().ok_or_else(truncated)?;
+            key.plugboard.push((letter(a)?, letter(b)?));
+        }
+        if rest.len() != bits.bit.div_ceil(8) {
+            return Err("Unexpected bytes after the key.".to_string());
+        }
+        Ok(key)
+    }
+
+    pub fn build(&self) -> Result<EnigmaMachine, String> {
+        let reflector = Reflector::by_name(&self.reflector)
+            .ok_or_else(|| format!("Unknown reflector '{}'.", self.reflector))?
+            .wiring
+            .ok_or_else(|| format!("Reflector {} is wired by hand; set its wiring as a custom reflector.", self.reflector))?;
+
+        // The machine lists its rotors from the entry side, i.e. right to left
+        let mut rotor_configurations = Vec::new();
+        for name in self.rotors.iter().rev() {
+            let (_, wiring, notches) = Rotor::catalog_entry(name)
+                .ok_or_else(|| format!("Unknown rotor '{}'.", name))?;
+            rotor_configurations.push((wiring, notches));
+        }
+
+        if self.ring_settings.len() != self.rotors.len() || self.positions.len() != self.rotors.len() {
+            return Err(format!("Expected {} ring settings and positions.", self.rotors.len()));
+        }
+        if let Some(c) = self.ring_settings.iter().chain(&self.positions).find(|c| !c.is_ascii_uppercase()) {
+            return Err(format!("Invalid setting '{}': Expected a letter A-Z.", c));
+        }
+
+        let mut machine = EnigmaMachine::new(rotor_configurations, reflector, &self.plugboard)?;
+        // On an M4 the leftmost (Greek) rotor is fixed in place
+        if let [_, _, _, greek] = machine.rotors.as_mut_slice() {
+            greek.steps = false;
+        }
+        let settings = self.ring_settings.iter().rev().zip(self.positions.iter().rev());
+        for (rotor, (&ring, &position)) in machine.rotors.iter_mut().zip(settings) {
+            rotor.set_ring_setting(ring);
+            rotor.set_position(position);
+        }
+
+        Ok(machine)
+    }
+}
+
+const KEY_FORMAT_VERSION: u8 = 1;
+
+// Letters packed five bits each, high bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u8) {
+        for shift in (0..5).rev() {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= ((value >> shift) & 1) << (7 - self.bit % 8);
+            self.bit += 1;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl BitReader<'_> {
+    fn next(&mut self) -> Option<u8> {
+        let mut value = 0;
+        for _ in 0..5 {
+            let byte = self.bytes.get(self.bit / 8)?;
+            value = value << 1 | (byte >> (7 - self.bit % 8)) & 1;
+            self.bit += 1;
+        }
+        Some(value)
+    }
+}
+
+// A setup the simulator can encode with but no operator could have used
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    // Each rotor existed once per machine
+    DuplicateRotor(String),
+    // (pairs, cables) when more plugs are set than cables were issued
+    TooManyPlugs(usize, usize),
+    // Beta and Gamma only fit the leftmost slot of a four-rotor M4
+    GreekRotorMisplaced(String),
+    // Only the M4 took a thin reflector, and it took nothing else
+    ReflectorMismatch(String),
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Warning::DuplicateRotor(name) => write!(f, "Rotor {} is used in more than one slot.", name),
+            Warning::TooManyPlugs(pairs, cables) => write!(f, "{} plug pairs set, but only {} cables were issued.", pairs, cables),
+            Warning::GreekRotorMisplaced(name) => write!(f, "Rotor {} only fits the leftmost slot of a four-rotor M4.", name),
+            Warning::ReflectorMismatch(name) => write!(f, "Reflector {} does not fit a machine with this many rotors.", name),
+        }
+    }
+}
+
+// Plug cables issued with the Enigma I from 1939
+pub const PLUG_CABLES: usize = 10;
+
+// Below this many cables most letters pass the board unchanged, far weaker
+// than any key used in service
+pub const WEAK_PLUGBOARD_PAIRS: usize = 6;
+
+// Cables plugged in; each one adds to the plugboard's share of the key space
+pub fn plugboard_strength(plugboard: &Plugboard) -> usize {
+    plugboard.pairs().len()
+}
+
+// Flags keys that encode fine but were physically or historically impossible
+pub fn validate_physical(key: &EnigmaKey) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    for (i, name) in key.rotors.iter().enumerate() {
+        if key.rotors[..i].iter().any(|earlier| earlier.eq_ignore_ascii_case(name))
+            && !warnings.contains(&Warning::DuplicateRotor(name.clone()))
+        {
+            warnings.push(Warning::DuplicateRotor(name.clone()));
+        }
+    }
+
+    if key.plugboard.len() > PLUG_CABLES {
+        warnings.push(Warning::TooManyPlugs(key.plugboard.len(), PLUG_CABLES));
+    }
+
+    let four_rotors = key.rotors.len() == 4;
+    for (i, name) in key.rotors.iter().enumerate() {
+        let greek = name.eq_ignore_ascii_case("Beta") || name.eq_ignore_ascii_case("Gamma");
+        if greek && !(four_rotors && i == 0) {
+            warnings.push(Warning::GreekRotorMisplaced(name.clone()));
+        }
+    }
+
+    let thin = key.reflector.to_ascii_lowercase().ends_with("-thin");
+    if thin != four_rotors {
+        warnings.push(Warning::ReflectorMismatch(key.reflector.clone()));
+    }
+
+    warnings
+}
+
+// (name, key sheet row) of ready-made settings for the preset gallery
+pub const PRESETS: &[(&str, &str)] = &[
+    (
+        "Default demo",
+        "Walzenlage III II I Ringstellung 01 01 01 Umkehrwalze B Steckerverbindungen AB CD",
+    ),
+    (
+        "Enigma Instruction Manual 1930",
+        "Walzenlage II I III Ringstellung 24 13 22 Grundstellung A B L Umkehrwalze A \
+         Steckerverbindungen AM FI NV PS TU WZ",
+    ),
+    (
+        "U-264 M4 example",
+        "Walzenlage Beta II IV I Ringstellung 01 01 01 22 Grundstellung V J N A Umkehrwalze B-thin \
+         Steckerverbindungen AT BL DF GJ HM NW OP QY RZ VX",
+    ),
+];
+
+// Key sheet settings are given either as letters or as numbers 01-26
+pub fn parse_setting_letter(token: &str) -> Result<char, String> {
+    if let Ok(number) = token.parse::<usize>() {
+        if let Some(letter) = Alphabet::number_to_char(number) {
+            return Ok(letter);
+        }
+    } else if token.len() == 1 && token.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok(token.chars().next().unwrap().to_ascii_uppercase());
+    }
+    Err(format!("Invalid setting '{}': Expected a letter A-Z or a number 01-26.", token))
+}
+
+// Parses a key sheet row such as
+// "Tag 31  Walzenlage II IV V  Ringstellung 06 22 14  Steckerverbindungen AD CN ET"
+// Umkehrwalze defaults to B and Grundstellung to all A when not given.
+pub fn parse_key_sheet_row(row: &str) -> Result<EnigmaKey, String> {
+    const LABELS: [&str; 6] = ["TAG", "WALZENLAGE", "RINGSTELLUNG", "STECKERVERBINDUNGEN", "UMKEHRWALZE", "GRUNDSTELLUNG"];
+
+    let row = row.to_uppercase();
+    let mut fields: std::collections::HashMap<&str, Vec<&str>> = std::collections::HashMap::new();
+    let mut current = None;
+
+    for token in row.split_whitespace() {
+        if let Some(label) = LABELS.iter().find(|&&label| label == token) {
+            if fields.insert(label, Vec::new()).is_some() {
+                return Err(format!("Invalid key sheet row: '{}' appears more than once.", token));
+            }
+            current = Some(*label);
+        } else if let Some(label) = current {
+            fields.get_mut(label).unwrap().push(token);
+        } else {
+            return Err(format!("Invalid key sheet row: unexpected '{}' before the first field label.", token));
+        }
+    }
+
+    let required = |label: &str| match fields.get(label) {
+        Some(values) if !values.is_empty() => Ok(values),
+        _ => Err(format!("Invalid key sheet row: missing {}.", label[..1].to_string() + &label[1..].to_lowercase())),
+    };
+
+    let rotors = required("WALZENLAGE")?.iter()
+        .map(|name| Rotor::catalog_entry(name)
+            .map(|(name, _, _)| name.to_string())
+            .ok_or_else(|| format!("Unknown rotor '{}'.", name)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let ring_settings = required("RINGSTELLUNG")?.iter()
+        .map(|token| parse_setting_letter(token))
+        .collect::<Result<Vec<_>, _>>()?;
+    if ring_settings.len() != rotors.len() {
+        return Err(format!("Invalid key sheet row: {} rotors but {} ring settings.", rotors.len(), ring_settings.len()));
+    }
+
+    // The label is required, but an unplugged board leaves it empty
+    let plugboard = match fields.get("STECKERVERBINDUNGEN") {
+        Some(values) => Plugboard::parse_pairs(&values.join(" "))?,
+        None => return Err("Invalid key sheet row: missing Steckerverbindungen.".to_string()),
+    };
+
+    let reflector = match fields.get("UMKEHRWALZE").map(|values| values.as_slice()) {
+        None => "B".to_string(),
+        Some([name]) => Reflector::by_name(name)
+            .map(|reflector| reflector.name.to_string())
+            .ok_or_else(|| format!("Unknown reflector '{}'.", name))?,
+        Some(_) => return Err("Invalid key sheet row: Umkehrwalze takes exactly one reflector.".to_string()),
+    };
+
+    let positions = match fields.get("GRUNDSTELLUNG") {
+        None => vec!['A'; rotors.len()],
+        Some(values) => values.iter()
+            .map(|token| parse_setting_letter(token))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    if positions.len() != rotors.len() {
+        return Err(format!("Invalid key sheet row: {} rotors but {} positions.", rotors.len(), positions.len()));
+    }
+
+    Ok(EnigmaKey { reflector, rotors, ring_settings, positions, plugboard })
+}
+
+// Formats the connections the way they are typed in, e.g. "AB CD"
+impl std::fmt::Display for Plugboard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let pairs: Vec<String> = self.pairs().iter().map(|(a, b)| format!("{}{}", a, b)).collect();
+        write!(f, "{}", pairs.join(" "))
+    }
+}
+
+// Small seedable generator (SplitMix64) so random settings can be reproduced
+// from a seed in tests and demos
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in 0..bound; the modulo bias is negligible for bounds this small
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_machine() -> EnigmaMachine {
+        EnigmaMachine::new(
+            vec![
+                ("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q"),
+                ("AJDKSIRUXBLHWTMCQGZNPYFVOE", "E"),
+                ("BDFHJLCPRTXVZNYEIWGAKMUSQO", "V"),
+            ],
+            "YRUHQSLDPXNGOKMIEBFZCWVJAT",
+            &[],
+        )
+        .unwrap()
+    }
+
+    fn positions(machine: &EnigmaMachine) -> String {
+        machine.rotors.iter().map(|r| Alphabet::index_to_char(r.position)).collect()
+    }
+
+    fn step_sequence(double_stepping: bool) -> Vec<String> {
+        let mut machine = default_machine();
+        machine.double_stepping = double_stepping;
+        for (rotor, pos) in machine.rotors.iter_mut().zip(['Q', 'D', 'A']) {
+            rotor.set_position(pos);
+        }
+
+        (0..2).map(|_| {
+            machine.step();
+            positions(&machine)
+        }).collect()
+    }
+
+    #[test]
+    fn only_enigma_k_turns_its_reflector() {
+        let machine = Variant::EnigmaK.build();
+        assert!(machine.rotatable_reflector);
+        assert_eq!(machine.entry_wheel, EnigmaMachine::QWERTZ_ENTRY_WHEEL);
+        assert!(Variant::EnigmaK.rotatable_reflector());
+        assert!(!Variant::EnigmaI.rotatable_reflector());
+        assert!(!Variant::Typex.rotatable_reflector());
+    }
+
+    #[test]
+    fn reflector_position_changes_output_and_round_trips() {
+        let plaintext = "ATTACKATDAWN";
+        let mut baseline = String::new();
+        default_machine().encode_decode(plaintext.to_string(), &mut baseline);
+
+        let rotated = || {
+            let mut machine = default_machine();
+            machine.rotatable_reflector = true;
+            machine.reflector_position = 7;
+            machine
+        };
+
+        let mut ciphertext = String::new();
+        rotated().encode_decode(plaintext.to_string(), &mut ciphertext);
+        assert_ne!(ciphertext, baseline);
+
+        let mut decoded = String::new();
+        rotated().encode_decode(ciphertext, &mut decoded);
+        assert_eq!(decoded, plaintext);
+    }
+
+    fn keystrokes_until_middle_steps(machine: &mut EnigmaMachine) -> usize {
+        let start = machine.rotors[1].position;
+        (1..=26).find(|_| {
+            machine.step();
+            machine.rotors[1].position != start
+        }).expect("middle rotor never stepped")
+    }
+
+    #[test]
+    fn changing_notch_changes_turnover_keystroke() {
+        // The middle rotor steps as the fast one turns from Q to R
+        assert_eq!(keystrokes_until_middle_steps(&mut default_machine()), 17);
+
+        let mut machine = default_machine();
+        machine.rotors[0].notches = Rotor::parse_notches("c").unwrap();
+        assert_eq!(keystrokes_until_middle_steps(&mut machine), 3);
+
+        let mut machine = default_machine();
+        machine.rotors[0].notches = Rotor::parse_notches("Z, E").unwrap();
+        assert_eq!(keystrokes_until_middle_steps(&mut machine), 5);
+    }
+
+    #[test]
+    fn parse_notches_rejects_non_letters() {
+        assert_eq!(Rotor::parse_notches("Q"), Ok(vec!['Q']));
+        assert!(Rotor::parse_notches("1").is_err());
+        assert!(Rotor::parse_notches("QE").is_err());
+        assert!(Rotor::parse_notches("").is_err());
+    }
+
+    #[test]
+    fn parses_key_sheet_row() {
+        let key = parse_key_sheet_row(
+            "Tag 31  Walzenlage II IV V  Ringstellung 06 22 14  Steckerverbindungen AD CN ET FL GI JV KZ PU QY WX",
+        ).unwrap();
+
+        assert_eq!(key.reflector, "B");
+        assert_eq!(key.rotors, vec!["II", "IV", "V"]);
+        assert_eq!(key.ring_settings, vec!['F', 'V', 'N']);
+        assert_eq!(key.positions, vec!['A', 'A', 'A']);
+        assert_eq!(key.plugboard.len(), 10);
+        assert_eq!(key.plugboard[0], ('A', 'D'));
+
+        let machine = key.build().unwrap();
+        let rings: Vec<usize> = machine.rotors.iter().map(|r| r.ring_setting).collect();
+        assert_eq!(rings, vec![13, 21, 5]);
+    }
+
+    #[test]
+    fn parses_umkehrwalze_and_numeric_settings() {
+        let key = parse_key_sheet_row(
+            "walzenlage i ii iii ringstellung a b 26 umkehrwalze c grundstellung 01 x 03 steckerverbindungen",
+        );
+        assert_eq!(key.unwrap().plugboard, vec![]);
+        let key = parse_key_sheet_row("walzenlage i ii iii ringstellung a b 26");
+        assert_eq!(key.unwrap_err(), "Invalid key sheet row: missing Steckerverbindungen.");
+
+        let key = parse_key_sheet_row(
+            "Walzenlage I II III Ringstellung A B 26 Umkehrwalze C Grundstellung 01 X 03 Steckerverbindungen AB",
+        ).unwrap();
+        assert_eq!(key.reflector, "C");
+        assert_eq!(key.ring_settings, vec!['A', 'B', 'Z']);
+        assert_eq!(key.positions, vec!['A', 'X', 'C']);
+    }
+
+    #[test]
+    fn rejects_key_sheet_rows_missing_fields() {
+        assert_eq!(
+            parse_key_sheet_row("Tag 1 Ringstellung 01 02 03 Steckerverbindungen AB").unwrap_err(),
+            "Invalid key sheet row: missing Walzenlage.",
+        );
+        assert_eq!(
+            parse_key_sheet_row("Walzenlage I II III Steckerverbindungen AB").unwrap_err(),
+            "Invalid key sheet row: missing Ringstellung.",
+        );
+        assert!(parse_key_sheet_row("Walzenlage I II III Ringstellung 01 02 Steckerverbindungen AB").is_err());
+        assert!(parse_key_sheet_row("Walzenlage I II IX Ringstellung 01 02 03 Steckerverbindungen AB").is_err());
+        assert!(parse_key_sheet_row("Walzenlage I II III Ringstellung 01 02 27 Steckerverbindungen AB").is_err());
+    }
+
+    #[test]
+    fn bypassed_reflector_passes_letters_straight_back() {
+        let mut machine = default_machine();
+        machine.plugboard = Plugboard::new(&[('A', 'B'), ('C', 'D')]).unwrap();
+        machine.bypass_reflector = true;
+
+        // With no reflection the backward pass undoes the forward pass exactly
+        let mut output = String::new();
+        machine.encode_decode("ABCDXYZ".to_string(), &mut output);
+        assert_eq!(output, "ABCDXYZ");
+    }
+
+    #[test]
+    fn random_plugboard_never_reuses_or_self_pairs_a_letter() {
+        for seed in 0..100 {
+            let pairs = Plugboard::random_pairs(&mut Rng::new(seed), 10);
+            assert_eq!(pairs.len(), 10);
+
+            let mut letters: Vec<char> = pairs.iter().flat_map(|&(a, b)| [a, b]).collect();
+            assert!(pairs.iter().all(|(a, b)| a != b));
+            letters.sort();
+            letters.dedup();
+            assert_eq!(letters.len(), 20);
+        }
+
+        assert_eq!(
+            Plugboard::random_pairs(&mut Rng::new(7), 10),
+            Plugboard::random_pairs(&mut Rng::new(7), 10),
+        );
+    }
+
+    #[test]
+    fn plugboard_pairs_returns_each_connection_once() {
+        let plugboard = Plugboard::new(&[('Q', 'B'), ('A', 'Z'), ('M', 'N')]).unwrap();
+        assert_eq!(plugboard.pairs(), vec![('A', 'Z'), ('B', 'Q'), ('M', 'N')]);
+        assert_eq!(plugboard.to_string(), "AZ BQ MN");
+
+        for seed in 0..10 {
+            let pairs = Plugboard::random_pairs(&mut Rng::new(seed), 10);
+            assert_eq!(Plugboard::new(&pairs).unwrap().pairs(), pairs);
+        }
+        assert!(Plugboard::new(&[]).unwrap().pairs().is_empty());
+    }
+
+    #[test]
+    fn typex_steps_like_an_odometer_at_every_notch() {
+        let windows = |machine: &EnigmaMachine| machine.rotors.iter().map(|rotor| rotor.window_letter()).collect::<String>();
+        let mut machine = Variant::Typex.build();
+
+        // The middle rotor sits on its notch B but the fast one does not: an
+        // Enigma would double-step the middle rotor here, the Typex leaves it
+        machine.set_positions(&['A', 'A', 'A', 'B', 'A']).unwrap();
+        machine.step();
+        assert_eq!(windows(&machine), "AABBA");
+
+        // The fast rotor turning past its notch D carries once, and so does
+        // the middle one turning past B
+        machine.set_positions(&['A', 'A', 'D', 'A', 'A']).unwrap();
+        machine.step();
+        assert_eq!(windows(&machine), "AAEBA");
+        machine.set_positions(&['A', 'A', 'D', 'B', 'A']).unwrap();
+        machine.step();
+        assert_eq!(windows(&machine), "AAECB");
+
+        // Five notches on the fast rotor give five carries per turn, and the
+        // middle rotor passes two of its own on the way from A to F
+        machine.set_positions(&['A', 'A', 'A', 'A', 'A']).unwrap();
+        for _ in 0..26 {
+            machine.step();
+        }
+        assert_eq!(windows(&machine), "AAAFC");
+    }
+
+    #[test]
+    fn typex_round_trips_and_keeps_stators_still() {
+        let plaintext = "THEREISNOHURRYATALLBUTTHEMESSAGEISLONGENOUGHTOSTEPTHEMIDDLEROTOR";
+
+        let mut ciphertext = String::new();
+        let mut machine = Variant::Typex.build();
+        machine.encode_decode(plaintext.to_string(), &mut ciphertext);
+        assert_ne!(ciphertext, plaintext);
+
+        // The stators never move, but the middle rotor has turned over at least once
+        assert_eq!(machine.rotors[0].position, 0);
+        assert_eq!(machine.rotors[1].position, 0);
+        assert_ne!(machine.rotors[3].position, 0);
+
+        let mut decoded = String::new();
+        Variant::Typex.build().encode_decode(ciphertext, &mut decoded);
+        assert_eq!(decoded, plaintext);
+    }
+
+    #[test]
+    fn rotors_step_before_the_letter_is_enciphered() {
+        // Wheel order I II III (left to right), UKW-B, rings and positions AAA:
+        // a real machine types AAAAA as BDZGO
+        let key = EnigmaKey {
+            reflector: "B".to_string(),
+            rotors: vec!["I".to_string(), "II".to_string(), "III".to_string()],
+            ring_settings: vec!['A'; 3],
+            positions: vec!['A'; 3],
+            plugboard: vec![],
+        };
+
+        let mut output = String::new();
+        key.build().unwrap().encode_decode("AAAAA".to_string(), &mut output);
+        assert_eq!(output, "BDZGO");
+    }
+
+    #[test]
+    fn every_preset_applies_and_round_trips() {
+        for (name, row) in PRESETS {
+            let key = parse_key_sheet_row(row).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let machine = key.build().unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+            let mut ciphertext = String::new();
+            machine.clone().encode_decode("PRESETCHECK".to_string(), &mut ciphertext);
+            let mut decoded = String::new();
+            machine.clone().encode_decode(ciphertext, &mut decoded);
+            assert_eq!(decoded, "PRESETCHECK", "{}", name);
+        }
+    }
+
+    #[test]
+    fn letters_convert_to_key_sheet_numbers() {
+        assert_eq!(Alphabet::char_to_number('A'), 1);
+        assert_eq!(Alphabet::char_to_number('Z'), 26);
+        assert_eq!(Alphabet::number_to_char(1), Some('A'));
+        assert_eq!(Alphabet::number_to_char(26), Some('Z'));
+        assert_eq!(Alphabet::number_to_char(0), None);
+        assert_eq!(Alphabet::number_to_char(27), None);
+        assert_eq!(parse_setting_letter("06"), Ok('F'));
+        assert_eq!(parse_setting_letter("26"), Ok('Z'));
+        assert!(parse_setting_letter("00").is_err());
+    }
+
+    #[test]
+    fn reflector_fixed_point_is_detected() {
+        let machine = default_machine();
+        assert!(machine.has_no_fixed_point());
+
+        let mut corrupted = default_machine();
+        corrupted.reflector[0] = 'A';
+        assert!(!corrupted.has_no_fixed_point());
+    }
+
+    fn u264_machine() -> EnigmaMachine {
+        let (_, row) = PRESETS.iter().find(|(name, _)| name.starts_with("U-264")).unwrap();
+        parse_key_sheet_row(row).unwrap().build().unwrap()
+    }
+
+    #[test]
+    fn greek_rotor_stays_put_by_default() {
+        let mut machine = u264_machine();
+        let greek = machine.rotors[3].position;
+        for _ in 0..26 * 26 * 26 {
+            machine.step();
+            assert_eq!(machine.rotors[3].position, greek);
+        }
+
+        let mut machine = u264_machine();
+        machine.rotors[3].steps = true;
+        let moved = (0..26 * 26 * 26).any(|_| {
+            machine.step();
+            machine.rotors[3].position != greek
+        });
+        assert!(moved);
+    }
+
+    #[test]
+    fn advance_matches_encoding_dummy_characters() {
+        for n in [0, 1, 25, 600, 17_000, 50_001] {
+            for mut start in [default_machine(), u264_machine(), Variant::Typex.build()] {
+                start.rotors[1].set_position('D');
+
+                let mut advanced = start.clone();
+                advanced.advance(n);
+
+                let mut output = String::new();
+                start.encode_decode("A".repeat(n), &mut output);
+                assert_eq!(positions(&advanced), positions(&start), "n = {}", n);
+            }
+        }
+
+        // Gear stepping turns the reflector too, so the period is 26^4; a
+        // jump past it still lands where stepping one by one does
+        let mut start = Variant::EnigmaK.build();
+        start.gear_stepping = true;
+        start.reflector_position = 3;
+        let mut advanced = start.clone();
+        advanced.advance(500_000);
+        for _ in 0..500_000 {
+            start.step();
+        }
+        assert_eq!(positions(&advanced), positions(&start));
+        assert_eq!(advanced.reflector_position, start.reflector_position);
+        assert_eq!(advanced.key_count, start.key_count);
+    }
+
+    #[test]
+    fn window_letter_accounts_for_ring_setting() {
+        let (_, wiring, notches) = Rotor::catalog_entry("I").unwrap();
+        let mut ringed = Rotor::new(wiring, notches);
+        ringed.set_ring_setting('F');
+        ringed.set_position('C');
+        assert_eq!(ringed.window_letter(), 'C');
+        assert_eq!(ringed.core_letter(), 'X');
+
+        // The wiring only depends on the core, so ring F at window C behaves
+        // like ring A at window X
+        let mut plain = Rotor::new(wiring, notches);
+        plain.set_position('X');
+        assert_eq!(plain.window_letter(), plain.core_letter());
+        for &c in Alphabet::LETTERS {
+            assert_eq!(ringed.encode_forward(c), plain.encode_forward(c));
+        }
+    }
+
+    #[test]
+    fn calibration_shifts_the_window_not_the_ciphertext() {
+        let mut calibrated = default_machine();
+        calibrated.set_calibrations(&[0, 3, 25]);
+        assert_eq!(calibrated.rotors[1].engraved_letter(), 'D');
+        assert_eq!(calibrated.rotors[2].engraved_letter(), 'Z');
+        assert_eq!(calibrated.rotors[2].window_letter(), 'A');
+
+        // Dialling the engraved letter lands on the standard position under it
+        calibrated.rotors[1].set_engraved_position('D');
+        assert_eq!(calibrated.rotors[1].window_letter(), 'A');
+
+        let mut standard = default_machine();
+        let (mut a, mut b) = (String::new(), String::new());
+        calibrated.encode_decode("CALIBRATIONISFORTHEEYEONLY".to_string(), &mut a);
+        standard.encode_decode("CALIBRATIONISFORTHEEYEONLY".to_string(), &mut b);
+        assert_eq!(a, b);
+        for (calibrated, standard) in calibrated.rotors.iter().zip(&standard.rotors) {
+            assert_eq!(calibrated.position, standard.position);
+        }
+    }
+
+    #[test]
+    fn key_settings_must_be_letters() {
+        let mut key = parse_key_sheet_row("Walzenlage I II III Ringstellung a b 03 Umkehrwalze B Steckerverbindungen ab").unwrap();
+        assert_eq!(key.ring_settings, vec!['A', 'B', 'C']);
+        assert_eq!(key.plugboard, vec![('A', 'B')]);
+        assert!(key.build().is_ok());
+
+        key.ring_settings[0] = 'a';
+        assert_eq!(key.build().err().unwrap(), "Invalid setting 'a': Expected a letter A-Z.");
+        key.ring_settings[0] = 'A';
+        key.positions[2] = '?';
+        assert!(key.build().is_err());
+        assert!(Plugboard::parse_pairs("é").is_err());
+    }
+
+    #[test]
+    fn components_must_share_the_alphabet_size() {
+        let reflector = "YRUHQSLDPXNGOKMIEBFZCWVJAT";
+        let error = EnigmaMachine::new(
+            vec![("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q"), ("ABCDEFGHIJKLMNOPQRST", "E")],
+            reflector,
+            &[],
+        ).err().unwrap();
+        assert_eq!(error, "Rotor 2 has 20 contacts but the reflector has 26; every component must use the same alphabet.");
+
+        assert!(EnigmaMachine::new(vec![("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q")], "ABCDEFGHIJ", &[]).is_err());
+        assert!(EnigmaMachine::new(vec![("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q")], reflector, &[]).is_ok());
+
+        // The right length is not enough: rotors must be permutations of A-Z
+        // and the reflector a valid pairing
+        let error = EnigmaMachine::new(vec![("ekmflgdqvzntowyhxuspaibrcj", "Q")], reflector, &[]).err().unwrap();
+        assert_eq!(error, "Rotor 1 wiring must use each letter A-Z exactly once.");
+        assert!(EnigmaMachine::new(vec![("EEMFLGDQVZNTOWYHXUSPAIBRCJ", "Q")], reflector, &[]).is_err());
+        let error = EnigmaMachine::new(vec![("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q")], "yruhqsldpxngokmiebfzcwvjat", &[]).err().unwrap();
+        assert_eq!(error, "Invalid reflector wiring: 'y' is not a letter A-Z.");
+        assert!(EnigmaMachine::new(vec![("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q")], "ABCDEFGHIJKLMNOPQRSTUVWXYZ", &[]).is_err());
+        assert!(EnigmaMachine::new(vec![("EKMFLGDQVZNTOWYHXUSPAIBRCJ", "Q")], "YYUHQSLDPXNGOKMIEBFZCWVJAT", &[]).is_err());
+    }
+
+    #[test]
+    fn key_bytes_round_trip() {
+        let key = parse_key_sheet_row("Walzenlage II IV V Ringstellung 02 21 12 Umkehrwalze B Grundstellung 06 22 14 Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX").unwrap();
+        let bytes = key.to_bytes().unwrap();
+        assert!(bytes.len() < 32, "{} bytes", bytes.len());
+        assert_eq!(EnigmaKey::from_bytes(&bytes).unwrap(), key);
+
+        let (_, row) = PRESETS.iter().find(|(name, _)| name.starts_with("U-264")).unwrap();
+        let m4 = parse_key_sheet_row(row).unwrap();
+        assert_eq!(EnigmaKey::from_bytes(&m4.to_bytes().unwrap()).unwrap(), m4);
+
+        assert!(EnigmaKey::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(EnigmaKey::from_bytes(&extra).is_err());
+    }
+
+    #[test]
+    fn odometer_returns_to_start_after_every_position() {
+        let mut machine = default_machine();
+        machine.double_stepping = false;
+        for _ in 0..26 * 26 * 26 {
+            machine.step();
+        }
+        assert_eq!(positions(&machine), "AAA");
+
+        // The double step skips the middle rotor's notch position each time the
+        // left rotor turns, so the cycle is 26 * 25 * 26 keypresses instead
+        let mut machine = default_machine();
+        for _ in 0..26 * 25 * 26 {
+            machine.step();
+        }
+        assert_eq!(positions(&machine), "AAA");
+        for _ in 26 * 25 * 26..26 * 26 * 26 {
+            machine.step();
+        }
+        assert_ne!(positions(&machine), "AAA");
+    }
+
+    #[test]
+    fn double_stepping_toggle_changes_sequence_across_notch() {
+        // The fast rotor leaving Q brings the middle one to its notch, E,
+        // and the next keypress steps it again along with the left rotor
+        assert_eq!(step_sequence(true), vec!["REA", "SFB"]);
+        assert_eq!(step_sequence(false), vec!["REA", "SEA"]);
+    }
+
+    #[test]
+    fn encode_stream_matches_encode_decode() {
+        let text = "Grüße aus Berlin, ".repeat(1000);
+        let mut expected = String::new();
+        default_machine().encode_decode(text.clone(), &mut expected);
+
+        let mut written = Vec::new();
+        let count = default_machine().encode_stream(text.as_bytes(), &mut written).unwrap();
+        assert_eq!(count, expected.len());
+        assert_eq!(String::from_utf8(written).unwrap(), expected);
+    }
+
+    #[test]
+    fn encode_stream_rejects_invalid_utf8() {
+        let mut written = Vec::new();
+        let result = default_machine().encode_stream(&b"HELLO\xFFWORLD"[..], &mut written);
+        assert_eq!(result, Err("Input is not valid UTF-8.".to_string()));
+
+        // A multi-byte character cut off at the end is invalid too
+        let result = default_machine().encode_stream(&b"HELLO\xC3"[..], &mut Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn trace_ends_at_the_enciphered_letter() {
+        let mut machine = u264_machine();
+        let mut traced = machine.clone();
+
+        for c in "VONVON".chars() {
+            let trace = traced.trace_char(c);
+            assert_eq!(trace.len(), 2 * 4 + 4);
+            assert_eq!(trace[0], c);
+            assert_eq!(*trace.last().unwrap(), machine.encode_char(c));
+        }
+    }
+
+    #[test]
+    fn diagnoses_a_broken_reflector() {
+        // UKW-B with C wired to itself and E rewired from Q to D
+        let ukw_b = Reflector::by_id(ReflectorId::B).wiring.unwrap();
+        let mut wiring = parse_reflector_wiring(ukw_b).unwrap();
+        wiring[2] = 'C';
+        wiring[4] = 'D';
+
+        let diagnosis = diagnose_reflector(&wiring);
+        assert!(!diagnosis.is_valid());
+        assert_eq!(diagnosis.fixed_points, vec!['C']);
+        assert_eq!(diagnosis.asymmetric, vec![('E', 'D', 'H'), ('Q', 'E', 'D'), ('U', 'C', 'C')]);
+        assert_eq!(diagnosis.problems()[1], "E maps to D but D maps to H.");
+
+        // Re-pairing the stray letters restores UKW-B
+        assert_eq!(diagnosis.suggestion.iter().collect::<String>(), ukw_b);
+        assert!(diagnose_reflector(&diagnosis.suggestion).is_valid());
+
+        let mut machine = default_machine();
+        assert!(machine.set_reflector(wiring).is_err());
+        assert!(machine.set_reflector(diagnosis.suggestion).is_ok());
+    }
+
+    #[test]
+    fn catalogued_reflectors_are_valid() {
+        for &id in Reflector::all() {
+            let reflector = Reflector::by_id(id);
+            assert_eq!(reflector.id, id);
+            // UKW-D is wired by the user
+            if let Some(wiring) = reflector.wiring {
+                let wiring = parse_reflector_wiring(wiring).unwrap();
+                assert!(diagnose_reflector(&wiring).is_valid(), "{}", reflector.name);
+            } else {
+                assert_eq!(id, ReflectorId::D);
+            }
+        }
+        assert_eq!(Reflector::by_name("b-THIN").map(|r| r.id), Some(ReflectorId::BThin));
+        assert!(parse_reflector_wiring("ABC").is_err());
+    }
+
+    #[test]
+    fn receiver_recovers_message_key_from_indicator() {
+        let ground = ['W', 'Z', 'A'];
+        let message_key = ['K', 'D', 'R'];
+        let mut sender = default_machine();
+        let mut receiver = default_machine();
+
+        let indicator = sender.encipher_message_key(&ground, &message_key).unwrap();
+        assert_eq!(indicator.len(), 3);
+        assert_eq!(positions(&sender), "KDR");
+
+        let mut body = String::new();
+        sender.encode_decode("ANGRIFF".to_string(), &mut body);
+
+        let indicator: Vec<char> = indicator.chars().collect();
+        assert_eq!(receiver.recover_message_key(&ground, &indicator).unwrap(), message_key);
+        let mut plaintext = String::new();
+        receiver.encode_decode(body, &mut plaintext);
+        assert_eq!(plaintext, "ANGRIFF");
+
+        // A bad setting on either side leaves the rotors alone
+        let before = positions(&sender);
+        assert!(sender.encipher_message_key(&['A', 'B'], &message_key).is_err());
+        assert_eq!(positions(&sender), before);
+        assert!(sender.encipher_message_key(&ground, &['K', 'd', 'R']).is_err());
+        assert_eq!(positions(&sender), before);
+        assert!(receiver.recover_message_key(&['A', 'B'], &indicator).is_err());
+        assert_eq!(positions(&receiver), before);
+    }
+
+    #[test]
+    fn flags_duplicate_rotors() {
+        let key = parse_key_sheet_row("Walzenlage II II I Ringstellung A A A Steckerverbindungen AB").unwrap();
+        assert!(key.build().is_ok());
+        assert_eq!(validate_physical(&key), vec![Warning::DuplicateRotor("II".to_string())]);
+    }
+
+    #[test]
+    fn flags_more_plugs_than_cables() {
+        let key = parse_key_sheet_row(
+            "Walzenlage I II III Ringstellung A A A Steckerverbindungen AB CD EF GH IJ KL MN OP QR ST UV",
+        ).unwrap();
+        assert_eq!(validate_physical(&key), vec![Warning::TooManyPlugs(11, PLUG_CABLES)]);
+        assert_eq!(validate_physical(&key)[0].to_string(), "11 plug pairs set, but only 10 cables were issued.");
+    }
+
+    #[test]
+    fn historical_presets_are_physically_possible() {
+        for (name, row) in PRESETS {
+            assert_eq!(validate_physical(&parse_key_sheet_row(row).unwrap()), vec![], "{}", name);
+        }
+    }
+
+    #[test]
+    fn current_mapping_is_its_own_inverse() {
+        let mut machine = u264_machine();
+        for _ in 0..30 {
+            let before = positions(&machine);
+            let mapping = machine.current_mapping();
+            assert_eq!(positions(&machine), before);
+
+            for (i, &c) in mapping.iter().enumerate() {
+                assert_ne!(c, Alphabet::index_to_char(i));
+                assert_eq!(mapping[Alphabet::char_to_index(c)], Alphabet::index_to_char(i));
+            }
+            machine.step();
+        }
+    }
+
+    // Notches of the Abwehr Enigma G rotors I, II and III
+    fn zaehlwerk_machine() -> EnigmaMachine {
+        let mut machine = default_machine();
+        machine.gear_stepping = true;
+        machine.rotatable_reflector = true;
+        for (rotor, notches) in machine.rotors.iter_mut().zip(["ABCEFGIKLOPQSUVWZ", "ABDGHIKLNOPSUVY", "CEFIMNPSUVZ"]) {
+            rotor.notches = notches.chars().collect();
+        }
+        machine
+    }
+
+    #[test]
+    fn gear_stepping_carries_once_per_notch_without_double_steps() {
+        let mut machine = zaehlwerk_machine();
+        let mut middle_steps = 0;
+        for _ in 0..26 {
+            let middle = machine.rotors[1].position;
+            machine.step();
+            if machine.rotors[1].position != middle {
+                middle_steps += 1;
+                // The middle wheel only moves when the fast one carries
+                assert!(machine.rotors[0].notches.contains(&Alphabet::index_to_char(machine.rotors[0].position + 25)));
+            }
+        }
+        // A full turn of the fast wheel passes each of its 17 notches once
+        assert_eq!(middle_steps, 17);
+        assert_eq!(machine.rotors[0].position, 0);
+        assert_eq!(machine.key_count, 26);
+    }
+
+    #[test]
+    fn gear_stepping_is_reversible() {
+        let mut machine = zaehlwerk_machine();
+        machine.set_positions(&['Q', 'E', 'V']).unwrap();
+        let start = positions(&machine);
+
+        let mut visited = Vec::new();
+        for _ in 0..5000 {
+            visited.push((positions(&machine), machine.reflector_position));
+            machine.step();
+        }
+        assert_ne!(machine.reflector_position, 0);
+        for expected in visited.into_iter().rev() {
+            machine.step_back().unwrap();
+            assert_eq!((positions(&machine), machine.reflector_position), expected);
+        }
+        assert_eq!(positions(&machine), start);
+        assert_eq!(machine.key_count, 0);
+
+        assert!(default_machine().step_back().is_err());
+    }
+
+    #[test]
+    fn filter_policies_handle_spaces_and_digits() {
+        let message = "Attack at 0600";
+        let mut letters_only = String::new();
+        default_machine().encode_decode(message.to_string(), &mut letters_only);
+
+        let dropped = default_machine().encode_with_policy(message, FilterPolicy::DropNonLetters).unwrap();
+        assert_eq!(dropped, letters_only);
+
+        // Non-letters keep their place and don't step the rotors
+        let passed = default_machine().encode_with_policy(message, FilterPolicy::PassThroughNonLetters).unwrap();
+        assert_eq!(passed.len(), message.len());
+        assert_eq!(&passed[6..7], " ");
+        assert_eq!(&passed[9..], " 0600");
+        assert_eq!(passed.chars().filter(|c| c.is_ascii_alphabetic()).collect::<String>(), letters_only);
+
+        let mut machine = default_machine();
+        let rejected = machine.encode_with_policy(message, FilterPolicy::RejectNonLetters);
+        assert_eq!(rejected, Err("Invalid input: ' ' is not a letter.".to_string()));
+        assert_eq!(positions(&machine), "AAA");
+        assert_eq!(machine.encode_with_policy("attack", FilterPolicy::RejectNonLetters).unwrap(), letters_only[..6]);
+    }
+
+    #[test]
+    fn match_input_casing_follows_each_input_letter() {
+        let upper = default_machine().encode_with_policy("Hello World", FilterPolicy::PassThroughNonLetters).unwrap();
+        let matched = default_machine().encode_with_casing("Hello World", FilterPolicy::PassThroughNonLetters, Casing::MatchInput).unwrap();
+        assert_eq!(matched.to_ascii_uppercase(), upper);
+        let cases: Vec<bool> = matched.chars().map(|c| c.is_ascii_uppercase()).collect();
+        let expected: Vec<bool> = "Hello World".chars().map(|c| c.is_ascii_uppercase()).collect();
+        assert_eq!(cases, expected);
+
+        let lower = default_machine().encode_with_casing("Hello World", FilterPolicy::PassThroughNonLetters, Casing::LowerOnly).unwrap();
+        assert_eq!(lower, upper.to_ascii_lowercase());
+    }
+
+    #[test]
+    fn qwertz_entry_wheel_changes_the_cipher() {
+        let mut straight = String::new();
+        default_machine().encode_decode("ATTACKATDAWN".to_string(), &mut straight);
+
+        let commercial = || {
+            let mut machine = default_machine();
+            machine.entry_wheel = EnigmaMachine::QWERTZ_ENTRY_WHEEL;
+            machine
+        };
+        let mut ciphertext = String::new();
+        commercial().encode_decode("ATTACKATDAWN".to_string(), &mut ciphertext);
+        assert_ne!(ciphertext, straight);
+
+        let mut decoded = String::new();
+        commercial().encode_decode(ciphertext, &mut decoded);
+        assert_eq!(decoded, "ATTACKATDAWN");
+        assert_eq!(commercial().trace('Q')[1], 'A');
+    }
+
+    #[test]
+    fn chunked_encode_matches_the_synchronous_path() {
+        let message = "Attack at dawn, hold the bridge!\n".repeat(50);
+        for policy in FilterPolicy::ALL.into_iter().filter(|&policy| policy != FilterPolicy::RejectNonLetters) {
+            let mut machine = default_machine();
+            let expected = machine.encode_with_casing(&message, policy, Casing::MatchInput).unwrap();
+
+            let mut chunked = ChunkedEncode::new(default_machine(), &message, policy, Casing::MatchInput).unwrap();
+            let mut pieces = 1;
+            while !chunked.run(37) {
+                pieces += 1;
+            }
+            assert_eq!(pieces, message.len().div_ceil(37));
+            assert_eq!(chunked.output, expected);
+            assert_eq!(positions(&chunked.machine), positions(&machine));
+        }
+        assert!(ChunkedEncode::new(default_machine(), "A B", FilterPolicy::RejectNonLetters, Casing::UpperOnly).is_err());
+    }
+
+    #[test]
+    fn line_breaks_survive_encoding() {
+        let message = "Attack at dawn\nHold the bridge";
+        let letters_only = default_machine().encode_with_policy(message, FilterPolicy::DropNonLetters).unwrap();
+
+        let lines = default_machine().encode_with_policy(message, FilterPolicy::KeepLineBreaks).unwrap();
+        assert_eq!(lines.lines().collect::<Vec<_>>(), vec![&letters_only[..12], &letters_only[12..]]);
+
+        let spaced = default_machine().encode_with_policy(message, FilterPolicy::KeepLineBreaksAndSpaces).unwrap();
+        assert_eq!(spaced.lines().count(), 2);
+        assert_eq!(spaced.replace([' ', '\n'], ""), letters_only);
+        assert_eq!(spaced.find('\n'), Some(14));
+
+        // Decoding at the same settings gives back the layout as well as the letters
+        let decoded = default_machine().encode_with_policy(&spaced, FilterPolicy::KeepLineBreaksAndSpaces).unwrap();
+        assert_eq!(decoded, message.to_uppercase());
+    }
+
+    #[test]
+    fn key_rows_round_trip_through_the_machine() {
+        for (name, row) in PRESETS {
+            let key = parse_key_sheet_row(row).unwrap();
+            let machine = key.build().unwrap();
+            let recovered = machine.to_key().unwrap();
+            assert_eq!(recovered, key, "{}", name);
+            assert_eq!(parse_key_sheet_row(&recovered.to_string()).unwrap(), key, "{}", name);
+        }
+        assert!(Variant::Typex.build().to_key().is_none());
+    }
+
+    #[test]
+    fn inspector_accessors_expose_the_wiring() {
+        let machine = default_machine();
+        let (_, wiring, _) = Rotor::catalog_entry("I").unwrap();
+        assert_eq!(machine.rotors[0].wiring(), wiring);
+        assert_eq!(machine.rotors[0].catalog_name(), Some("I"));
+        assert_eq!(Rotor::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ", "").catalog_name(), None);
+        let reflector_b = Reflector::by_id(ReflectorId::B).wiring.unwrap();
+        assert!(reflector_b.chars().eq(machine.reflector_wiring()));
+    }
+
+    #[test]
+    fn plugboard_rejects_overlapping_and_self_pairs() {
+        assert_eq!(
+            Plugboard::new(&[('A', 'B'), ('B', 'C')]).err().unwrap(),
+            "Invalid plugboard configuration: duplicate letters or invalid pair 'BC'.",
+        );
+        assert!(Plugboard::new(&[('A', 'B'), ('C', 'A')]).is_err());
+        assert!(Plugboard::new(&[('E', 'E')]).is_err());
+        assert!(Plugboard::new(&[('a', 'b')]).is_err());
+        assert!(EnigmaMachine::new(vec![], "YRUHQSLDPXNGOKMIEBFZCWVJAT", &[('A', 'B'), ('A', 'C')]).is_err());
+    }
+
+    #[test]
+    fn removing_a_pair_leaves_the_rest() {
+        let mut plugboard = Plugboard::new(&[('A', 'M'), ('F', 'I'), ('N', 'V')]).unwrap();
+        // Either end of the cable pulls it out
+        assert!(plugboard.remove_pair('I'));
+        assert_eq!(plugboard.pairs(), vec![('A', 'M'), ('N', 'V')]);
+        assert_eq!(plugboard.swap('F'), 'F');
+        assert_eq!(plugboard.swap('I'), 'I');
+        assert_eq!(plugboard.swap('N'), 'V');
+        assert!(!plugboard.remove_pair('I'));
+        assert_eq!(plugboard.to_string(), "AM NV");
+    }
+
+    #[test]
+    fn valid_plugboard_is_an_involution() {
+        let plugboard = Plugboard::new(&Plugboard::random_pairs(&mut Rng::new(3), 13)).unwrap();
+        for &c in Alphabet::LETTERS {
+            assert_ne!(plugboard.swap(c), c);
+            assert_eq!(plugboard.swap(plugboard.swap(c)), c);
+        }
+    }
+
+    #[test]
+    fn figure_shift_survives_the_machine() {
+        let letters = figure_shift_encode("ABC 123").unwrap();
+        assert_eq!(letters, "ABCYXQWE");
+
+        let mut ciphertext = String::new();
+        default_machine().encode_decode(letters, &mut ciphertext);
+        let mut deciphered = String::new();
+        default_machine().encode_decode(ciphertext, &mut deciphered);
+        assert_eq!(figure_shift_decode(&deciphered), "ABC 123");
+    }
+
+    #[test]
+    fn figure_shift_escapes_the_shift_letter() {
+        for text in ["YES 1", "1Y", "MAY 8, 1945.", "Y", "YYY 0"] {
+            let letters = figure_shift_encode(text).unwrap();
+            assert!(letters.chars().all(|c| c.is_ascii_uppercase()));
+            assert_eq!(figure_shift_decode(&letters), text, "{}", letters);
+        }
+        assert!(figure_shift_encode("50%").is_err());
+    }
+
+    #[test]
+    fn fingerprint_ignores_how_the_key_was_written() {
+        let key = parse_key_sheet_row("Walzenlage II IV V Ringstellung 02 21 12 Grundstellung B L A Umkehrwalze B Steckerverbindungen AV BS CG DL").unwrap();
+        let reordered = parse_key_sheet_row("Walzenlage ii iv v Ringstellung B U L Grundstellung Q Q Q Umkehrwalze b Steckerverbindungen LD GC AV SB").unwrap();
+        assert_eq!(key.fingerprint().len(), 8);
+        assert!(key.fingerprint().chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(key.fingerprint(), reordered.fingerprint());
+
+        let mut different = key.clone();
+        different.plugboard[0] = ('A', 'W');
+        assert_ne!(key.fingerprint(), different.fingerprint());
+        let mut different = key.clone();
+        different.ring_settings[2] = 'M';
+        assert_ne!(key.fingerprint(), different.fingerprint());
+    }
+
+    #[test]
+    fn space_convention_fills_and_restores_spaces() {
+        assert_eq!(SpaceConvention::X.apply("MEET  AT\nDAWN"), "MEETXAT\nDAWN");
+        assert_eq!(SpaceConvention::J.apply("MEET AT X"), "MEETJATJX");
+        assert_eq!(SpaceConvention::None.apply("MEET AT"), "MEET AT");
+        assert_eq!(SpaceConvention::X.restore("meetxat"), "meet at");
+    }
+
+    #[test]
+    fn normalizes_grouped_ciphertext() {
+        assert_eq!(normalize_ciphertext("HELLO WORLD TESTX"), ("HELLOWORLDTESTX".to_string(), Some(5)));
+        assert_eq!(normalize_ciphertext("NCZW VUSX\nPNYM INH"), ("NCZWVUSXPNYMINH".to_string(), Some(4)));
+        assert_eq!(normalize_ciphertext("GCDSEAHUGW"), ("GCDSEAHUGW".to_string(), None));
+        // Uneven groups are still run together, but have no size
+        assert_eq!(normalize_ciphertext("AB CDE FG"), ("ABCDEFG".to_string(), None));
+        assert_eq!(normalize_ciphertext(""), (String::new(), None));
+    }
+
+    #[test]
+    fn reflector_bank_switches_the_active_wiring() {
+        let mut bank = ReflectorBank::default();
+        let b = bank.load("B", Reflector::by_id(ReflectorId::B).wiring.unwrap()).unwrap();
+        let c = bank.load("C", Reflector::by_id(ReflectorId::C).wiring.unwrap()).unwrap();
+        // Every loaded entry is its own inverse with no letter left alone
+        for (_, wiring) in bank.entries() {
+            for (i, &partner) in wiring.iter().enumerate() {
+                assert_ne!(Alphabet::char_to_index(partner), i);
+                assert_eq!(Alphabet::char_to_index(wiring[Alphabet::char_to_index(partner)]), i);
+            }
+        }
+
+        let mut machine = EnigmaMachine::default();
+        let encode = |machine: &EnigmaMachine| {
+            let mut output = String::new();
+            machine.clone().encode_decode("HELLOWORLD".to_string(), &mut output);
+            output
+        };
+        bank.activate(c, &mut machine).unwrap();
+        let with_c = encode(&machine);
+        bank.activate(b, &mut machine).unwrap();
+        assert_eq!(bank.active(), Some(b));
+        assert_eq!(encode(&machine), encode(&EnigmaMachine::default()));
+        assert_ne!(encode(&machine), with_c);
+
+        // A wiring that is not an involution is refused on load
+        assert!(bank.load("Broken", "BCDEFGHIJKLMNOPQRSTUVWXYZA").unwrap_err().starts_with("Invalid reflector wiring"));
+        assert_eq!(bank.entries().len(), 2);
+
+        bank.remove(b);
+        assert_eq!(bank.active(), None);
+        assert_eq!(bank.entries()[0].0, "C");
+    }
+
+    #[test]
+    fn bypassed_plugboard_acts_as_no_plugboard() {
+        let row = "Walzenlage II IV V Ringstellung 02 21 12 Grundstellung B L A Umkehrwalze B Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX";
+        let mut machine = parse_key_sheet_row(row).unwrap().build().unwrap();
+        let mut unplugged = parse_key_sheet_row(row).unwrap();
+        unplugged.plugboard.clear();
+        let unplugged = unplugged.build().unwrap();
+
+        let message = "AUFKLXABTEILUNGXVONXKURTINOWA".to_string();
+        let mut expected = String::new();
+        unplugged.clone().encode_decode(message.clone(), &mut expected);
+
+        machine.bypass_plugboard = true;
+        let mut output = String::new();
+        machine.clone().encode_decode(message.clone(), &mut output);
+        assert_eq!(output, expected);
+        assert_eq!(machine.trace('A'), unplugged.trace('A'));
+
+        // The pairs are still there when the plugboard comes back
+        machine.bypass_plugboard = false;
+        assert_eq!(machine.plugboard.pairs().len(), 10);
+        machine.encode_decode(message, &mut output);
+        assert_ne!(output, expected);
+    }
+
+    #[test]
+    fn sanitizer_reports_what_it_drops() {
+        let (letters, dropped) = sanitize("Hi, it's 9 o'clock\nStraße");
+        assert_eq!(letters, "HIITSOCLOCKSTRASSE");
+        assert_eq!(dropped, vec![(2, ','), (3, ' '), (6, '\''), (8, ' '), (9, '9'), (10, ' '), (12, '\''), (18, '\n')]);
+        assert_eq!(
+            describe_dropped(&dropped[..3]).unwrap(),
+            "Dropped 3 non-letters at positions 3, 4, 7",
+        );
+        assert_eq!(describe_dropped(&[(0, '1')]).unwrap(), "Dropped 1 non-letter at position 1");
+        assert_eq!(describe_dropped(&[]), None);
+
+        // encode_decode enciphers exactly the sanitized letters
+        let mut output = String::new();
+        EnigmaMachine::default().encode_decode("Hi, it's 9 o'clock\nStraße".to_string(), &mut output);
+        let mut expected = String::new();
+        EnigmaMachine::default().encode_decode(letters, &mut expected);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn plugboard_strength_counts_cables() {
+        let empty = Plugboard::new(&[]).unwrap();
+        assert_eq!(plugboard_strength(&empty), 0);
+
+        let demo = Plugboard::new(&Plugboard::parse_pairs("AB CD").unwrap()).unwrap();
+        assert_eq!(plugboard_strength(&demo), 2);
+        assert!(plugboard_strength(&demo) < WEAK_PLUGBOARD_PAIRS);
+
+        let full = Plugboard::new(&Plugboard::parse_pairs("AB CD EF GH IJ KL MN OP QR ST UV WX YZ").unwrap()).unwrap();
+        assert_eq!(plugboard_strength(&full), 13);
+    }
+
+    #[test]
+    fn backward_undoes_forward_under_ring_settings() {
+        // (ring setting, position), including the wrap where the ring is
+        // ahead of the window letter
+        let settings = [(0, 0), (1, 0), (0, 1), (5, 3), (3, 5), (25, 0), (0, 25), (25, 25), (13, 7)];
+        for &(name, wiring, notches) in Rotor::CATALOG {
+            let mut rotor = Rotor::new(wiring, notches);
+            for &(ring_setting, position) in &settings {
+                rotor.ring_setting = ring_setting;
+                rotor.position = position;
+                for c in 'A'..='Z' {
+                    assert_eq!(rotor.encode_backward(rotor.encode_forward(c)), c, "rotor {} ring {} position {}", name, ring_setting, position);
+                    assert_eq!(rotor.encode_forward(rotor.encode_backward(c)), c, "rotor {} ring {} position {}", name, ring_setting, position);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ring_setting_shifts_the_wiring() {
+        // Rotor I with Ringstellung B at window A takes A to K, the usual
+        // worked example; at Ringstellung A it would give E
+        let (_, wiring, notches) = Rotor::catalog_entry("I").unwrap();
+        let mut rotor = Rotor::new(wiring, notches);
+        assert_eq!(rotor.encode_forward('A'), 'E');
+        rotor.ring_setting = 1;
+        assert_eq!(rotor.encode_forward('A'), 'K');
+        assert_eq!(rotor.encode_backward('K'), 'A');
+    }
+
+    #[test]
+    fn array_reverse_lookup_matches_hashmap() {
+        for &(name, wiring, notches) in Rotor::CATALOG {
+            let mut rotor = Rotor::new(wiring, notches);
+            for ring_setting in 0..26 {
+                for position in 0..26 {
+                    rotor.position = position;
+                    rotor.ring_setting = ring_setting;
+                    for c in 'A'..='Z' {
+                        assert_eq!(rotor.encode_backward_array(c), rotor.encode_backward(c), "rotor {} {}", name, c);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,367 +1,3300 @@
 use eframe::egui;
+use enigma_machine::key_sheet::random_key;
+use enigma_machine::transmission::{prepare_batch, receive, LiveGroups, Transmission};
+use enigma_machine::walkthrough::SlowMotion;
+use enigma_machine::analysis::{cycle_structure, flattening, format_cycles, index_of_coincidence, indicator_permutations, Flattening, mismatch_indices, perturbed_decodes, WrongDecode, top_ngrams, word_hit_score, ENGLISH_WORDS, GERMAN_WORDS};
+use enigma_machine::{describe_dropped, diagnose_reflector, sanitize, figure_shift_decode, figure_shift_encode, normalize_ciphertext, parse_key_sheet_row, parse_reflector_wiring, parse_setting_letter, Alphabet, PRESETS, WEAK_PLUGBOARD_PAIRS, Casing, ChunkedEncode, EnigmaKey, EnigmaMachine, FilterPolicy, Plugboard, Reflector, ReflectorBank, ReflectorId, Rng, Rotor, RotorOrder, SpaceConvention, Variant, plugboard_strength, validate_physical};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
 
-struct Alphabet;
+mod text;
 
-impl Alphabet {
-    const LETTERS: &'static [char; 26] = &[
-        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M',
-        'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
-    ];
+// Oldest entries are dropped once the history reaches this size
+const MAX_HISTORY: usize = 50;
 
-    fn char_to_index(c: char) -> usize {
-        c as usize - 'A' as usize
+struct HistoryEntry {
+    input: String,
+    output: String,
+    settings: String,
+}
+
+// What the message input holds, so an empty message isn't reported as invalid
+#[derive(Debug, PartialEq)]
+enum InputKind {
+    Empty,
+    Invalid,
+    Letters,
+}
+
+// How much of the machine the window shows. Operator keeps to what a
+// signals operator handled; Expert adds the internals and analysis tools.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum UiMode {
+    Operator,
+    Expert,
+}
+
+impl UiMode {
+    const ALL: [UiMode; 2] = [UiMode::Operator, UiMode::Expert];
+
+    fn name(&self) -> &'static str {
+        match self {
+            UiMode::Operator => "Operator",
+            UiMode::Expert => "Expert",
+        }
     }
 
-    fn index_to_char(index: usize) -> char {
-        Alphabet::LETTERS[index % 26]
+    // Anything unrecognised starts a newcomer off in Operator
+    fn from_storage(stored: Option<String>) -> Self {
+        Self::ALL.into_iter()
+            .find(|mode| stored.as_deref() == Some(mode.name()))
+            .unwrap_or(UiMode::Operator)
     }
 }
 
-struct Rotor {
-    wiring: Vec<char>,
-    reverse_lookup: std::collections::HashMap<char, usize>,
-    notch: char,
-    position: usize,
+// Named configurations the user keeps coming back to, each stored as a key
+// sheet row. Persisted one per line as "name<TAB>row".
+#[derive(Default)]
+struct Pinboard {
+    pins: Vec<(String, String)>,
 }
 
-impl Rotor {
-    fn new(wiring: &str, notch: char) -> Self {
-        let wiring_array: Vec<char> = wiring.chars().collect();
-        // Reverse lookup for rotor makes encode_backward O(1) instead of O(n)
-        let reverse_lookup: std::collections::HashMap<char, usize> = wiring_array.iter().enumerate()
-            .map(|(i, &c)| (c, i))
+impl Pinboard {
+    fn from_storage(stored: &str) -> Self {
+        let pins = stored.lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(name, row)| (name.to_string(), row.to_string()))
             .collect();
-
-        Self {
-            wiring: wiring_array,
-            reverse_lookup,
-            notch,
-            position: 0,
-        }
+        Self { pins }
     }
 
-    fn encode_forward(&self, input: char) -> char {
-        let index = Alphabet::char_to_index(input);
-        let shifted_index = (index + self.position) % 26;
-        self.wiring[shifted_index]
+    fn to_storage(&self) -> String {
+        self.pins.iter().map(|(name, row)| format!("{}\t{}", name, row)).collect::<Vec<_>>().join("\n")
     }
-        
-    fn encode_backward(&self, input: char) -> char {
-        let index = *self.reverse_lookup.get(&input).expect("Invalid character in reverse lookup");
-        let shifted_index = (26 + index - self.position) % 26;
-        Alphabet::index_to_char(shifted_index)
+
+    // Pinning under an existing name replaces that pin
+    fn add(&mut self, name: &str, row: String) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() || name.contains(['\t', '\n']) {
+            return Err("Give the pinned configuration a name on one line.".to_string());
+        }
+        match self.pins.iter_mut().find(|(existing, _)| existing == name) {
+            Some(pin) => pin.1 = row,
+            None => self.pins.push((name.to_string(), row)),
+        }
+        Ok(())
     }
-    
-    fn rotate(&mut self) -> bool {
-        self.position = (self.position + 1) % 26;
-        Alphabet::index_to_char(self.position) == self.notch
+
+    fn remove(&mut self, name: &str) -> bool {
+        let count = self.pins.len();
+        self.pins.retain(|(existing, _)| existing != name);
+        self.pins.len() != count
     }
 
-    fn set_position(&mut self, pos: char) {
-        self.position = Alphabet::char_to_index(pos);
+    fn get(&self, name: &str) -> Option<&str> {
+        self.pins.iter().find(|(existing, _)| existing == name).map(|(_, row)| row.as_str())
     }
 }
 
-struct Plugboard {
-    swaps: std::collections::HashMap<char, char>,
+// Messages at least this long are encoded off the UI thread (native) or a
+// chunk per frame (web), with a progress bar, so the window stays responsive
+const BULK_THRESHOLD: usize = 100_000;
+const BULK_CHUNK: usize = 20_000;
+
+// What run_machine needs to finish up once a bulk encode comes back
+struct BulkRun {
+    start: EnigmaMachine,
+    text: String,
+    settings: String,
+    decoding: bool,
 }
 
-impl Plugboard {
-    fn new(pairs: &[(char, char)]) -> Self {
-        let mut swaps = std::collections::HashMap::new();
-        for &(a, b) in pairs {
-            swaps.insert(a, b);
-            swaps.insert(b, a);
-        }
-        Self { swaps }
+#[cfg(not(target_arch = "wasm32"))]
+struct BulkJob {
+    run: BulkRun,
+    total: usize,
+    done: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    cancel: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    result: std::sync::mpsc::Receiver<ChunkedEncode>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BulkJob {
+    fn start(run: BulkRun, mut encode: ChunkedEncode, ctx: Option<egui::Context>) -> Self {
+        use std::sync::atomic::Ordering;
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let cancel = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (sender, result) = std::sync::mpsc::channel();
+        let total = encode.len();
+        let (thread_done, thread_cancel) = (done.clone(), cancel.clone());
+        std::thread::spawn(move || {
+            while !encode.run(BULK_CHUNK) {
+                if thread_cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                thread_done.store(encode.done(), Ordering::Relaxed);
+                if let Some(ctx) = &ctx {
+                    ctx.request_repaint();
+                }
+            }
+            let _ = sender.send(encode);
+            if let Some(ctx) = &ctx {
+                ctx.request_repaint();
+            }
+        });
+        Self { run, total, done, cancel, result }
     }
 
-    fn swap(&self, input: char) -> char {
-        *self.swaps.get(&input).unwrap_or(&input)
+    // The finished encode, once the thread has sent it
+    fn poll(&mut self) -> Option<ChunkedEncode> {
+        self.result.try_recv().ok()
     }
-}
 
-struct EnigmaMachine {
-    rotors: Vec<Rotor>,
-    reflector: [char; 26],
-    plugboard: Plugboard,
-}
+    fn progress(&self) -> f32 {
+        self.done.load(std::sync::atomic::Ordering::Relaxed) as f32 / self.total.max(1) as f32
+    }
 
-impl EnigmaMachine {
-    fn new(rotor_configurations: Vec<(&str, char)>, reflector_wiring: &str, plugboard_pairs: &[(char, char)]) -> Self {
-        let rotors = rotor_configurations
-            .into_iter()
-            .map(|(wiring, notch)| Rotor::new(wiring, notch))
-            .collect();
+    fn cancel(&self) {
+        self.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
 
-        let reflector: [char; 26] = reflector_wiring.chars().collect::<Vec<_>>().try_into().unwrap();
-        let plugboard = Plugboard::new(plugboard_pairs);
+// No threads on the web: the encode advances a chunk each frame instead
+#[cfg(target_arch = "wasm32")]
+struct BulkJob {
+    run: BulkRun,
+    encode: ChunkedEncode,
+    ctx: Option<egui::Context>,
+}
 
-        Self { rotors, reflector, plugboard }
+#[cfg(target_arch = "wasm32")]
+impl BulkJob {
+    fn start(run: BulkRun, encode: ChunkedEncode, ctx: Option<egui::Context>) -> Self {
+        Self { run, encode, ctx }
     }
 
-    fn rotate_rotors(&mut self) {
-        let mut rotate_next = true;
-    
-        for i in 0..self.rotors.len() {
-            if i == 0 || rotate_next {
-                rotate_next = self.rotors[i].rotate();
-            }
-    
-            // Double-stepping:
-            // Check if the rotor is the second rotor from the right and it has hit its notch
-            // If so, ensure the next rotor to its left also rotates in the next cycle
-            if i == 1 && self.rotors[i].position == Alphabet::char_to_index(self.rotors[i].notch) {
-                rotate_next = true;
-            }
+    fn poll(&mut self) -> Option<ChunkedEncode> {
+        if self.encode.run(BULK_CHUNK) {
+            return Some(self.encode.clone());
+        }
+        if let Some(ctx) = &self.ctx {
+            ctx.request_repaint();
         }
+        None
     }
 
-    fn encode_decode(&mut self, input: String, output: &mut String) {
-        output.clear();
-
-        for input_char in input.to_uppercase().chars().filter(|c| c.is_ascii_alphabetic()) {
-            let mut encoded_char = self.plugboard.swap(input_char); // Plugboard swap before encoding
+    fn progress(&self) -> f32 {
+        self.encode.done() as f32 / self.encode.len().max(1) as f32
+    }
 
-            // Forward through the rotors
-            for rotor in &mut self.rotors {
-                encoded_char = rotor.encode_forward(encoded_char);
-            }
+    fn cancel(&self) {}
+}
 
-            // Reflector
-            let index = Alphabet::char_to_index(encoded_char);
-            encoded_char = self.reflector[index];
-            encoded_char = Alphabet::index_to_char(Alphabet::char_to_index(encoded_char)); 
+// Plays an encoded message back one letter at a time on a copy of the
+// machine as it was before encoding, so an audience can follow each step
+struct Replay {
+    machine: EnigmaMachine,
+    letters: Vec<char>,
+    index: usize,
+    output: String,
+    paused: bool,
+    // Time of the next letter, in egui's clock
+    next_at: f64,
+}
 
-            // Through the rotors in reverse order
-            for rotor in self.rotors.iter_mut().rev() {
-                encoded_char = rotor.encode_backward(encoded_char);
-            }
+impl Replay {
+    fn new(machine: EnigmaMachine, input: &str) -> Self {
+        Self {
+            machine,
+            letters: sanitize(input).0.chars().collect(),
+            index: 0,
+            output: String::new(),
+            paused: false,
+            next_at: 0.0,
+        }
+    }
 
-            // Rotate rotors
-            self.rotate_rotors();
+    fn finished(&self) -> bool {
+        self.index == self.letters.len()
+    }
 
-            encoded_char = self.plugboard.swap(encoded_char); // Plugboard swap back after decoding
-            output.push(encoded_char);
-        }
+    // Presses the next key; returns its signal path
+    fn tick(&mut self) -> Option<Vec<char>> {
+        let &letter = self.letters.get(self.index)?;
+        let trace = self.machine.trace_char(letter);
+        self.output.push(trace[trace.len() - 1]);
+        self.index += 1;
+        Some(trace)
     }
 }
 
 struct EnigmaApp {
+    ui_mode: UiMode,
     input: String,
     output: String,
+    filter_policy: FilterPolicy,
+    casing: Casing,
+    figure_shift: bool,
+    // Spaces sent as a filler letter, and whether decode turns them back
+    space_convention: SpaceConvention,
+    restore_spaces: bool,
     enigma: EnigmaMachine,
+    variant: Variant,
     rotor_positions_input: String,
+    // Last input text that was successfully applied, to spot pending edits
+    applied_rotor_positions: String,
+    numeric_positions: bool,
+    // Window positions being previewed with the scrubber, machine order
+    scrub_positions: Vec<usize>,
+    // Apply the previewed positions when a slider is let go
+    scrub_commit: bool,
+    // Display and input order of the rotor slots; the machine is unaffected
+    rotor_order: RotorOrder,
+    show_core_letters: bool,
+    jump_keystrokes: usize,
+    // Rotors, in the same order as the machine's, that Randomize Positions skips
+    position_locks: Vec<bool>,
     plugboard_input: String,
+    applied_plugboard: String,
+    // Advice only: flag boards with fewer cables than this
+    stecker_warning: bool,
+    stecker_threshold: usize,
+    notch_inputs: Vec<String>,
+    ground_setting_input: String,
+    message_key_input: String,
+    // None when the reflector is a custom wiring or not an Enigma one
+    reflector: Option<ReflectorId>,
+    reflector_input: String,
+    // Nearest valid wiring to the last rejected custom reflector
+    reflector_suggestion: Option<String>,
+    reflector_bank: ReflectorBank,
+    bank_name_input: String,
+    key_sheet_input: String,
+    // Physically impossible parts of the last applied key, shown but not enforced
+    key_warnings: Vec<String>,
+    pinboard: Pinboard,
+    pin_name_input: String,
     show_help_bool: bool,
+    high_contrast: bool,
+    // Historical guard: keep every ring at A whatever key is applied, for
+    // reference outputs that assume Ringstellung AAA
+    rings_locked: bool,
+    download_error: Option<String>,
+    // Plaintext the output should match, to spot where a decode goes wrong
+    expected_output: String,
+    // Filled by the clipboard read, which completes asynchronously on the web
+    pasted: Rc<RefCell<Option<Result<String, String>>>>,
+    history: VecDeque<HistoryEntry>,
+    // The machine and message of the last encode, for replaying it
+    last_start: Option<(EnigmaMachine, String)>,
+    replay: Option<Replay>,
+    replay_seconds: f32,
+    // Signal path of the most recently enciphered letter
+    last_trace: Option<Vec<char>>,
+    // Presentation timing, persisted across sessions
+    lamp_seconds: f32,
+    step_seconds: f32,
+    // When the lamp for the last trace lit; set on the next frame after encoding
+    lamp_lit_at: Option<f64>,
+    lamp_pending: bool,
+    // Rotor window positions as drawn, trailing the real ones while animating
+    shown_positions: Vec<f32>,
+    // When the lamp test started; it runs through every lamp once
+    lamp_test_started: Option<f64>,
+    // Keyboard test: keys light their own lamp instead of enciphering
+    keyboard_test: bool,
+    keyboard_test_key: Option<char>,
+    // Live typing: keys encipher one at a time into grouped ciphertext
+    live_typing: bool,
+    live_output: LiveGroups,
+    // A keypress being walked through one stage at a time
+    slow_motion: Option<SlowMotion>,
+    slow_key_input: String,
+    // Shared by every randomize button; None until seeded
+    seed: Option<u64>,
+    seed_input: String,
+    rng: Rng,
+    // Group size of the last decoded ciphertext, for display
+    detected_group_size: Option<usize>,
+    // What the filter left out of the last message, for display
+    dropped_summary: Option<String>,
+    // Length of the repeated-letter plaintext and its last flattening run
+    flatten_length: usize,
+    flattening: Option<Flattening>,
+    // The last message decoded with its key, then under each slip
+    wrong_settings: Option<(String, Vec<WrongDecode>)>,
+    // A long message being encoded in the background
+    bulk: Option<BulkJob>,
+    // Kept so a background encode can wake the UI when it finishes
+    ctx: Option<egui::Context>,
 }
 
 impl EnigmaApp {
+    fn with_storage(storage: Option<&dyn eframe::Storage>) -> Self {
+        let mut app = Self::new();
+        if let Some(storage) = storage {
+            app.lamp_seconds = parse_seconds(storage.get_string(LAMP_SECONDS_KEY), DEFAULT_LAMP_SECONDS, LAMP_SECONDS_RANGE);
+            app.step_seconds = parse_seconds(storage.get_string(STEP_SECONDS_KEY), DEFAULT_STEP_SECONDS, STEP_SECONDS_RANGE);
+            app.pinboard = Pinboard::from_storage(&storage.get_string(PINS_KEY).unwrap_or_default());
+            app.ui_mode = UiMode::from_storage(storage.get_string(UI_MODE_KEY));
+        }
+        app
+    }
+
     fn new() -> Self {
         // Initialize the Enigma Machine with a default configuration
-        let enigma = EnigmaMachine::new(
-            vec![
-                ("EKMFLGDQVZNTOWYHXUSPAIBRCJ", 'Q'),
-                ("AJDKSIRUXBLHWTMCQGZNPYFVOE", 'E'),
-                ("BDFHJLCPRTXVZNYEIWGAKMUSQO", 'V'),
-            ],
-            "YRUHQSLDPXNGOKMIEBFZCWVJAT",
-            &[
-                ('A', 'B'), ('C', 'D'), // Default plugboard configuration
-            ],
-        );
+        let enigma = EnigmaMachine::default();
+
+        let notch_inputs = Self::notch_inputs_for(&enigma);
 
         Self {
+            ui_mode: UiMode::Operator,
             input: Default::default(),
             output: Default::default(),
+            filter_policy: FilterPolicy::DropNonLetters,
+            casing: Casing::UpperOnly,
+            figure_shift: false,
+            space_convention: SpaceConvention::None,
+            restore_spaces: true,
             enigma,
+            variant: Variant::EnigmaI,
             rotor_positions_input: String::new(),
+            applied_rotor_positions: String::new(),
+            numeric_positions: false,
+            scrub_positions: Vec::new(),
+            scrub_commit: false,
+            rotor_order: RotorOrder::EntryFirst,
+            show_core_letters: false,
+            jump_keystrokes: 0,
+            position_locks: Vec::new(),
+            stecker_warning: true,
+            stecker_threshold: WEAK_PLUGBOARD_PAIRS,
             plugboard_input: String::new(),
+            applied_plugboard: String::new(),
+            notch_inputs,
+            ground_setting_input: String::new(),
+            message_key_input: String::new(),
+            reflector: Some(ReflectorId::B),
+            reflector_input: String::new(),
+            reflector_suggestion: None,
+            reflector_bank: ReflectorBank::default(),
+            bank_name_input: String::new(),
+            key_sheet_input: String::new(),
+            key_warnings: Vec::new(),
+            pinboard: Pinboard::default(),
+            pin_name_input: String::new(),
             show_help_bool: false,
+            high_contrast: false,
+            rings_locked: false,
+            download_error: None,
+            expected_output: String::new(),
+            pasted: Default::default(),
+            history: VecDeque::new(),
+            last_start: None,
+            replay: None,
+            replay_seconds: 0.6,
+            last_trace: None,
+            lamp_seconds: DEFAULT_LAMP_SECONDS,
+            step_seconds: DEFAULT_STEP_SECONDS,
+            lamp_lit_at: None,
+            lamp_pending: false,
+            shown_positions: Vec::new(),
+            lamp_test_started: None,
+            keyboard_test: false,
+            keyboard_test_key: None,
+            live_typing: false,
+            live_output: LiveGroups::default(),
+            slow_motion: None,
+            slow_key_input: String::new(),
+            seed: None,
+            seed_input: String::new(),
+            rng: Rng::new(0),
+            detected_group_size: None,
+            dropped_summary: None,
+            flatten_length: 1000,
+            flattening: None,
+            wrong_settings: None,
+            bulk: None,
+            ctx: None,
         }
     }
 
-    fn encode(&mut self) {
-        if self.input.chars().all(|c| c.is_ascii_alphabetic() || c == ' ') {
-            self.enigma.encode_decode(self.input.clone(), &mut self.output);
+    fn notch_inputs_for(enigma: &EnigmaMachine) -> Vec<String> {
+        enigma.rotors.iter()
+            .map(|rotor| rotor.notches.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(","))
+            .collect()
+    }
+
+    fn select_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        let calibrations = self.enigma.calibrations();
+        self.enigma = variant.build();
+        self.enigma.set_calibrations(&calibrations);
+        self.key_warnings.clear();
+        self.reflector = match variant {
+            Variant::EnigmaI => Some(ReflectorId::B),
+            Variant::EnigmaK | Variant::Typex => None,
+        };
+        self.notch_inputs = Self::notch_inputs_for(&self.enigma);
+        self.plugboard_input = self.enigma.plugboard.to_string();
+        self.applied_plugboard = self.plugboard_input.clone();
+        self.output = format!("{} selected.", variant.name());
+        self.enforce_ring_lock();
+    }
+
+    fn set_rings_locked(&mut self, locked: bool) {
+        self.rings_locked = locked;
+        if locked {
+            self.enigma.clear_ring_settings();
+            self.output = "Ring settings locked to A.".to_string();
+        }
+    }
+
+    // True if rings had to be reset
+    fn enforce_ring_lock(&mut self) -> bool {
+        let reset = self.rings_locked && self.enigma.rotors.iter().any(|rotor| rotor.ring_setting != 0);
+        if reset {
+            self.enigma.clear_ring_settings();
+        }
+        reset
+    }
+
+    fn apply_key_sheet_row(&mut self, row: &str) {
+        match parse_key_sheet_row(row).and_then(|key| Ok((key.build()?, key))) {
+            Ok((machine, key)) => {
+                self.key_warnings = validate_physical(&key).iter().map(|warning| warning.to_string()).collect();
+                self.variant = Variant::EnigmaI;
+                let calibrations = self.enigma.calibrations();
+                self.enigma = machine;
+                self.enigma.set_calibrations(&calibrations);
+                self.reflector = Reflector::by_name(&key.reflector).map(|reflector| reflector.id);
+                self.notch_inputs = Self::notch_inputs_for(&self.enigma);
+                self.plugboard_input = self.enigma.plugboard.to_string();
+                self.applied_plugboard = self.plugboard_input.clone();
+                self.output = format!("Key applied: Walzenlage {}, Umkehrwalze {}.", key.rotors.join(" "), key.reflector);
+                if self.enforce_ring_lock() {
+                    self.output.push_str(" Ringstellung ignored: rings are locked to A.");
+                }
+            }
+            Err(error) => self.output = error,
+        }
+    }
+
+    // Under the reject policy anything but letters is invalid, though a
+    // blank message is still just empty
+    fn classify_input(text: &str, policy: FilterPolicy) -> InputKind {
+        let (letters, dropped) = sanitize(text);
+        if policy == FilterPolicy::RejectNonLetters && !dropped.is_empty() && !text.trim().is_empty() {
+            InputKind::Invalid
+        } else if !letters.is_empty() {
+            InputKind::Letters
         } else {
-            self.output = "Invalid input: Please enter only alphabetic characters.".to_string();
+            InputKind::Empty
         }
     }
 
-    fn set_rotor_positions_from_string(&mut self, positions: &str) {
-        let positions: Vec<char> = positions.chars()
-            .map(|c| c.to_uppercase().next().unwrap())
-            .collect();
+    // Summarises the settings a message was encoded with, for the history panel
+    fn settings_summary(&self) -> String {
+        let rotors = self.rotor_order.arrange(&self.enigma.rotors);
+        let positions: String = rotors.iter().map(|r| Alphabet::index_to_char(r.position)).collect();
+        let rings: String = rotors.iter().map(|r| Alphabet::index_to_char(r.ring_setting)).collect();
+        format!("Positions {}, Rings {}, Plugboard {}", positions, rings, self.enigma.plugboard)
+    }
 
-        if positions.len() == self.enigma.rotors.len() {
-            for (i, &pos) in positions.iter().enumerate() {
-                if pos.is_ascii_alphabetic() {
-                    self.enigma.rotors[i].set_position(pos);
-                    self.output = format!("Rotor positions set.");
-                } else {
-                    self.output = format!("Invalid input: {} is not an alphabetic character.", pos);
+    // Applies edited but not yet applied position and plugboard fields, so
+    // encoding never silently uses stale settings. False if one was invalid.
+    fn apply_pending_inputs(&mut self) -> bool {
+        if is_dirty(&self.rotor_positions_input, &self.applied_rotor_positions) {
+            let input = self.rotor_positions_input.clone();
+            self.set_rotor_positions_from_string(&input);
+            if is_dirty(&self.rotor_positions_input, &self.applied_rotor_positions) {
+                return false;
+            }
+        }
+        if is_dirty(&self.plugboard_input, &self.applied_plugboard) {
+            self.update_plugboard_from_input();
+            if is_dirty(&self.plugboard_input, &self.applied_plugboard) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn encode(&mut self) {
+        self.run_machine(false);
+    }
+
+    // With figure shift on, plaintext is shifted into letters before
+    // enciphering and deciphered letters are shifted back afterwards
+    fn decode(&mut self) {
+        self.run_machine(true);
+    }
+
+    fn run_machine(&mut self, decoding: bool) {
+        self.detected_group_size = None;
+        self.dropped_summary = None;
+        let text = if self.figure_shift && !decoding {
+            match figure_shift_encode(&self.input) {
+                Ok(letters) => letters,
+                Err(error) => {
+                    self.output = error;
                     return;
                 }
             }
+        } else if decoding
+            && !self.filter_policy.keeps('\n')
+            && !self.filter_policy.keeps(' ')
+            && self.input.chars().all(|c| c.is_ascii_alphabetic() || c.is_whitespace())
+        {
+            // Grouped ciphertext is decoded as one run of letters, unless
+            // the policy keeps the spaces or line breaks
+            let (letters, group_size) = normalize_ciphertext(&self.input);
+            self.detected_group_size = group_size;
+            letters
+        } else if !decoding {
+            // Figure shift has its own code for spaces, so this only applies without it
+            self.space_convention.apply(&self.input)
+        } else {
+            self.input.clone()
+        };
+        match Self::classify_input(&text, self.filter_policy) {
+            InputKind::Empty => {
+                self.output = "Nothing to encode: enter a message first.".to_string();
+                return;
+            }
+            InputKind::Invalid => {
+                self.output = "Invalid input: Please enter only letters, or choose another policy for other characters.".to_string();
+                return;
+            }
+            InputKind::Letters => {}
+        }
+        if !self.apply_pending_inputs() {
+            return;
+        }
+
+        // Characters the policy keeps are not dropped
+        let (_, dropped) = sanitize(&text);
+        let dropped: Vec<(usize, char)> = dropped.into_iter().filter(|&(_, c)| !self.filter_policy.keeps(c)).collect();
+        self.dropped_summary = describe_dropped(&dropped);
+
+        let run = BulkRun {
+            settings: self.settings_summary(),
+            start: self.enigma.clone(),
+            text,
+            decoding,
+        };
+        // Figure shift letters are all upper case, so there is no input case to follow
+        let casing = if self.figure_shift { Casing::UpperOnly } else { self.casing };
+        let mut encode = match ChunkedEncode::new(self.enigma.clone(), &run.text, self.filter_policy, casing) {
+            Ok(encode) => encode,
+            Err(error) => {
+                self.output = error;
+                return;
+            }
+        };
+        if encode.len() >= BULK_THRESHOLD {
+            self.output = format!("Encoding {} characters...", encode.len());
+            self.bulk = Some(BulkJob::start(run, encode, self.ctx.clone()));
         } else {
-            self.output = format!("Invalid input: Expected {} positions, got {}.", self.enigma.rotors.len(), positions.len());
+            encode.run(usize::MAX);
+            self.finish_run(run, encode);
         }
     }
 
-    fn update_plugboard_from_input(&mut self) {
-        if !self.plugboard_input.is_empty() {
-            let pair_strings = self.plugboard_input.split_whitespace().collect::<Vec<&str>>();
-            let mut plugboard_pairs = Vec::new();
-            let mut letter_set = std::collections::HashSet::new();
-            let mut valid_configuration = true;
-            let mut error_message = String::new();
-    
-            for pair_str in pair_strings {
-                // Each pair should be exactly 2 characters long
-                if pair_str.len() == 2 {
-                    let chars: Vec<char> = pair_str.chars().collect();
-                    let pair = (chars[0], chars[1]);
-    
-                    // Check for duplicate or invalid pairs
-                    if pair.0 == pair.1 || letter_set.contains(&pair.0) || letter_set.contains(&pair.1) {
-                        error_message = format!("Invalid plugboard configuration: duplicate letters or invalid pair '{}{}'.", pair.0, pair.1);
-                        valid_configuration = false;
-                        break;
-                    } else {
-                        plugboard_pairs.push(pair);
-                        letter_set.insert(pair.0);
-                        letter_set.insert(pair.1);
-                    }
-                } else {
-                    error_message = format!("Invalid input: Plugboard pairs must be exactly 2 letters. '{}' is invalid.", pair_str);
-                    valid_configuration = false;
-                    break;
-                }
+    fn poll_bulk(&mut self) {
+        if let Some(encode) = self.bulk.as_mut().and_then(BulkJob::poll) {
+            let job = self.bulk.take().expect("just polled");
+            self.finish_run(job.run, encode);
+        }
+    }
+
+    fn cancel_bulk(&mut self) {
+        if let Some(job) = self.bulk.take() {
+            job.cancel();
+            self.output = "Encoding cancelled.".to_string();
+        }
+    }
+
+    fn finish_run(&mut self, run: BulkRun, encode: ChunkedEncode) {
+        let BulkRun { start, text, settings, decoding } = run;
+        self.enigma = encode.machine;
+        self.output = if self.figure_shift && decoding {
+            figure_shift_decode(&encode.output)
+        } else if decoding && self.restore_spaces {
+            self.space_convention.restore(&encode.output)
+        } else {
+            encode.output
+        };
+        self.last_start = Some((start, text.clone()));
+        // Stepping happens before the current flows, so the machine is
+        // still at the positions the last letter was enciphered at
+        self.last_trace = sanitize(&text).0.chars().last().map(|c| self.enigma.trace(c));
+        self.lamp_pending = self.last_trace.is_some();
+
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            input: self.input.clone(),
+            output: self.output.clone(),
+            settings,
+        });
+    }
+
+    fn apply_pasted(&mut self, pasted: Result<String, String>) {
+        match pasted {
+            Ok(text) if Self::classify_input(&text, self.filter_policy) != InputKind::Invalid => {
+                self.input = text;
+                self.encode();
             }
-    
-            if valid_configuration {
-                self.enigma.plugboard = Plugboard::new(&plugboard_pairs);
-                self.output.clear();
-                self.output.push_str("Plugboard set.")
-            } else {
-                // If the configuration is not valid, push the error message to the output
-                self.output.clear(); 
-                self.output.push_str(&error_message);
+            Ok(_) => {
+                self.output = "Pasted text rejected: Please copy only alphabetic characters.".to_string();
             }
+            Err(error) => self.output = error,
         }
-    }     
-}
+    }
 
-impl Default for EnigmaApp {
-    fn default() -> Self {
-        Self::new()
+    fn pin_current(&mut self) {
+        let Some(key) = self.enigma.to_key() else {
+            self.output = "Only Enigma machines built from catalogue rotors and reflectors can be pinned.".to_string();
+            return;
+        };
+        let name = self.pin_name_input.trim().to_string();
+        match self.pinboard.add(&name, key.to_string()) {
+            Ok(()) => self.output = format!("Pinned \"{}\".", name),
+            Err(error) => self.output = error,
+        }
     }
-}
 
-impl eframe::App for EnigmaApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Enigma Machine Simulator");
-            ui.separator();
-            const AVERAGE_CHAR_WIDTH: f32 = 12.0;
-            let text_edit_width = AVERAGE_CHAR_WIDTH * self.enigma.rotors.len() as f32;
+    fn apply_pin(&mut self, name: &str) {
+        if let Some(row) = self.pinboard.get(name).map(str::to_string) {
+            self.apply_key_sheet_row(&row);
+        }
+    }
 
-            // Plugboard input
-            ui.horizontal(|ui| {
-                ui.label("Plugboard Pairs (e.g., AB CD):");
-                ui.add(egui::TextEdit::singleline(&mut self.plugboard_input)
-                    .desired_width(text_edit_width));
-                if ui.button("Set Plugboard").clicked() {
-                    self.update_plugboard_from_input();
-                }    
-            });
+    // The expected text as the current policy would have produced it, so a
+    // pasted "Attack at dawn" lines up with "ATTACKATDAWN"
+    fn normalized_expected_output(&self) -> String {
+        self.expected_output.chars()
+            .filter(|&c| c.is_ascii_alphabetic() || self.filter_policy.keeps(c))
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    }
 
-            ui.add_space(2.5);
+    fn select_reflector(&mut self, id: ReflectorId) {
+        let reflector = Reflector::by_id(id);
+        match reflector.wiring.map(parse_reflector_wiring) {
+            Some(Ok(wiring)) => {
+                let _ = self.enigma.set_reflector(wiring);
+                self.reflector = Some(id);
+                self.output = format!("Reflector {} selected.", reflector.name);
+            }
+            _ => self.output = format!("Reflector {} is rewired by hand: enter its wiring as a custom reflector.", reflector.name),
+        }
+    }
 
-            // Set rotor positions            
-            ui.horizontal(|ui| {
-                ui.label("Set rotor positions (A-Z):");
-                ui.add(egui::TextEdit::singleline(&mut self.rotor_positions_input)
-                    .desired_width(text_edit_width));
-                if ui.button("Set Positions").clicked() {
-                    let input = std::mem::take(&mut self.rotor_positions_input);
-                    self.set_rotor_positions_from_string(&input);
-                    self.rotor_positions_input = input;
-                }    
-            });
+    // Loads the custom wiring field into the bank, or the machine's own
+    // reflector if the field is empty
+    fn load_into_bank(&mut self) {
+        let wiring = if self.reflector_input.trim().is_empty() {
+            self.enigma.reflector_wiring().iter().collect()
+        } else {
+            self.reflector_input.clone()
+        };
+        self.output = match self.reflector_bank.load(&self.bank_name_input, &wiring) {
+            Ok(_) => format!("Reflector {} loaded into the bank.", self.bank_name_input.trim()),
+            Err(error) => error,
+        };
+    }
 
-            ui.add_space(2.5);
+    fn activate_bank_entry(&mut self, index: usize) {
+        match self.reflector_bank.activate(index, &mut self.enigma) {
+            Ok(()) => {
+                let (name, wiring) = &self.reflector_bank.entries()[index];
+                // Still shown by catalog name if it is one of the standard wirings
+                self.reflector = Reflector::all().iter().copied()
+                    .find(|&id| Reflector::by_id(id).wiring.is_some_and(|catalog| catalog.chars().eq(wiring.iter().copied())));
+                self.output = format!("Reflector {} active.", name);
+            }
+            Err(error) => self.output = error,
+        }
+    }
 
-            ui.horizontal(|ui| {
-                ui.label("Current Rotor Positions:");
-                for rotor in &self.enigma.rotors {
-                    ui.label(format!("{}", (rotor.position as u8 + 'A' as u8) as char));
-                }
-            });
+    fn set_reflector_from_input(&mut self) {
+        self.reflector_suggestion = None;
+        let wiring = match parse_reflector_wiring(&self.reflector_input) {
+            Ok(wiring) => wiring,
+            Err(e) => {
+                self.output = e;
+                return;
+            }
+        };
 
-            // Encode/decode message input
-            ui.add(egui::TextEdit::multiline(&mut self.input).hint_text("Enter your message here"));
-            ui.add_space(2.5);
-            ui.horizontal(|ui| {
-                if ui.button("Encode").clicked() {
-                    self.encode();
-                }
-                if ui.button("Decode").clicked() {
-                    self.encode(); // Encoding and decoding are the same operation in the Enigma machine
-                }
-                if ui.button("About").clicked() {
-                    self.show_help_bool = !self.show_help_bool;
-                }    
-            });
+        let diagnosis = diagnose_reflector(&wiring);
+        if diagnosis.is_valid() {
+            // A valid diagnosis means the machine accepts it
+            let _ = self.enigma.set_reflector(wiring);
+            self.reflector = None;
+            self.output = "Reflector wiring set.".to_string();
+        } else {
+            self.output = format!("Invalid reflector wiring:\n{}", diagnosis.problems().join("\n"));
+            self.reflector_suggestion = Some(diagnosis.suggestion.iter().collect());
+        }
+    }
 
-            if self.show_help_bool {
-                // Help window with information
-                egui::Window::new("About the Enigma Machine")
-                    .open(&mut self.show_help_bool) 
-                    .show(ctx, |ui| {
-                        ui.label("The Enigma machine was a cryptographic device used by the German military in World War II for secure communication. 
-                        \nIt uses a combination of rotors, a plugboard, and a reflector to encrypt and decrypt messages. 
-                        \nHere's a brief overview of its components:");
-                        ui.label("\n- Rotors: These are disks with wiring that scrambles the letters. Each rotor can be set to a starting position, affecting the encryption. The historical Enigma machine had three rotors.");
-                        ui.label("\n- Plugboard: A panel used to swap pairs of letters before and after they pass through the rotors.");
-                        ui.label("\n- Reflector: A component that redirects the signal back through the rotors in a different path, ensuring that the machine can both encrypt and decrypt messages using the same settings.");
-                        ui.label("\nHistorically, the rotor arrangement and plugboard configurations were changed daily. Operators would receive codebooks with daily settings.");
-                    });
+    // Letters typed in the listed order, turned into machine order
+    fn parse_positions(&self, input: &str) -> Result<Vec<char>, String> {
+        parse_position_letters(input).map(|letters| self.rotor_order.arrange(&letters))
+    }
+
+    // The window letters in the listed order, for the positions field
+    fn listed_positions(&self) -> String {
+        self.rotor_order.arrange(&self.enigma.rotors).iter().map(|rotor| rotor.window_letter()).collect()
+    }
+
+    // Turns the rotor in listed `slot` until its window shows `letter`, as
+    // read off a real machine; the wiring core sits the ring setting behind
+    fn set_window_letter(&mut self, slot: usize, letter: char) {
+        let i = self.rotor_order.indices(self.enigma.rotors.len())[slot];
+        self.enigma.rotors[i].set_engraved_position(letter);
+        self.rotor_positions_input = self.listed_positions();
+        self.applied_rotor_positions = self.rotor_positions_input.clone();
+    }
+
+    fn set_rotor_order(&mut self, order: RotorOrder) {
+        self.rotor_order = order;
+        self.rotor_positions_input = self.listed_positions();
+        self.applied_rotor_positions = self.rotor_positions_input.clone();
+    }
+
+    fn set_rotor_positions_from_string(&mut self, positions: &str) {
+        match self.parse_positions(positions).and_then(|letters| self.enigma.set_positions(&letters)) {
+            Ok(()) => {
+                self.output = "Rotor positions set.".to_string();
+                self.applied_rotor_positions = positions.to_string();
             }
+            Err(error) => self.output = error,
+        }
+    }
 
-            ui.separator();
-            ui.add_space(10.0);
+    // Sender side of the message key procedure
+    fn encipher_message_key(&mut self) {
+        let result = self.parse_positions(&self.ground_setting_input).and_then(|ground| {
+            let message_key = self.parse_positions(&self.message_key_input)?;
+            self.enigma.encipher_message_key(&ground, &message_key)
+        });
+        match result {
+            Ok(indicator) => {
+                let indicator: String = self.rotor_order.arrange(&indicator.chars().collect::<Vec<_>>()).into_iter().collect();
+                self.output = format!("Indicator to transmit: {}. Rotors set to the message key.", indicator);
+                self.rotor_positions_input = self.message_key_input.clone();
+                self.applied_rotor_positions = self.rotor_positions_input.clone();
+            }
+            Err(error) => self.output = error,
+        }
+    }
 
-            ui.label("Output:");
-            ui.monospace(&self.output);
+    // Receiver side: the message key field holds the received indicator
+    fn recover_message_key(&mut self) {
+        let result = self.parse_positions(&self.ground_setting_input).and_then(|ground| {
+            let indicator = self.parse_positions(&self.message_key_input)?;
+            self.enigma.recover_message_key(&ground, &indicator)
         });
+        match result {
+            Ok(message_key) => {
+                let message_key: String = self.rotor_order.arrange(&message_key).into_iter().collect();
+                self.output = format!("Message key: {}. Rotors set to the message key.", message_key);
+                self.rotor_positions_input = message_key;
+                self.applied_rotor_positions = self.rotor_positions_input.clone();
+            }
+            Err(error) => self.output = error,
+        }
     }
-}
 
-// native app
-#[cfg(not(target_arch = "wasm32"))]
-fn main() {
-    let options = eframe::NativeOptions::default();
-    let _ = eframe::run_native(
-        "Enigma Machine Simulator",
-        options,
-        Box::new(|_cc| Box::new(EnigmaApp::new())),
-    );
-}
+    fn set_rotor_notches_from_string(&mut self, rotor: usize, notches: &str) {
+        match Rotor::parse_notches(notches) {
+            Ok(notches) => {
+                self.enigma.rotors[rotor].notches = notches;
+                let slot = self.rotor_order.indices(self.enigma.rotors.len()).iter().position(|&i| i == rotor).unwrap_or(rotor);
+                self.output = format!("Rotor {} notches set.", slot + 1);
+            }
+            Err(error) => self.output = error,
+        }
+    }
 
-// When compiling to web using trunk:
-#[cfg(target_arch = "wasm32")]
-fn main() {
-    let options = eframe::WebOptions::default();
-    wasm_bindgen_futures::spawn_local(async {
-        eframe::WebRunner::new()
-            .start(
-                "the_canvas_id", // hardcode it
-                options,
-                Box::new(|_cc| Box::new(EnigmaApp::new())),
-            )
-            .await
-            .expect("failed to start eframe");
-    });
+    fn reseed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.seed_input = seed.to_string();
+        self.rng = Rng::new(seed);
+    }
+
+    // Every random setting draws from one generator, so entering the same
+    // seed and repeating the same clicks gives the same settings. Until a
+    // seed is entered, the first draw seeds it from the clock.
+    fn rng(&mut self, clock: f64) -> &mut Rng {
+        if self.seed.is_none() {
+            // Short enough to read off and type back in
+            self.reseed(clock.to_bits() % 1_000_000);
+        }
+        &mut self.rng
+    }
+
+    fn seeded(&self, message: &str) -> String {
+        format!("{} (seed {})", message, self.seed.unwrap_or_default())
+    }
+
+    // Ten pairs, as used by the Wehrmacht from 1939 on
+    fn randomize_plugboard(&mut self, clock: f64) {
+        self.enigma.plugboard = Plugboard::new(&Plugboard::random_pairs(self.rng(clock), 10))
+            .expect("random pairs never share a letter");
+        self.plugboard_input = self.enigma.plugboard.to_string();
+        self.applied_plugboard = self.plugboard_input.clone();
+        self.output = self.seeded("Random plugboard set.");
+    }
+
+    // Locked rotors keep their letter; every unlocked one moves to a
+    // different random letter
+    fn randomize_positions(&mut self, clock: f64) {
+        self.position_locks.resize(self.enigma.rotors.len(), false);
+        for i in 0..self.enigma.rotors.len() {
+            if !self.position_locks[i] {
+                let step = 1 + self.rng(clock).below(25);
+                let rotor = &mut self.enigma.rotors[i];
+                rotor.position = (rotor.position + step) % 26;
+            }
+        }
+        self.rotor_positions_input = self.listed_positions();
+        self.applied_rotor_positions = self.rotor_positions_input.clone();
+        self.output = self.seeded("Random positions set.");
+    }
+
+    // A whole daily key, applied as if it had been typed in as a row
+    fn randomize_daily_key(&mut self, clock: f64) {
+        let key = random_key(self.rng(clock));
+        self.key_sheet_input = key.to_string();
+        self.apply_key_sheet_row(&key.to_string());
+        let applied = std::mem::take(&mut self.output);
+        self.output = self.seeded(&applied);
+    }
+
+    // The input as an operator would have sent it under the current daily
+    // key, with a fresh ground setting and message key. The machine on
+    // screen is left where it is.
+    fn transmission_view(&mut self, clock: f64) {
+        if !self.apply_pending_inputs() {
+            return;
+        }
+        let Some(key) = self.enigma.to_key() else {
+            self.output = "Transmission view needs catalog rotors and reflector.".to_string();
+            return;
+        };
+        let input = self.input.clone();
+        self.output = match Transmission::prepare(&key, &input, self.rng(clock)) {
+            Ok(transmission) if transmission.ciphertext.is_empty() => "Nothing to encode: enter a message first.".to_string(),
+            Ok(transmission) => transmission.render(),
+            Err(error) => error,
+        };
+    }
+
+    // Deciphers a pasted transmission under whichever key its Kenngruppe
+    // names: the machine's own or one of the pinned configurations
+    fn receive_transmission(&mut self) {
+        if !self.apply_pending_inputs() {
+            return;
+        }
+        let mut held: Vec<(String, EnigmaKey)> = self.enigma.to_key()
+            .map(|key| ("the current key".to_string(), key))
+            .into_iter()
+            .collect();
+        held.extend(self.pinboard.pins.iter()
+            .filter_map(|(name, row)| parse_key_sheet_row(row).ok().map(|key| (format!("pinned key \"{}\"", name), key))));
+        let keys: Vec<EnigmaKey> = held.iter().map(|(_, key)| key.clone()).collect();
+
+        let input = self.input.clone();
+        self.output = match receive(&input, &keys) {
+            Ok((index, plaintext)) => {
+                let plaintext = if self.restore_spaces { SpaceConvention::X.restore(&plaintext) } else { plaintext };
+                format!("Kenngruppe matches {}.\n{}", held[index].0, plaintext)
+            }
+            Err(error) => error,
+        };
+    }
+
+    // One key in live typing. Backspace takes the letter off the display
+    // but, as on the machine, cannot turn the rotors back.
+    fn live_key(&mut self, key: egui::Key) {
+        if key == egui::Key::Backspace {
+            self.live_output.pop();
+            return;
+        }
+        let Some(letter) = key.name().chars().next().filter(|c| key.name().len() == 1 && c.is_ascii_alphabetic()) else {
+            return;
+        };
+        let trace = self.enigma.trace_char(letter);
+        self.live_output.push(trace[trace.len() - 1]);
+        self.last_trace = Some(trace);
+        self.lamp_pending = true;
+        self.rotor_positions_input = self.listed_positions();
+        self.applied_rotor_positions = self.rotor_positions_input.clone();
+    }
+
+    // Presses the key for slow motion; the rotors step now, as on the machine
+    fn start_slow_motion(&mut self) {
+        let Some(letter) = position_entry_letter(self.slow_key_input.trim()) else {
+            self.output = "Enter one letter to press.".to_string();
+            return;
+        };
+        if !self.apply_pending_inputs() {
+            return;
+        }
+        self.slow_motion = Some(SlowMotion::new(&mut self.enigma, letter));
+        self.rotor_positions_input = self.listed_positions();
+        self.applied_rotor_positions = self.rotor_positions_input.clone();
+    }
+
+    // Moves the signal on one stage, lighting the lamp at the end
+    fn slow_motion_step(&mut self) {
+        let Some(slow) = &mut self.slow_motion else {
+            return;
+        };
+        if slow.advance().is_some() && slow.finished() {
+            self.last_trace = Some(slow.trace().to_vec());
+            self.lamp_pending = true;
+        }
+    }
+
+    // Runs the last encoded message again under each slip of its key
+    fn compare_wrong_settings(&mut self) {
+        let Some((machine, text)) = &self.last_start else {
+            self.output = "Encode or decode a message first.".to_string();
+            return;
+        };
+        let Some(key) = machine.to_key() else {
+            self.output = "Wrong-settings comparison needs catalog rotors and reflector.".to_string();
+            return;
+        };
+        match perturbed_decodes(&key, text) {
+            Ok(result) => self.wrong_settings = Some(result),
+            Err(error) => self.output = error,
+        }
+    }
+
+    // A run of one letter through a copy of the machine; the count is capped
+    // at the bulk threshold so it finishes within the frame
+    fn run_flattening(&mut self) {
+        let plaintext = "A".repeat(self.flatten_length.min(BULK_THRESHOLD));
+        self.flattening = Some(flattening(&self.enigma, &plaintext));
+    }
+
+    // Moves the machine to the scrubber's positions
+    fn commit_scrub(&mut self) {
+        for (rotor, &position) in self.enigma.rotors.iter_mut().zip(&self.scrub_positions) {
+            rotor.position = position;
+        }
+        self.rotor_positions_input = self.listed_positions();
+        self.applied_rotor_positions = self.rotor_positions_input.clone();
+        self.output = format!("Rotor positions set to {}.", self.rotor_positions_input);
+    }
+
+    // Not an error: a weak board still encodes, this only says so
+    fn weak_plugboard_warning(&self) -> Option<String> {
+        let pairs = plugboard_strength(&self.enigma.plugboard);
+        (self.stecker_warning && pairs < self.stecker_threshold)
+            .then(|| format!("Weak plugboard: {} pair{} set", pairs, if pairs == 1 { "" } else { "s" }))
+    }
+
+    // Each line of the input is a separate message: all go out under the
+    // current daily key, each with its own message key
+    fn batch_encipher(&mut self, clock: f64) {
+        if !self.apply_pending_inputs() {
+            return;
+        }
+        let Some(key) = self.enigma.to_key() else {
+            self.output = "Batch encipher needs catalog rotors and reflector.".to_string();
+            return;
+        };
+        let input = self.input.clone();
+        let messages: Vec<&str> = input.lines().filter(|line| line.chars().any(|c| c.is_ascii_alphabetic())).collect();
+        if messages.is_empty() {
+            self.output = "Nothing to encode: enter one message per line.".to_string();
+            return;
+        }
+        self.output = match prepare_batch(&key, &messages, self.rng(clock)) {
+            Ok(batch) => batch.iter().map(Transmission::render).collect::<Vec<_>>().join("\n"),
+            Err(error) => error,
+        };
+    }
+
+    fn clear_plugboard(&mut self) {
+        self.enigma.plugboard = Plugboard::new(&[]).expect("an empty plugboard is valid");
+        self.plugboard_input.clear();
+        self.applied_plugboard.clear();
+        self.output = "Plugboard cleared.".to_string();
+    }
+
+    fn remove_plug_pair(&mut self, letter: char) {
+        if self.enigma.plugboard.remove_pair(letter) {
+            self.plugboard_input = self.enigma.plugboard.to_string();
+            self.applied_plugboard = self.plugboard_input.clone();
+            self.output = format!("Plug {} removed.", letter);
+        }
+    }
+
+    fn update_plugboard_from_input(&mut self) {
+        if !self.plugboard_input.is_empty() {
+            match Plugboard::parse_pairs(&self.plugboard_input).and_then(|pairs| Plugboard::new(&pairs)) {
+                Ok(plugboard) => {
+                    self.enigma.plugboard = plugboard;
+                    self.applied_plugboard = self.plugboard_input.clone();
+                    self.output.clear();
+                    self.output.push_str("Plugboard set.")
+                }
+                Err(error_message) => {
+                    // If the configuration is not valid, push the error message to the output
+                    self.output.clear(); 
+                    self.output.push_str(&error_message);
+                }
+            }
+        }
+    }     
+}
+
+const LAMP_SECONDS_KEY: &str = "lamp_seconds";
+const STEP_SECONDS_KEY: &str = "step_seconds";
+const PINS_KEY: &str = "pinned_configurations";
+const UI_MODE_KEY: &str = "ui_mode";
+const DEFAULT_LAMP_SECONDS: f32 = 0.8;
+const DEFAULT_STEP_SECONDS: f32 = 0.15;
+const LAMP_SECONDS_RANGE: std::ops::RangeInclusive<f32> = 0.1..=5.0;
+const STEP_SECONDS_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+
+// Reads a stored duration, falling back to the default when it is missing,
+// unparsable or out of range
+fn parse_seconds(stored: Option<String>, default: f32, range: std::ops::RangeInclusive<f32>) -> f32 {
+    stored
+        .and_then(|value| value.parse::<f32>().ok())
+        .filter(|value| range.contains(value))
+        .unwrap_or(default)
+}
+
+// Brightness of a lamp lit `elapsed` seconds ago, fading linearly to zero
+fn lamp_brightness(elapsed: f64, lamp_seconds: f32) -> f32 {
+    (1.0 - elapsed as f32 / lamp_seconds).clamp(0.0, 1.0)
+}
+
+// Lamps in the order of the German lampboard
+const LAMP_ROWS: [&str; 3] = ["QWERTZUIO", "ASDFGHJK", "PYXCVBNML"];
+
+// Seconds each lamp stays lit during the lamp test
+const LAMP_TEST_SECONDS: f64 = 0.15;
+
+// The lamp test lights the board left to right, row by row, so a lamp in
+// the wrong place or a letter missing from LAMP_ROWS shows up at a glance
+fn lamp_test_sequence() -> Vec<char> {
+    LAMP_ROWS.iter().flat_map(|row| row.chars()).collect()
+}
+
+fn draw_lampboard(ui: &mut egui::Ui, lit: Option<(char, f32)>) {
+    const LAMP_SPACING: f32 = 30.0;
+    const LAMP_RADIUS: f32 = 12.0;
+
+    let size = egui::vec2(LAMP_SPACING * 9.0, LAMP_SPACING * 3.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+    let visuals = ui.visuals();
+    let font = egui::FontId::proportional(13.0);
+
+    for (row, letters) in LAMP_ROWS.iter().enumerate() {
+        // Shorter rows are centred under the top one
+        let indent = (9 - letters.len()) as f32 * LAMP_SPACING / 2.0;
+        for (column, letter) in letters.chars().enumerate() {
+            let center = rect.left_top()
+                + egui::vec2(indent + LAMP_SPACING * (column as f32 + 0.5), LAMP_SPACING * (row as f32 + 0.5));
+            let brightness = match lit {
+                Some((lit_letter, brightness)) if lit_letter == letter => brightness,
+                _ => 0.0,
+            };
+            let (off, on) = (visuals.extreme_bg_color, egui::Color32::from_rgb(255, 210, 80));
+            let mix = |a: u8, b: u8| egui::lerp(a as f32..=b as f32, brightness) as u8;
+            let fill = egui::Color32::from_rgb(mix(off.r(), on.r()), mix(off.g(), on.g()), mix(off.b(), on.b()));
+            painter.circle(center, LAMP_RADIUS, fill, visuals.widgets.noninteractive.bg_stroke);
+            let text = if brightness > 0.5 { egui::Color32::BLACK } else { visuals.text_color() };
+            painter.text(center, egui::Align2::CENTER_CENTER, letter, font.clone(), text);
+        }
+    }
+}
+
+// A rotor window showing the letter at `position` with its neighbours above
+// and below, so a fractional position scrolls between letters. The letters
+// are shifted by the rotor's calibration.
+fn draw_rotor_window(ui: &mut egui::Ui, position: f32, calibration: usize) {
+    const ROW_HEIGHT: f32 = 18.0;
+
+    let (response, painter) = ui.allocate_painter(egui::vec2(24.0, ROW_HEIGHT * 3.0), egui::Sense::hover());
+    let rect = response.rect;
+    let visuals = ui.visuals();
+    painter.rect_stroke(rect, 2.0, visuals.widgets.noninteractive.bg_stroke);
+    painter.rect_stroke(
+        egui::Rect::from_center_size(rect.center(), egui::vec2(rect.width(), ROW_HEIGHT)),
+        0.0,
+        egui::Stroke::new(1.0, visuals.text_color()),
+    );
+
+    let painter = painter.with_clip_rect(rect);
+    let base = position.floor();
+    for step in -2..=2 {
+        let letter = Alphabet::index_to_char((base as i32 + step + calibration as i32).rem_euclid(26) as usize);
+        let y = rect.center().y + (base + step as f32 - position) * ROW_HEIGHT;
+        let color = if step == 0 { visuals.strong_text_color() } else { visuals.weak_text_color() };
+        painter.text(egui::pos2(rect.center().x, y), egui::Align2::CENTER_CENTER, letter, egui::FontId::monospace(14.0), color);
+    }
+}
+
+// Letters such as "ABL", or numbers as printed on key sheets, e.g. "06 22 14"
+fn parse_position_letters(input: &str) -> Result<Vec<char>, String> {
+    if input.chars().any(|c| c.is_ascii_digit()) {
+        input.split_whitespace().map(parse_setting_letter).collect()
+    } else {
+        Ok(input.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_ascii_uppercase()).collect())
+    }
+}
+
+// How many positions the field holds, counted the way
+// parse_position_letters splits it, and whether that is one per rotor
+fn position_length(input: &str, rotors: usize) -> (usize, bool) {
+    let count = if input.chars().any(|c| c.is_ascii_digit()) {
+        input.split_whitespace().count()
+    } else {
+        input.chars().filter(|c| !c.is_whitespace()).count()
+    };
+    (count, count == rotors)
+}
+
+// Keys across the top, the lamp each one lights underneath. A lamp matching
+// its own key can only come from a broken reflector, so it is shown in red.
+fn draw_mapping(ui: &mut egui::Ui, id: &str, mapping: &[char; 26]) {
+    egui::Grid::new(id).show(ui, |ui| {
+        ui.label("Key");
+        for &c in Alphabet::LETTERS {
+            ui.monospace(c.to_string());
+        }
+        ui.end_row();
+        ui.label("Lamp");
+        for (&key, &lamp) in Alphabet::LETTERS.iter().zip(mapping) {
+            if lamp == key {
+                ui.colored_label(ui.visuals().error_fg_color, egui::RichText::new(lamp.to_string()).monospace());
+            } else {
+                ui.monospace(lamp.to_string());
+            }
+        }
+        ui.end_row();
+    });
+}
+
+// A bar per letter, scaled to the tallest, with the letter underneath
+fn draw_histogram(ui: &mut egui::Ui, counts: &[usize; 26]) {
+    const BAR_WIDTH: f32 = 10.0;
+    const HEIGHT: f32 = 100.0;
+
+    let (response, painter) = ui.allocate_painter(egui::vec2(BAR_WIDTH * 26.0, HEIGHT + 14.0), egui::Sense::hover());
+    let rect = response.rect;
+    let visuals = ui.visuals();
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    for (i, &count) in counts.iter().enumerate() {
+        let left = rect.left() + i as f32 * BAR_WIDTH;
+        let top = rect.top() + HEIGHT * (1.0 - count as f32 / max);
+        let bar = egui::Rect::from_min_max(egui::pos2(left + 1.0, top), egui::pos2(left + BAR_WIDTH - 1.0, rect.top() + HEIGHT));
+        painter.rect_filled(bar, 0.0, visuals.selection.bg_fill);
+        painter.text(
+            egui::pos2(left + BAR_WIDTH / 2.0, rect.bottom()),
+            egui::Align2::CENTER_BOTTOM,
+            Alphabet::index_to_char(i),
+            egui::FontId::monospace(9.0),
+            visuals.weak_text_color(),
+        );
+    }
+}
+
+// The letter a typed key sets a rotor to, if it is one
+fn position_entry_letter(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+// A focusable box showing one rotor's letter. Typing a letter while it has
+// focus sets the rotor and returns true so focus can move on.
+fn rotor_entry_box(ui: &mut egui::Ui, id: egui::Id, rotor: &mut Rotor) -> bool {
+    const SIZE: egui::Vec2 = egui::vec2(24.0, 24.0);
+
+    let (rect, _) = ui.allocate_exact_size(SIZE, egui::Sense::hover());
+    let response = ui.interact(rect, id, egui::Sense::click());
+    ui.memory_mut(|memory| memory.interested_in_focus(id));
+    if response.clicked() {
+        response.request_focus();
+    }
+
+    let mut entered = false;
+    if response.has_focus() {
+        let typed = ui.input(|input| input.events.iter().find_map(|event| match event {
+            egui::Event::Text(text) => position_entry_letter(text),
+            _ => None,
+        }));
+        if let Some(letter) = typed {
+            rotor.set_engraved_position(letter);
+            entered = true;
+        }
+    }
+
+    let visuals = ui.style().interact_selectable(&response, response.has_focus());
+    ui.painter().rect(rect, 2.0, visuals.bg_fill, visuals.bg_stroke);
+    ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, rotor.engraved_letter(), egui::FontId::monospace(16.0), visuals.text_color());
+    response.widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Button, format!("Rotor position {}", rotor.engraved_letter())));
+    entered
+}
+
+// An input field is dirty when it holds text that differs from what was last
+// applied; an empty field has nothing to apply
+fn is_dirty(input: &str, applied: &str) -> bool {
+    let input = input.trim();
+    !input.is_empty() && input != applied.trim()
+}
+
+// Black background, white text and a blue/orange accent pair that stays
+// distinguishable under the common forms of color blindness
+fn high_contrast_visuals() -> egui::Visuals {
+    let mut visuals = egui::Visuals::dark();
+    let accent = egui::Color32::from_rgb(0, 114, 178);
+    let highlight = egui::Color32::from_rgb(230, 159, 0);
+
+    visuals.override_text_color = Some(egui::Color32::WHITE);
+    visuals.panel_fill = egui::Color32::BLACK;
+    visuals.window_fill = egui::Color32::BLACK;
+    visuals.extreme_bg_color = egui::Color32::BLACK;
+    visuals.window_stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
+    visuals.widgets.noninteractive.fg_stroke = egui::Stroke::new(1.0, egui::Color32::WHITE);
+    visuals.widgets.noninteractive.bg_stroke = egui::Stroke::new(1.0, egui::Color32::WHITE);
+    visuals.widgets.inactive.bg_stroke = egui::Stroke::new(1.0, egui::Color32::WHITE);
+    visuals.widgets.hovered.bg_stroke = egui::Stroke::new(2.0, highlight);
+    visuals.widgets.active.bg_stroke = egui::Stroke::new(2.0, highlight);
+    visuals.selection.bg_fill = accent;
+    visuals.selection.stroke = egui::Stroke::new(1.0, egui::Color32::WHITE);
+    visuals.hyperlink_color = highlight;
+    visuals.warn_fg_color = highlight;
+    visuals.error_fg_color = highlight;
+    visuals
+}
+
+// Draws the 26 entry contacts on the left, the 26 exit contacts on the right and
+// a wire for each, so the wiring at the rotor's current position can be seen
+fn draw_rotor_wiring(ui: &mut egui::Ui, rotor: &Rotor) {
+    const ROW_HEIGHT: f32 = 12.0;
+    const WIDTH: f32 = 120.0;
+    const LABEL_WIDTH: f32 = 12.0;
+
+    let (response, painter) = ui.allocate_painter(egui::vec2(WIDTH, ROW_HEIGHT * 26.0), egui::Sense::hover());
+    let rect = response.rect;
+    let text_color = ui.visuals().text_color();
+    let wire = egui::Stroke::new(1.0, ui.visuals().weak_text_color());
+    let font = egui::FontId::monospace(10.0);
+    let row_y = |i: usize| rect.top() + ROW_HEIGHT * (i as f32 + 0.5);
+
+    for i in 0..26 {
+        let input = Alphabet::index_to_char(i);
+        let output = Alphabet::char_to_index(rotor.encode_forward(input));
+
+        painter.text(egui::pos2(rect.left(), row_y(i)), egui::Align2::LEFT_CENTER, input, font.clone(), text_color);
+        painter.text(egui::pos2(rect.right(), row_y(i)), egui::Align2::RIGHT_CENTER, input, font.clone(), text_color);
+        painter.line_segment(
+            [
+                egui::pos2(rect.left() + LABEL_WIDTH, row_y(i)),
+                egui::pos2(rect.right() - LABEL_WIDTH, row_y(output)),
+            ],
+            wire,
+        );
+    }
+}
+
+// The signal path of one letter, left to right from the key through the
+// plugboard and rotors to the reflector and back to the lamp. The letter
+// columns are the wires between the parts; forward and return paths are
+// highlighted in different colours.
+fn draw_signal_path(ui: &mut egui::Ui, trace: &[char]) {
+    const ROW_HEIGHT: f32 = 11.0;
+    const PART_WIDTH: f32 = 70.0;
+    const HEADER_HEIGHT: f32 = 14.0;
+
+    let rotor_count = (trace.len() - 4) / 2;
+    let parts = rotor_count + 2;
+    let size = egui::vec2(PART_WIDTH * parts as f32 + 10.0, HEADER_HEIGHT + ROW_HEIGHT * 26.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+
+    let visuals = ui.visuals();
+    let font = egui::FontId::monospace(9.0);
+    let forward = egui::Stroke::new(2.0, visuals.hyperlink_color);
+    let back = egui::Stroke::new(2.0, visuals.warn_fg_color);
+    let x = |gap: usize| rect.left() + 5.0 + PART_WIDTH * gap as f32;
+    let y = |c: char| rect.top() + HEADER_HEIGHT + ROW_HEIGHT * (Alphabet::char_to_index(c) as f32 + 0.5);
+
+    for part in 0..parts {
+        let name = match part {
+            0 => "Plugboard".to_string(),
+            p if p == parts - 1 => "Reflector".to_string(),
+            p => format!("Rotor {}", p),
+        };
+        painter.text(egui::pos2((x(part) + x(part + 1)) / 2.0, rect.top()), egui::Align2::CENTER_TOP, name, font.clone(), visuals.text_color());
+    }
+    for gap in 0..parts {
+        for &c in Alphabet::LETTERS {
+            painter.text(egui::pos2(x(gap), y(c)), egui::Align2::CENTER_CENTER, c, font.clone(), visuals.weak_text_color());
+        }
+    }
+
+    // Forward values sit at gaps 0..=rotors+1, the return values at the same
+    // gaps in reverse
+    let last_gap = parts - 1;
+    for gap in 0..last_gap {
+        painter.line_segment([egui::pos2(x(gap), y(trace[gap])), egui::pos2(x(gap + 1), y(trace[gap + 1]))], forward);
+        let (from, to) = (trace[trace.len() - 1 - gap], trace[trace.len() - 2 - gap]);
+        painter.line_segment([egui::pos2(x(gap), y(from)), egui::pos2(x(gap + 1), y(to))], back);
+    }
+    let (into, out) = (trace[last_gap], trace[last_gap + 1]);
+    painter.line_segment([egui::pos2(x(last_gap), y(into)), egui::pos2(x(parts), y(into))], forward);
+    painter.line_segment([egui::pos2(x(parts), y(into)), egui::pos2(x(parts), y(out))], forward);
+    painter.line_segment([egui::pos2(x(parts), y(out)), egui::pos2(x(last_gap), y(out))], back);
+
+    painter.circle_stroke(egui::pos2(x(0), y(trace[0])), 5.0, forward);
+    painter.circle_stroke(egui::pos2(x(0), y(*trace.last().unwrap())), 5.0, back);
+}
+
+impl Default for EnigmaApp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Sections of the central panel that only the Expert layout shows
+impl EnigmaApp {
+    // Machine variant and entry wheel
+    fn variant_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let label = ui.label("Machine:");
+            let mut selected = self.variant;
+            egui::ComboBox::from_id_source("variant")
+                .selected_text(selected.name())
+                .show_ui(ui, |ui| {
+                    for variant in Variant::ALL {
+                        ui.selectable_value(&mut selected, variant, variant.name());
+                    }
+                })
+                .response
+                .labelled_by(label.id);
+            if selected != self.variant {
+                self.select_variant(selected);
+            }
+            let mut commercial = self.enigma.entry_wheel == EnigmaMachine::QWERTZ_ENTRY_WHEEL;
+            if ui.checkbox(&mut commercial, "QWERTZ entry wheel")
+                .on_hover_text("Commercial machines wired the keys to the rotors in keyboard order; military ones straight through.")
+                .changed()
+            {
+                self.enigma.entry_wheel = if commercial {
+                    EnigmaMachine::QWERTZ_ENTRY_WHEEL
+                } else {
+                    EnigmaMachine::STRAIGHT_ENTRY_WHEEL
+                };
+            }
+        });
+    }
+
+    // Presets, the raw key sheet row, the random seed and the ring lock
+    fn key_rows(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        // Preset gallery
+        ui.horizontal(|ui| {
+            let label = ui.label("Preset:");
+            let mut selected = None;
+            egui::ComboBox::from_id_source("preset")
+                .selected_text("Choose a scenario")
+                .show_ui(ui, |ui| {
+                    for (name, row) in PRESETS {
+                        if ui.selectable_label(false, *name).clicked() {
+                            selected = Some(*row);
+                        }
+                    }
+                })
+                .response
+                .labelled_by(label.id);
+            if let Some(row) = selected {
+                self.key_sheet_input = row.to_string();
+                self.apply_key_sheet_row(row);
+            }
+        });
+
+        ui.add_space(2.5);
+
+        // Key sheet row
+        ui.horizontal(|ui| {
+            let label = ui.label("Key sheet row:");
+            ui.add(egui::TextEdit::singleline(&mut self.key_sheet_input)
+                .hint_text("Walzenlage II IV V Ringstellung 06 22 14 Steckerverbindungen AD CN"))
+                .labelled_by(label.id);
+            if ui.button("Apply Key").clicked() {
+                let input = self.key_sheet_input.clone();
+                self.apply_key_sheet_row(&input);
+            }
+            if ui.button("Random Daily Key").clicked() {
+                self.randomize_daily_key(ctx.input(|i| i.time));
+            }
+            if let Some(key) = self.enigma.to_key() {
+                ui.label(format!("Fingerprint: {}", key.fingerprint()))
+                    .on_hover_text(text::FINGERPRINT_HELP);
+            }
+        });
+        ui.horizontal(|ui| {
+            let label = ui.label("Random seed:");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.seed_input)
+                .desired_width(80.0)
+                .hint_text("from clock"))
+                .labelled_by(label.id)
+                .on_hover_text("The random plugboard, positions and daily key all draw from this seed. Enter it again to repeat a demo.");
+            if response.changed() {
+                // Reseed on every valid edit, restarting the sequence
+                match self.seed_input.trim().parse() {
+                    Ok(seed) => {
+                        self.seed = Some(seed);
+                        self.rng = Rng::new(seed);
+                    }
+                    Err(_) if self.seed_input.trim().is_empty() => self.seed = None,
+                    Err(_) => {}
+                }
+            }
+            if !self.seed_input.trim().is_empty() && self.seed_input.trim().parse::<u64>().is_err() {
+                ui.colored_label(ui.visuals().error_fg_color, "Not a whole number");
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut locked = self.rings_locked;
+            if ui.checkbox(&mut locked, "Lock ring settings to A")
+                .on_hover_text("For reproducing setups that did not use the rings: every key applied keeps Ringstellung AAA.")
+                .changed()
+            {
+                self.set_rings_locked(locked);
+            }
+            if self.rings_locked {
+                ui.colored_label(ui.visuals().warn_fg_color, "🔒 Rings locked at A; Ringstellung in key rows is ignored");
+            }
+        });
+    }
+
+    // The plugboard as text, with its buttons
+    fn plugboard_row(&mut self, ui: &mut egui::Ui, ctx: &egui::Context, text_edit_width: f32) {
+        // Plugboard input
+        ui.horizontal(|ui| {
+            let label = ui.label("Plugboard Pairs (e.g., AB CD):");
+            ui.add(egui::TextEdit::singleline(&mut self.plugboard_input)
+                .desired_width(text_edit_width))
+                .labelled_by(label.id)
+                .on_hover_text(text::PLUGBOARD_HELP);
+            if ui.button("Set Plugboard").clicked() {
+                self.update_plugboard_from_input();
+            }    
+            if ui.button("Random Plugboard").clicked() {
+                self.randomize_plugboard(ctx.input(|i| i.time));
+            }
+            if ui.button("Clear Plugboard").clicked() {
+                self.clear_plugboard();
+            }
+            if is_dirty(&self.plugboard_input, &self.applied_plugboard) {
+                ui.colored_label(ui.visuals().warn_fg_color, "Not applied yet");
+            }
+            if self.enigma.bypass_plugboard {
+                ui.colored_label(ui.visuals().warn_fg_color, "Bypassed");
+            }
+        });
+    }
+
+    // Warns when too few cables are plugged in
+    fn stecker_warning_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.stecker_warning, "Warn below");
+            ui.add_enabled(self.stecker_warning, egui::DragValue::new(&mut self.stecker_threshold)
+                .clamp_range(1..=13)
+                .suffix(" pairs"));
+            if let Some(warning) = self.weak_plugboard_warning() {
+                ui.colored_label(ui.visuals().warn_fg_color, warning)
+                    .on_hover_text(text::STECKER_WARNING_HELP);
+            }
+        });
+    }
+
+    // Positions as text, rotor locks, ring calibration, and the ground setting and message key
+    fn position_rows(&mut self, ui: &mut egui::Ui, ctx: &egui::Context, text_edit_width: f32) {
+        // Set rotor positions            
+        ui.horizontal(|ui| {
+            let label = ui.label("Set rotor positions (A-Z or 01-26):");
+            let rotors = self.enigma.rotors.len();
+            ui.add(egui::TextEdit::singleline(&mut self.rotor_positions_input)
+                .desired_width(text_edit_width)
+                .hint_text(format!("{} letters, e.g. {}", rotors, "A".repeat(rotors))))
+                .labelled_by(label.id)
+                .on_hover_text(text::ROTOR_POSITIONS_HELP);
+            // Checked as you type, before Set is clicked
+            let (count, fits) = position_length(&self.rotor_positions_input, rotors);
+            let count_text = format!("{}/{}", count, rotors);
+            if fits || count == 0 {
+                ui.weak(count_text);
+            } else {
+                ui.colored_label(ui.visuals().error_fg_color, count_text)
+                    .on_hover_text(format!("This machine has {} rotors, so it needs {} positions.", rotors, rotors));
+            }
+            if ui.button("Set Positions").clicked() {
+                let input = std::mem::take(&mut self.rotor_positions_input);
+                self.set_rotor_positions_from_string(&input);
+                self.rotor_positions_input = input;
+            }    
+            if is_dirty(&self.rotor_positions_input, &self.applied_rotor_positions) {
+                ui.colored_label(ui.visuals().warn_fg_color, "Not applied yet");
+            }
+        });
+
+        ui.horizontal(|ui| {
+            self.position_locks.resize(self.enigma.rotors.len(), false);
+            for (slot, i) in self.rotor_order.indices(self.enigma.rotors.len()).into_iter().enumerate() {
+                ui.checkbox(&mut self.position_locks[i], format!("Lock rotor {}", slot + 1));
+            }
+            if ui.button("Randomize Positions").clicked() {
+                self.randomize_positions(ctx.input(|i| i.time));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Ring calibration:").on_hover_text(text::CALIBRATION_HELP);
+            let count = self.enigma.rotors.len();
+            for (slot, i) in self.rotor_order.indices(count).into_iter().enumerate() {
+                ui.add(egui::DragValue::new(&mut self.enigma.rotors[i].calibration)
+                    .clamp_range(0..=25)
+                    .prefix(format!("{}: +", slot + 1)));
+            }
+            if ui.button("Reset").clicked() {
+                self.enigma.set_calibrations(&[]);
+            }
+        });
+
+        // Grundstellung and message key, as operators enciphered them
+        ui.horizontal(|ui| {
+            let label = ui.label("Ground setting:");
+            ui.add(egui::TextEdit::singleline(&mut self.ground_setting_input)
+                .desired_width(text_edit_width))
+                .labelled_by(label.id);
+            let label = ui.label("Message key / indicator:");
+            ui.add(egui::TextEdit::singleline(&mut self.message_key_input)
+                .desired_width(text_edit_width))
+                .labelled_by(label.id);
+            if ui.button("Encipher Message Key")
+                .on_hover_text("Sender: enciphers the message key at the ground setting, then sets the rotors to the message key.")
+                .clicked()
+            {
+                self.encipher_message_key();
+            }
+            if ui.button("Recover Message Key")
+                .on_hover_text("Receiver: deciphers the indicator at the ground setting, then sets the rotors to the message key.")
+                .clicked()
+            {
+                self.recover_message_key();
+            }
+        });
+    }
+
+    // Stepping and reflector options, and the reflector bank
+    fn machine_options(&mut self, ui: &mut egui::Ui, text_edit_width: f32) {
+        ui.checkbox(&mut self.enigma.double_stepping, "Double-stepping")
+            .on_hover_text("Debug option, not historical: untick to make the rotors step like an odometer and compare.");
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.enigma.gear_stepping, "Zählwerk gear stepping (A28 / G31)")
+                .on_hover_text("Every wheel carries into the next when it leaves one of its notches, and the reflector turns too when it is rotatable. Set the notch letters under Rotor Notches.");
+            ui.label(format!("Counter: {:04}", self.enigma.key_count % 10_000));
+            if self.enigma.gear_stepping && ui.button("Step Back").clicked() {
+                // Gear stepping is always reversible
+                let _ = self.enigma.step_back();
+            }
+        });
+
+        if let [_, _, _, greek] = self.enigma.rotors.as_mut_slice() {
+            ui.checkbox(&mut greek.steps, "Fourth (Greek) rotor steps")
+                .on_hover_text("Experimental, not historical: the M4's leftmost rotor never stepped.");
+        }
+
+        ui.checkbox(&mut self.enigma.bypass_plugboard, "No plugboard")
+            .on_hover_text("Letters skip the plugboard, for watching the rotors on their own. The pairs are kept for when you switch it back.");
+        ui.checkbox(&mut self.enigma.bypass_reflector, "Bypass reflector (diagnostic)")
+            .on_hover_text("Diagnostic only: sends the signal straight back through the rotors. This disables proper Enigma behavior, so decoding will not work.");
+
+        // Only machines whose reflector could be turned offer the setting
+        if self.variant.rotatable_reflector() {
+            ui.horizontal(|ui| {
+                let label = ui.label("Reflector position:");
+                egui::ComboBox::from_id_source("reflector_position")
+                    .selected_text(Alphabet::index_to_char(self.enigma.reflector_position).to_string())
+                    .show_ui(ui, |ui| {
+                        for (i, &letter) in Alphabet::LETTERS.iter().enumerate() {
+                            ui.selectable_value(&mut self.enigma.reflector_position, i, letter.to_string());
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+            });
+        }
+
+        ui.horizontal(|ui| {
+            let label = ui.label("Reflector:");
+            let mut selected = self.reflector;
+            egui::ComboBox::from_id_source("reflector")
+                .selected_text(selected.map_or("Custom", |id| Reflector::by_id(id).name))
+                .show_ui(ui, |ui| {
+                    for &id in Reflector::all() {
+                        ui.selectable_value(&mut selected, Some(id), Reflector::by_id(id).name);
+                    }
+                })
+                .response
+                .labelled_by(label.id)
+                .on_hover_text(text::REFLECTOR_HELP);
+            if let Some(id) = selected.filter(|&id| Some(id) != self.reflector) {
+                self.select_reflector(id);
+            }
+
+            let label = ui.label("Custom reflector:");
+            ui.add(egui::TextEdit::singleline(&mut self.reflector_input)
+                .hint_text("26 letters, A's partner first")
+                .font(egui::TextStyle::Monospace))
+                .labelled_by(label.id);
+            if ui.button("Set Reflector").clicked() {
+                self.set_reflector_from_input();
+            }
+            if let Some(suggestion) = self.reflector_suggestion.clone() {
+                if ui.button(format!("Use {}", suggestion))
+                    .on_hover_text("Keeps the letters that were already paired and re-pairs the rest.")
+                    .clicked()
+                {
+                    self.reflector_input = suggestion;
+                    self.set_reflector_from_input();
+                }
+            }
+        });
+
+        ui.collapsing("Reflector Bank", |ui| {
+            ui.label("Load several wirings once, then switch between them with one click.");
+            ui.horizontal(|ui| {
+                let label = ui.label("Name:");
+                ui.add(egui::TextEdit::singleline(&mut self.bank_name_input).desired_width(text_edit_width))
+                    .labelled_by(label.id);
+                if ui.button("Load")
+                    .on_hover_text("Loads the custom reflector wiring above, or the current reflector if that is empty.")
+                    .clicked()
+                {
+                    self.load_into_bank();
+                }
+            });
+            let mut activate = None;
+            let mut remove = None;
+            for (i, (name, wiring)) in self.reflector_bank.entries().iter().enumerate() {
+                ui.horizontal(|ui| {
+                    // Picking a reflector elsewhere also changes which entry is live
+                    let active = self.enigma.reflector_wiring() == *wiring;
+                    if ui.add_enabled(!active, egui::Button::new(if active { "Active" } else { "Use" })).clicked() {
+                        activate = Some(i);
+                    }
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.label(name);
+                    ui.monospace(wiring.iter().collect::<String>());
+                });
+            }
+            if let Some(index) = activate {
+                self.activate_bank_entry(index);
+            }
+            if let Some(index) = remove {
+                self.reflector_bank.remove(index);
+            }
+        });
+    }
+
+    // Jumping ahead, mappings, machine state, pins, notches and wiring
+    fn inspection_panels(&mut self, ui: &mut egui::Ui, text_edit_width: f32) {
+        ui.horizontal(|ui| {
+            let label = ui.label("Jump ahead by");
+            ui.add(egui::DragValue::new(&mut self.jump_keystrokes).suffix(" keystrokes"))
+                .labelled_by(label.id);
+            if ui.button("Jump").on_hover_text("Step the rotors as if that many letters had been typed, e.g. to resume a long message midway").clicked() {
+                self.enigma.advance(self.jump_keystrokes);
+                self.output = format!("Advanced {} keystrokes.", self.jump_keystrokes);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            // Always true on a correctly wired machine, which is the weakness Bletchley exploited
+            if self.enigma.has_no_fixed_point() {
+                ui.label("✔ No letter encodes to itself at these positions");
+            } else {
+                ui.colored_label(ui.visuals().error_fg_color, "✘ A letter encodes to itself: the reflector is misconfigured");
+            }
+        });
+
+        ui.collapsing("Current Mapping", |ui| {
+            ui.label("What each key would light right now, before the next keypress steps the rotors.");
+            draw_mapping(ui, "current_mapping", &self.enigma.current_mapping());
+        });
+
+        ui.collapsing("Position Scrubber", |ui| {
+            ui.label("Drag a rotor through its positions to see how the substitution changes. The machine is not touched unless you commit.");
+            let count = self.enigma.rotors.len();
+            if self.scrub_positions.len() != count {
+                self.scrub_positions = self.enigma.rotors.iter().map(|rotor| rotor.position).collect();
+            }
+            let mut released = false;
+            for (slot, i) in self.rotor_order.indices(count).into_iter().enumerate() {
+                let response = ui.add(egui::Slider::new(&mut self.scrub_positions[i], 0..=25)
+                    .custom_formatter(|n, _| Alphabet::index_to_char(n as usize).to_string())
+                    .text(format!("Rotor {}", slot + 1)));
+                released |= response.drag_released();
+            }
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.scrub_commit, "Commit on release");
+                if ui.button("Commit").clicked() {
+                    self.commit_scrub();
+                }
+                if ui.button("Back to Machine").clicked() {
+                    self.scrub_positions.clear();
+                }
+            });
+            if released && self.scrub_commit {
+                self.commit_scrub();
+            }
+            draw_mapping(ui, "scrub_mapping", &self.enigma.mapping_at(&self.scrub_positions));
+        });
+
+        ui.collapsing("Machine State", |ui| {
+            ui.label("A read-only dump of the live machine, for working out why a decode went wrong.");
+            egui::Grid::new("machine_state").striped(true).show(ui, |ui| {
+                for heading in ["Rotor", "Wiring", "Position", "Ring", "Notches"] {
+                    ui.strong(heading);
+                }
+                ui.end_row();
+                for (i, rotor) in self.rotor_order.arrange(&self.enigma.rotors).iter().enumerate() {
+                    ui.label(format!("{} ({})", i + 1, rotor.catalog_name().unwrap_or("custom")));
+                    ui.monospace(rotor.wiring());
+                    ui.monospace(format!("{} ({:02})", rotor.window_letter(), rotor.position + 1));
+                    ui.monospace(format!("{} ({:02})", Alphabet::index_to_char(rotor.ring_setting), rotor.ring_setting + 1));
+                    ui.monospace(rotor.notches.iter().collect::<String>());
+                    ui.end_row();
+                }
+            });
+            ui.horizontal(|ui| {
+                let name = self.reflector.map_or("Custom", |id| Reflector::by_id(id).name);
+                ui.label(format!("Reflector {}:", name));
+                ui.monospace(self.enigma.reflector_wiring().iter().collect::<String>());
+            });
+            ui.horizontal(|ui| {
+                ui.label("Plugboard:");
+                let pairs = self.enigma.plugboard.to_string();
+                ui.monospace(if pairs.is_empty() { "(none)" } else { &pairs });
+            });
+            ui.horizontal(|ui| {
+                ui.label("Current mapping:");
+                ui.monospace(self.enigma.current_mapping().iter().collect::<String>());
+            });
+        });
+
+        ui.collapsing("Pinned Configurations", |ui| {
+            ui.horizontal(|ui| {
+                let label = ui.label("Name:");
+                ui.text_edit_singleline(&mut self.pin_name_input).labelled_by(label.id);
+                if ui.button("Pin Current").clicked() {
+                    self.pin_current();
+                }
+            });
+            let mut apply = None;
+            let mut delete = None;
+            for (name, row) in &self.pinboard.pins {
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").on_hover_text(row).clicked() {
+                        apply = Some(name.clone());
+                    }
+                    if ui.button("Delete").clicked() {
+                        delete = Some(name.clone());
+                    }
+                    ui.label(name);
+                });
+            }
+            if let Some(name) = apply {
+                self.apply_pin(&name);
+            }
+            if let Some(name) = delete {
+                self.pinboard.remove(&name);
+            }
+        });
+
+        ui.collapsing("Rotor Notches", |ui| {
+            for (slot, i) in self.rotor_order.indices(self.enigma.rotors.len()).into_iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let label = ui.label(format!("Rotor {} notch(es) (e.g., Q or Z,M):", slot + 1));
+                    ui.add(egui::TextEdit::singleline(&mut self.notch_inputs[i])
+                        .desired_width(text_edit_width))
+                        .labelled_by(label.id);
+                    if ui.button("Set Notches").clicked() {
+                        let input = self.notch_inputs[i].clone();
+                        self.set_rotor_notches_from_string(i, &input);
+                    }
+                });
+            }
+        });
+
+        ui.collapsing("Rotor Wiring", |ui| {
+            ui.horizontal(|ui| {
+                for (i, rotor) in self.rotor_order.arrange(&self.enigma.rotors).iter().enumerate() {
+                    ui.vertical(|ui| {
+                        ui.label(format!("Rotor {} at {}", i + 1, Alphabet::index_to_char(rotor.position)));
+                        draw_rotor_wiring(ui, rotor);
+                    });
+                }
+            });
+        });
+    }
+
+    // Filtering, casing and spacing of the message, and the transmission tools
+    fn message_options(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let label = ui.label("Non-letters:");
+        egui::ComboBox::from_id_source("filter_policy")
+            .selected_text(self.filter_policy.name())
+            .show_ui(ui, |ui| {
+                for policy in FilterPolicy::ALL {
+                    ui.selectable_value(&mut self.filter_policy, policy, policy.name());
+                }
+            })
+            .response
+            .labelled_by(label.id)
+            .on_hover_text("Drop: leave spaces and digits out. Pass through: copy them unchanged. Reject: refuse the message. Keep line breaks: drop the rest but keep the lines, and optionally the spaces, as typed.");
+        let label = ui.label("Case:");
+        egui::ComboBox::from_id_source("casing")
+            .selected_text(self.casing.name())
+            .show_ui(ui, |ui| {
+                for casing in Casing::ALL {
+                    ui.selectable_value(&mut self.casing, casing, casing.name());
+                }
+            })
+            .response
+            .labelled_by(label.id)
+            .on_hover_text("Match input writes each output letter in the case of the letter typed for it.");
+        ui.checkbox(&mut self.figure_shift, "Figure shift")
+            .on_hover_text("Send digits, spaces and punctuation as letters after a Y, like a teleprinter's figure shift. Decode turns them back.");
+        ui.add_enabled_ui(!self.figure_shift, |ui| {
+            let label = ui.label("Spaces:");
+            egui::ComboBox::from_id_source("space_convention")
+                .selected_text(self.space_convention.name())
+                .show_ui(ui, |ui| {
+                    for convention in SpaceConvention::ALL {
+                        ui.selectable_value(&mut self.space_convention, convention, convention.name());
+                    }
+                })
+                .response
+                .labelled_by(label.id)
+                .on_hover_text("Send each space as a filler letter, as operators did. X was the usual choice.");
+            ui.checkbox(&mut self.restore_spaces, "Restore on decode")
+                .on_hover_text("Turn the filler back into spaces when decoding. A real X in the message becomes a space too.");
+        });
+        if ui.button("Transmission View")
+            .on_hover_text(text::TRANSMISSION_HELP)
+            .clicked()
+        {
+            self.transmission_view(ctx.input(|i| i.time));
+        }
+        if ui.button("Receive Transmission")
+            .on_hover_text("Reads a transmission from the message box, finds the key from its Kenngruppe among the current and pinned keys, and deciphers it.")
+            .clicked()
+        {
+            self.receive_transmission();
+        }
+        if ui.button("Batch Encipher")
+            .on_hover_text("Treats each line as its own message: one daily key, a fresh message key for each.")
+            .clicked()
+        {
+            self.batch_encipher(ctx.input(|i| i.time));
+        }
+    }
+
+    // Signal path, analysis tools and history
+    fn analysis_panels(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Signal Path", |ui| match &self.last_trace {
+            Some(trace) => {
+                ui.label(format!("Last letter: {} lit {}", trace[0], trace[trace.len() - 1]));
+                draw_signal_path(ui, trace);
+            }
+            None => {
+                ui.label("Encode a message to see the path of its last letter.");
+            }
+        });
+
+        ui.collapsing("Output Analysis", |ui| {
+            for (n, title) in [(2, "Top bigrams:"), (3, "Top trigrams:")] {
+                ui.horizontal(|ui| {
+                    ui.label(title);
+                    for (ngram, count) in top_ngrams(&self.output, n, 5) {
+                        ui.monospace(format!("{} ({})", ngram, count));
+                    }
+                });
+            }
+            ui.label(format!(
+                "Dictionary word hits: German {}, English {}",
+                word_hit_score(&self.output, GERMAN_WORDS),
+                word_hit_score(&self.output, ENGLISH_WORDS),
+            )).on_hover_text("Higher scores suggest the output is real plaintext, which helps rank candidate settings");
+        });
+
+        ui.collapsing("Frequency Flattening", |ui| {
+            ui.label("A message of one repeated letter comes out with every letter but that one about equally \
+                often, so letter frequencies give nothing away.");
+            ui.horizontal(|ui| {
+                let label = ui.label("Letters:");
+                ui.add(egui::DragValue::new(&mut self.flatten_length).clamp_range(26..=BULK_THRESHOLD))
+                    .labelled_by(label.id);
+                if ui.button("Encode A's").on_hover_text("Runs on a copy; the machine does not move.").clicked() {
+                    self.run_flattening();
+                }
+            });
+            if let Some(result) = &self.flattening {
+                ui.horizontal(|ui| {
+                    for (title, counts) in [("Plaintext", &result.input), ("Ciphertext", &result.output)] {
+                        ui.vertical(|ui| {
+                            ui.label(format!("{} (IoC {:.4})", title, index_of_coincidence(counts)));
+                            draw_histogram(ui, counts);
+                        });
+                    }
+                });
+            }
+        });
+
+        ui.collapsing("Cycle Structure", |ui| {
+            ui.label("Rejewski's characteristic of the current setting: the cycles of the permutations that link \
+                the 1st and 4th, 2nd and 5th, and 3rd and 6th letters of a doubled message key.");
+            for (name, perm) in ["AD", "BE", "CF"].iter().zip(indicator_permutations(&self.enigma)) {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", name));
+                    ui.monospace(format_cycles(&cycle_structure(&perm)));
+                });
+            }
+        });
+
+        ui.collapsing("Wrong Settings", |ui| {
+            ui.label("The last message run again with the key slightly wrong. One slip is enough to lose almost every letter.");
+            if ui.add_enabled(self.last_start.is_some(), egui::Button::new("Compare")).clicked() {
+                self.compare_wrong_settings();
+            }
+            if let Some((correct, decodes)) = &self.wrong_settings {
+                egui::Grid::new("wrong_settings").striped(true).show(ui, |ui| {
+                    ui.label("Correct key");
+                    ui.monospace(correct);
+                    ui.label("");
+                    ui.end_row();
+                    for decode in decodes {
+                        ui.label(decode.perturbation.name());
+                        ui.monospace(&decode.output);
+                        ui.label(format!("{}/{} letters right", decode.matching, correct.len()));
+                        ui.end_row();
+                    }
+                });
+            }
+        });
+
+        egui::CollapsingHeader::new(format!("History ({})", self.history.len()))
+            .id_source("history")
+            .show(ui, |ui| {
+                if ui.button("Clear History").clicked() {
+                    self.history.clear();
+                }
+                egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                    // Newest first; clicking an entry loads its input back for editing
+                    for entry in self.history.iter().rev() {
+                        let text = format!("[{}] {} -> {}", entry.settings, entry.input, entry.output);
+                        if ui.selectable_label(false, text).on_hover_text("Click to reload this input").clicked() {
+                            self.input = entry.input.clone();
+                        }
+                    }
+                });
+            });
+    }
+}
+
+impl eframe::App for EnigmaApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(LAMP_SECONDS_KEY, self.lamp_seconds.to_string());
+        storage.set_string(STEP_SECONDS_KEY, self.step_seconds.to_string());
+        storage.set_string(PINS_KEY, self.pinboard.to_storage());
+        storage.set_string(UI_MODE_KEY, self.ui_mode.name().to_string());
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let pasted = self.pasted.borrow_mut().take();
+        if let Some(pasted) = pasted {
+            self.apply_pasted(pasted);
+        }
+        let help_was_open = self.show_help_bool;
+        if self.ctx.is_none() {
+            self.ctx = Some(ctx.clone());
+        }
+        self.poll_bulk();
+
+        let now = ctx.input(|i| i.time);
+        if let Some(replay) = self.replay.as_mut().filter(|replay| !replay.paused && !replay.finished()) {
+            if now >= replay.next_at {
+                self.last_trace = replay.tick();
+                self.lamp_pending = true;
+                replay.next_at = now + self.replay_seconds as f64;
+            }
+            ctx.request_repaint_after(std::time::Duration::from_secs_f64((replay.next_at - now).max(0.0)));
+        }
+        if self.lamp_pending {
+            self.lamp_pending = false;
+            self.lamp_lit_at = Some(now);
+        }
+        let lamp = self.lamp_lit_at.zip(self.last_trace.as_ref()).and_then(|(lit_at, trace)| {
+            let brightness = lamp_brightness(now - lit_at, self.lamp_seconds);
+            (brightness > 0.0).then(|| (trace[trace.len() - 1], brightness))
+        });
+        if lamp.is_some() {
+            ctx.request_repaint_after(std::time::Duration::from_millis(16));
+        }
+
+        // The diagnostics take over the lampboard while they run
+        if self.keyboard_test {
+            let pressed = ctx.input(|i| i.events.iter().rev().find_map(|event| match event {
+                egui::Event::Key { key, pressed: true, .. } => key.name().chars().next()
+                    .filter(|c| key.name().len() == 1 && c.is_ascii_alphabetic()),
+                _ => None,
+            }));
+            // Typing into a text field is not a keyboard test
+            if pressed.is_some() && ctx.memory(|memory| memory.focus().is_none()) {
+                self.keyboard_test_key = pressed;
+            }
+        } else if self.live_typing && ctx.memory(|memory| memory.focus().is_none()) {
+            // Every key of the frame, in order, so fast typing loses nothing
+            let keys: Vec<egui::Key> = ctx.input(|i| i.events.iter().filter_map(|event| match event {
+                egui::Event::Key { key, pressed: true, .. } => Some(*key),
+                _ => None,
+            }).collect());
+            for key in keys {
+                self.live_key(key);
+            }
+        }
+        let lamp_test_lamp = self.lamp_test_started.and_then(|started| {
+            let index = ((now - started) / LAMP_TEST_SECONDS) as usize;
+            let next = started + LAMP_TEST_SECONDS * (index + 1) as f64;
+            lamp_test_sequence().get(index).map(|&letter| (letter, next))
+        });
+        if lamp_test_lamp.is_none() {
+            self.lamp_test_started = None;
+        }
+        let lamp = match (lamp_test_lamp, self.keyboard_test_key.filter(|_| self.keyboard_test)) {
+            (Some((letter, next)), _) => {
+                ctx.request_repaint_after(std::time::Duration::from_secs_f64((next - now).max(0.0)));
+                Some((letter, 1.0))
+            }
+            (None, Some(key)) => Some((key, 1.0)),
+            (None, None) => lamp,
+        };
+
+        // Windows roll forward towards the real positions, covering what is
+        // left of the distance in `step_seconds`
+        // During a replay they follow the replayed machine instead
+        let shown_machine = self.replay.as_ref().map_or(&self.enigma, |replay| &replay.machine);
+        if self.shown_positions.len() != shown_machine.rotors.len() {
+            self.shown_positions = shown_machine.rotors.iter().map(|rotor| rotor.position as f32).collect();
+        }
+        let dt = ctx.input(|i| i.stable_dt);
+        for (shown, rotor) in self.shown_positions.iter_mut().zip(&shown_machine.rotors) {
+            let remaining = (rotor.position as f32 - *shown).rem_euclid(26.0);
+            if remaining < 0.01 || self.step_seconds == 0.0 {
+                *shown = rotor.position as f32;
+            } else {
+                *shown = (*shown + remaining * (dt / self.step_seconds).min(1.0)).rem_euclid(26.0);
+                ctx.request_repaint_after(std::time::Duration::from_millis(16));
+            }
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Enigma Machine Simulator");
+                ui.add_space(20.0);
+                for mode in UiMode::ALL {
+                    ui.selectable_value(&mut self.ui_mode, mode, mode.name())
+                        .on_hover_text(text::UI_MODE_HELP);
+                }
+            });
+            ui.separator();
+            let expert = self.ui_mode == UiMode::Expert;
+            const AVERAGE_CHAR_WIDTH: f32 = 12.0;
+            let text_edit_width = AVERAGE_CHAR_WIDTH * self.enigma.rotors.len() as f32;
+
+            if expert {
+                self.variant_row(ui);
+            }
+
+            ui.add_space(2.5);
+
+            if expert {
+                self.key_rows(ui, ctx);
+            }
+            for warning in &self.key_warnings {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}", warning));
+            }
+
+            ui.add_space(2.5);
+
+            if expert {
+                self.plugboard_row(ui, ctx, text_edit_width);
+            }
+            // The cables currently plugged in, each with its own remove button
+            let mut removed = None;
+            ui.horizontal_wrapped(|ui| {
+                for (a, b) in self.enigma.plugboard.pairs() {
+                    ui.monospace(format!("{}{}", a, b));
+                    if ui.small_button("x").on_hover_text(format!("Remove {}{}", a, b)).clicked() {
+                        removed = Some(a);
+                    }
+                }
+            });
+            if let Some(letter) = removed {
+                self.remove_plug_pair(letter);
+            }
+            if expert {
+                self.stecker_warning_row(ui);
+            }
+
+            ui.add_space(2.5);
+
+            if expert {
+                self.position_rows(ui, ctx, text_edit_width);
+            }
+
+            ui.add_space(2.5);
+
+            if expert {
+                self.machine_options(ui, text_edit_width);
+            }
+
+            ui.add_space(2.5);
+
+            ui.horizontal(|ui| {
+                ui.label("Current Rotor Positions:").on_hover_text(text::ROTOR_POSITIONS_HELP);
+                for (i, rotor) in self.rotor_order.arrange(&self.enigma.rotors).iter().enumerate() {
+                    let letter = rotor.engraved_letter();
+                    let mut text = if self.numeric_positions {
+                        format!("{:02}", Alphabet::char_to_number(letter))
+                    } else {
+                        letter.to_string()
+                    };
+                    if expert && self.show_core_letters {
+                        text = format!("{} (core {})", text, rotor.core_letter());
+                    }
+                    ui.label(&text)
+                        .widget_info(|| egui::WidgetInfo::labeled(
+                            egui::WidgetType::Label,
+                            format!("Rotor {} position {}", i + 1, text),
+                        ));
+                }
+                ui.checkbox(&mut self.numeric_positions, "Show as numbers (01-26)");
+                let mut order = self.rotor_order;
+                egui::ComboBox::from_id_source("rotor_order")
+                    .selected_text(order.name())
+                    .show_ui(ui, |ui| {
+                        for option in RotorOrder::ALL {
+                            ui.selectable_value(&mut order, option, option.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text(text::ROTOR_ORDER_HELP);
+                if order != self.rotor_order {
+                    self.set_rotor_order(order);
+                }
+                if expert {
+                    ui.checkbox(&mut self.show_core_letters, "Show wiring core")
+                        .on_hover_text("The window shows the letter on the ring; with a ring setting the wiring core underneath is turned that many letters back.");
+                }
+            });
+
+            // Click a box and type the letters, like dialling a combination
+            ui.horizontal(|ui| {
+                ui.label("Type positions:");
+                let count = self.enigma.rotors.len();
+                for (slot, i) in self.rotor_order.indices(count).into_iter().enumerate() {
+                    let id = egui::Id::new(("rotor_entry", slot));
+                    if rotor_entry_box(ui, id, &mut self.enigma.rotors[i]) {
+                        ui.memory_mut(|memory| {
+                            if slot + 1 < count {
+                                memory.request_focus(egui::Id::new(("rotor_entry", slot + 1)));
+                            } else {
+                                memory.surrender_focus(id);
+                            }
+                        });
+                        self.rotor_positions_input = self.listed_positions();
+                        self.applied_rotor_positions = self.rotor_positions_input.clone();
+                    }
+                }
+            });
+
+            // For reading letters off a photo of a surviving machine
+            ui.horizontal(|ui| {
+                ui.label("Read from window:").on_hover_text(text::WINDOW_READING_HELP);
+                let count = self.enigma.rotors.len();
+                let mut chosen = None;
+                for (slot, i) in self.rotor_order.indices(count).into_iter().enumerate() {
+                    let rotor = &self.enigma.rotors[i];
+                    let shown = rotor.engraved_letter();
+                    egui::ComboBox::from_id_source(("window_reading", slot))
+                        .selected_text(shown.to_string())
+                        .width(40.0)
+                        .show_ui(ui, |ui| {
+                            for &letter in Alphabet::LETTERS {
+                                if ui.selectable_label(letter == shown, letter.to_string()).clicked() {
+                                    chosen = Some((slot, letter));
+                                }
+                            }
+                        });
+                    if expert {
+                        ui.weak(format!("core {}", rotor.core_letter()));
+                    }
+                }
+                if let Some((slot, letter)) = chosen {
+                    self.set_window_letter(slot, letter);
+                }
+            });
+
+            if expert {
+                self.inspection_panels(ui, text_edit_width);
+            }
+
+            // Encode/decode message input
+            ui.add(egui::TextEdit::multiline(&mut self.input).hint_text("Enter your message here"))
+                .widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::TextEdit, "Message input"));
+            ui.add_space(2.5);
+            ui.horizontal(|ui| {
+                // Nothing to do until the message has at least one letter,
+                // or with figure shift on, anything at all
+                let has_letters = (!sanitize(&self.input).0.is_empty()
+                    || (self.figure_shift && !self.input.trim().is_empty()))
+                    && self.bulk.is_none();
+                if ui.add_enabled(has_letters, egui::Button::new("Encode")).clicked() {
+                    self.encode();
+                }
+                if ui.add_enabled(has_letters, egui::Button::new("Decode")).clicked() {
+                    self.decode(); // The same operation as encoding, apart from figure shift
+                }
+                if expert {
+                    self.message_options(ui, ctx);
+                }
+                if ui.button("Paste & Encode").clicked() {
+                    read_clipboard(ctx, &self.pasted);
+                }
+                if ui.button("About").clicked() {
+                    self.show_help_bool = !self.show_help_bool;
+                }    
+                if ui.checkbox(&mut self.high_contrast, "High contrast").changed() {
+                    if self.high_contrast {
+                        ctx.set_visuals(high_contrast_visuals());
+                    } else {
+                        ctx.set_visuals(egui::Visuals::default());
+                    }
+                }
+            });
+
+            if self.show_help_bool {
+                // Help window with information
+                let about = egui::Window::new(text::ABOUT_TITLE)
+                    .open(&mut self.show_help_bool)
+                    .show(ctx, |ui| {
+                        ui.label(text::ABOUT_INTRO);
+                        for component in text::ABOUT_COMPONENTS {
+                            ui.label(format!("\n- {}", component));
+                        }
+                        ui.label(format!("\n{}", text::ABOUT_HISTORY));
+                        ui.separator();
+                        ui.label(format!("{} {}", text::ABOUT_VERSION, env!("CARGO_PKG_VERSION")));
+                        ui.label(text::ABOUT_CREDITS);
+                        ui.collapsing(text::ABOUT_LEARN_MORE, |ui| {
+                            for (name, url) in text::ABOUT_LINKS {
+                                ui.hyperlink_to(*name, *url);
+                            }
+                        });
+                        ui.small(text::ABOUT_DISMISS);
+                    });
+                // The click that opened the window is not a click outside it
+                let clicked_outside = help_was_open && about.is_some_and(|about| ctx.input(|i| {
+                    i.pointer.any_click()
+                        && i.pointer.interact_pos().is_some_and(|pos| !about.response.rect.contains(pos))
+                }));
+                if clicked_outside || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    self.show_help_bool = false;
+                }
+            }
+
+            ui.separator();
+            ui.add_space(10.0);
+
+            ui.horizontal(|ui| {
+                ui.label("Output:").on_hover_text(text::OUTPUT_HELP);
+                if let Some(job) = &self.bulk {
+                    ui.add(egui::ProgressBar::new(job.progress()).show_percentage().desired_width(150.0));
+                    if ui.button("Cancel").clicked() {
+                        self.cancel_bulk();
+                    }
+                }
+                if let Some(summary) = &self.dropped_summary {
+                    ui.weak(summary);
+                }
+                if let Some(size) = self.detected_group_size {
+                    ui.weak(format!("(decoded from groups of {})", size));
+                }
+                if ui.add_enabled(!self.output.is_empty(), egui::Button::new("Download")).clicked() {
+                    self.download_error = save_output(&self.output).err();
+                }
+                if let Some(error) = &self.download_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+            let expected = self.normalized_expected_output();
+            let mismatches = if expected.is_empty() {
+                Vec::new()
+            } else {
+                mismatch_indices(&self.output.to_ascii_uppercase(), &expected)
+            };
+            if mismatches.is_empty() {
+                ui.monospace(&self.output)
+                    .on_hover_text(text::OUTPUT_HELP)
+                    .widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Label, "Output"));
+            } else {
+                // Differing letters in red; a missing tail shows as red underscores
+                let mut job = egui::text::LayoutJob::default();
+                let font = egui::TextStyle::Monospace.resolve(ui.style());
+                let length = self.output.chars().count().max(expected.chars().count());
+                let mut chars = self.output.chars();
+                for i in 0..length {
+                    let c = chars.next().unwrap_or('_');
+                    let color = if mismatches.contains(&i) { ui.visuals().error_fg_color } else { ui.visuals().text_color() };
+                    job.append(&c.to_string(), 0.0, egui::TextFormat::simple(font.clone(), color));
+                }
+                ui.label(job)
+                    .widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Label, "Output"));
+                ui.colored_label(ui.visuals().error_fg_color, format!("{} characters differ from the expected text.", mismatches.len()));
+            }
+            if expert {
+                ui.horizontal(|ui| {
+                    let label = ui.label("Expected:");
+                    ui.add(egui::TextEdit::singleline(&mut self.expected_output)
+                        .hint_text("Paste the plaintext you expect to compare"))
+                        .labelled_by(label.id);
+                });
+            }
+
+            ui.add_space(10.0);
+
+            egui::CollapsingHeader::new("Lampboard").default_open(!expert).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    // Leftmost rotor first, as the windows sit on the machine
+                    for (&position, rotor) in self.shown_positions.iter().zip(&self.enigma.rotors).rev() {
+                        draw_rotor_window(ui, position, rotor.calibration);
+                    }
+                });
+                draw_lampboard(ui, lamp);
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(self.lamp_test_started.is_none(), egui::Button::new("Lamp Test"))
+                        .on_hover_text("Lights every lamp in turn, row by row, to check the board.")
+                        .clicked()
+                    {
+                        self.lamp_test_started = Some(ui.input(|i| i.time));
+                    }
+                    ui.checkbox(&mut self.keyboard_test, "Keyboard test")
+                        .on_hover_text("Click outside the text fields and type: each key lights its own lamp.");
+                    ui.add_enabled(!self.keyboard_test, egui::Checkbox::new(&mut self.live_typing, "Live typing"))
+                        .on_hover_text("Click outside the text fields and type: each key is enciphered as you press it. Backspace removes a letter but the rotors stay where they are.");
+                    if self.keyboard_test {
+                        match self.keyboard_test_key {
+                            Some(key) => ui.label(format!("Key pressed: {}", key)),
+                            None => ui.label("Press a letter key."),
+                        };
+                    }
+                });
+                if self.live_typing && !self.keyboard_test {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Typed ({} letters):", self.live_output.letters()));
+                        if ui.add_enabled(self.live_output.letters() > 0, egui::Button::new("Clear")).clicked() {
+                            self.live_output.clear();
+                        }
+                    });
+                    ui.monospace(self.live_output.as_str());
+                }
+                ui.add(egui::Slider::new(&mut self.lamp_seconds, LAMP_SECONDS_RANGE).text("Lamp lit (seconds)"));
+                ui.add(egui::Slider::new(&mut self.step_seconds, STEP_SECONDS_RANGE).text("Rotor step animation (seconds)"));
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(self.last_start.is_some(), egui::Button::new("Replay"))
+                        .on_hover_text("Plays the last encoded message back one letter at a time.")
+                        .clicked()
+                    {
+                        if let Some((machine, input)) = &self.last_start {
+                            self.replay = Some(Replay::new(machine.clone(), input));
+                        }
+                    }
+                    if let Some(replay) = &mut self.replay {
+                        let label = if replay.paused { "Resume" } else { "Pause" };
+                        if ui.add_enabled(!replay.finished(), egui::Button::new(label)).clicked() {
+                            replay.paused = !replay.paused;
+                        }
+                    }
+                    if self.replay.is_some() && ui.button("Stop").clicked() {
+                        self.replay = None;
+                    }
+                });
+                ui.add(egui::Slider::new(&mut self.replay_seconds, 0.1..=3.0).text("Replay speed (seconds per letter)"));
+                if let Some(replay) = &self.replay {
+                    let progress = replay.index as f32 / replay.letters.len().max(1) as f32;
+                    ui.add(egui::ProgressBar::new(progress).text(format!("{} / {}", replay.index, replay.letters.len())));
+                    ui.monospace(&replay.output);
+                }
+            });
+
+            ui.collapsing("Slow Motion", |ui| {
+                ui.label("Press one key, then follow its signal through the machine one part at a time.");
+                ui.horizontal(|ui| {
+                    let label = ui.label("Key:");
+                    ui.add(egui::TextEdit::singleline(&mut self.slow_key_input).desired_width(20.0).char_limit(1))
+                        .labelled_by(label.id);
+                    if ui.button("Press Key").on_hover_text("The rotors step as the key goes down.").clicked() {
+                        self.start_slow_motion();
+                    }
+                    let can_step = self.slow_motion.as_ref().is_some_and(|slow| !slow.finished());
+                    if ui.add_enabled(can_step, egui::Button::new("Next Step")).clicked() {
+                        self.slow_motion_step();
+                    }
+                });
+                if let Some(slow) = &self.slow_motion {
+                    for (stage, explanation) in slow.explanations().iter().enumerate() {
+                        ui.label(format!("{}. {}", stage + 1, explanation));
+                    }
+                    match slow.lamp() {
+                        Some(lamp) => ui.strong(format!("Lamp {} is lit.", lamp)),
+                        None => ui.weak(format!("The signal is at {}. {} of {} steps done.", slow.letter(), slow.cursor(), slow.stages())),
+                    };
+                }
+            });
+
+            if expert {
+                self.analysis_panels(ui);
+            }
+        });
+    }
+}
+
+// Times encode_decode over a few message sizes, keeping the best of several
+// runs so the numbers are stable enough to compare between builds
+#[cfg(not(target_arch = "wasm32"))]
+fn run_benchmark() {
+    const SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+    const RUNS: usize = 5;
+
+    let sample = "THEQUICKBROWNFOXJUMPSOVERTHELAZYDOG";
+    let mut output = String::new();
+
+    println!("{:>10}  {:>12}  {:>14}", "chars", "best (ms)", "chars/sec");
+    for size in SIZES {
+        let message: String = sample.chars().cycle().take(size).collect();
+        let mut best = std::time::Duration::MAX;
+
+        for _ in 0..RUNS {
+            let mut machine = EnigmaMachine::default();
+            let start = std::time::Instant::now();
+            machine.encode_decode(message.clone(), &mut output);
+            best = best.min(start.elapsed());
+        }
+
+        println!(
+            "{:>10}  {:>12.3}  {:>14.0}",
+            size,
+            best.as_secs_f64() * 1000.0,
+            size as f64 / best.as_secs_f64(),
+        );
+    }
+}
+
+// native app
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    if std::env::args().any(|arg| arg == "--bench") {
+        run_benchmark();
+        return;
+    }
+
+    // `--key-sheet [days] [seed]` prints a codebook page of random daily keys
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "--key-sheet") {
+        match parse_key_sheet_args(&args[1..]) {
+            Ok((days, seed)) => {
+                let mut rng = Rng::new(seed);
+                let keys: Vec<_> = (0..days).map(|_| random_key(&mut rng)).collect();
+                print!("{}", enigma_machine::key_sheet::render_key_sheet(&keys));
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `--gen-vectors [--check <file>]` prints or verifies the golden vectors
+    if args.first().is_some_and(|arg| arg == "--gen-vectors") {
+        use enigma_machine::cli::{check_vectors, generate_vectors, USAGE, VECTOR_COUNT, VECTOR_SEED};
+        let result = generate_vectors(VECTOR_SEED, VECTOR_COUNT).and_then(|vectors| match &args[1..] {
+            [] => {
+                print!("{}", vectors);
+                Ok(())
+            }
+            [flag, path] if flag == "--check" => {
+                let golden = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+                let count = check_vectors(&golden, &vectors)?;
+                println!("All {} vectors match {}.", count, path);
+                Ok(())
+            }
+            _ => Err(USAGE.to_string()),
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // `--repl [settings]` enciphers lines typed at a prompt
+    if args.first().is_some_and(|arg| arg == "--repl") {
+        let result = enigma_machine::cli::parse_repl_args(&args[1..])
+            .and_then(enigma_machine::cli::Repl::new)
+            .and_then(|mut repl| {
+                println!("{}", enigma_machine::cli::REPL_HELP);
+                enigma_machine::cli::run_repl(&mut repl, std::io::stdin().lock(), std::io::stdout())
+            });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if !args.is_empty() {
+        match enigma_machine::cli::parse_args(&args).and_then(|job| enigma_machine::cli::run(&job)) {
+            Ok(count) => println!("Encoded {} letters.", count),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let options = eframe::NativeOptions::default();
+    let _ = eframe::run_native(
+        "Enigma Machine Simulator",
+        options,
+        Box::new(|cc| Box::new(EnigmaApp::with_storage(cc.storage))),
+    );
+}
+
+// Days default to a month; without a seed each run draws new keys
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_key_sheet_args(args: &[String]) -> Result<(usize, u64), String> {
+    const USAGE: &str = "Usage: enigma_machine --key-sheet [days] [seed]";
+    let days = match args.first() {
+        Some(days) => days.parse().map_err(|_| format!("Invalid number of days '{}'.\n{}", days, USAGE))?,
+        None => 31,
+    };
+    let seed = match args.get(1) {
+        Some(seed) => seed.parse().map_err(|_| format!("Invalid seed '{}'.\n{}", seed, USAGE))?,
+        None => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64),
+    };
+    Ok((days, seed))
+}
+
+// Native save goes through the OS file dialog; cancelling it is not an error
+#[cfg(not(target_arch = "wasm32"))]
+fn save_output(text: &str) -> Result<(), String> {
+    let path = rfd::FileDialog::new()
+        .set_file_name("enigma_output.txt")
+        .add_filter("Text", &["txt"])
+        .save_file();
+
+    match path {
+        Some(path) => std::fs::write(&path, text)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e)),
+        None => Ok(()),
+    }
+}
+
+// The browser has no file system access, so hand the text to a temporary
+// anchor element with a download attribute and let the browser save it
+#[cfg(target_arch = "wasm32")]
+fn save_output(text: &str) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+
+    let parts = js_sys::Array::of1(&wasm_bindgen::JsValue::from_str(text));
+    let blob = web_sys::Blob::new_with_str_sequence(&parts)
+        .map_err(|_| "Could not create the download.".to_string())?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|_| "Could not create the download.".to_string())?;
+
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| "No document to download from.".to_string())?;
+    let anchor: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .ok()
+        .and_then(|element| element.dyn_into().ok())
+        .ok_or_else(|| "Could not create the download.".to_string())?;
+
+    anchor.set_href(&url);
+    anchor.set_download("enigma_output.txt");
+    anchor.click();
+
+    let _ = web_sys::Url::revoke_object_url(&url);
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_clipboard(_ctx: &egui::Context, slot: &Rc<RefCell<Option<Result<String, String>>>>) {
+    let text = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|e| format!("Could not read the clipboard: {}", e));
+    *slot.borrow_mut() = Some(text);
+}
+
+// The async clipboard API is looked up dynamically since web-sys only exposes
+// it behind an unstable cfg flag
+#[cfg(target_arch = "wasm32")]
+fn read_clipboard(ctx: &egui::Context, slot: &Rc<RefCell<Option<Result<String, String>>>>) {
+    use wasm_bindgen::JsCast;
+
+    async fn read_text() -> Option<String> {
+        let navigator = web_sys::window()?.navigator();
+        let clipboard = js_sys::Reflect::get(&navigator, &"clipboard".into()).ok()?;
+        let read_text: js_sys::Function = js_sys::Reflect::get(&clipboard, &"readText".into()).ok()?.dyn_into().ok()?;
+        let promise: js_sys::Promise = read_text.call0(&clipboard).ok()?.dyn_into().ok()?;
+        wasm_bindgen_futures::JsFuture::from(promise).await.ok()?.as_string()
+    }
+
+    let ctx = ctx.clone();
+    let slot = slot.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let text = read_text().await
+            .ok_or_else(|| "Could not read the clipboard: permission denied or unsupported browser.".to_string());
+        *slot.borrow_mut() = Some(text);
+        ctx.request_repaint();
+    });
+}
+
+// When compiling to web using trunk:
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    let options = eframe::WebOptions::default();
+    wasm_bindgen_futures::spawn_local(async {
+        eframe::WebRunner::new()
+            .start(
+                "the_canvas_id", // hardcode it
+                options,
+                Box::new(|cc| Box::new(EnigmaApp::with_storage(cc.storage))),
+            )
+            .await
+            .expect("failed to start eframe");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_pending_input() {
+        assert!(!is_dirty("", ""));
+        assert!(!is_dirty("  ", "ABC"));
+        assert!(is_dirty("ABC", ""));
+        assert!(is_dirty("ABD", "ABC"));
+        assert!(!is_dirty("ABC ", "ABC"));
+    }
+
+    #[test]
+    fn position_length_follows_the_rotor_count() {
+        assert_eq!(position_length("QEV", 3), (3, true));
+        assert_eq!(position_length("Q E V", 3), (3, true));
+        assert_eq!(position_length("17 05 22", 3), (3, true));
+        assert_eq!(position_length("QEV", 4), (3, false));
+        assert_eq!(position_length("BQEV", 4), (4, true));
+        assert_eq!(position_length("02 17 05 22", 4), (4, true));
+        assert_eq!(position_length("02 17 05 22", 3), (4, false));
+        assert_eq!(position_length("", 3), (0, false));
+
+        let mut app = EnigmaApp::new();
+        let (_, row) = PRESETS.iter().find(|(name, _)| name.starts_with("U-264")).unwrap();
+        app.apply_key_sheet_row(row);
+        assert_eq!(app.enigma.rotors.len(), 4);
+        assert!(position_length("VJNA", app.enigma.rotors.len()).1);
+    }
+
+    #[test]
+    fn encode_applies_pending_positions_first() {
+        let mut app = EnigmaApp::new();
+        app.rotor_positions_input = "QEV".to_string();
+        app.input = "A".to_string();
+        app.encode();
+
+        assert!(!is_dirty(&app.rotor_positions_input, &app.applied_rotor_positions));
+        assert_eq!(app.history.back().unwrap().settings, "Positions QEV, Rings AAA, Plugboard AB CD");
+    }
+
+    #[test]
+    fn both_rotor_orders_set_the_same_machine() {
+        let mut entry_first = EnigmaApp::new();
+        entry_first.rotor_positions_input = "QEV".to_string();
+        entry_first.apply_pending_inputs();
+
+        let mut reflector_first = EnigmaApp::new();
+        reflector_first.set_rotor_order(RotorOrder::ReflectorFirst);
+        reflector_first.rotor_positions_input = "VEQ".to_string();
+        reflector_first.apply_pending_inputs();
+
+        let positions = |app: &EnigmaApp| app.enigma.rotors.iter().map(|rotor| rotor.position).collect::<Vec<_>>();
+        assert_eq!(positions(&entry_first), positions(&reflector_first));
+        assert_eq!(entry_first.enigma.current_mapping(), reflector_first.enigma.current_mapping());
+
+        // Switching order relists the same machine rather than changing it
+        entry_first.set_rotor_order(RotorOrder::ReflectorFirst);
+        assert_eq!(entry_first.rotor_positions_input, "VEQ");
+        assert_eq!(positions(&entry_first), positions(&reflector_first));
+        assert_eq!(entry_first.settings_summary(), reflector_first.settings_summary());
+    }
+
+    #[test]
+    fn ring_lock_resets_rings_and_matches_the_no_ring_reference() {
+        let row = "Walzenlage I II III Ringstellung 06 22 14 Steckerverbindungen AB CD";
+        let mut reference = parse_key_sheet_row(row).unwrap().build().unwrap();
+        reference.clear_ring_settings();
+        let mut expected = String::new();
+        reference.encode_decode("ATTACKATDAWN".to_string(), &mut expected);
+
+        let mut app = EnigmaApp::new();
+        app.apply_key_sheet_row(row);
+        assert!(app.enigma.rotors.iter().any(|rotor| rotor.ring_setting != 0));
+        app.set_rings_locked(true);
+        assert!(app.enigma.rotors.iter().all(|rotor| rotor.ring_setting == 0));
+
+        // Applying the row again keeps the lock
+        app.apply_key_sheet_row(row);
+        assert!(app.output.ends_with("Ringstellung ignored: rings are locked to A."));
+        app.input = "Attack at dawn".to_string();
+        app.encode();
+        assert_eq!(app.output, expected);
+    }
+
+    #[test]
+    fn plug_removal_syncs_the_text_field() {
+        let mut app = EnigmaApp::new();
+        app.plugboard_input = "AM FI NV".to_string();
+        app.update_plugboard_from_input();
+        app.remove_plug_pair('F');
+        assert_eq!(app.plugboard_input, "AM NV");
+        assert!(!is_dirty(&app.plugboard_input, &app.applied_plugboard));
+
+        app.clear_plugboard();
+        assert_eq!(app.plugboard_input, "");
+        assert!(app.enigma.plugboard.pairs().is_empty());
+    }
+
+    #[test]
+    fn lamp_test_lights_each_lamp_once_in_board_order() {
+        let sequence = lamp_test_sequence();
+        assert_eq!(sequence.len(), 26);
+        assert_eq!(sequence.iter().collect::<String>(), "QWERTZUIOASDFGHJKPYXCVBNML");
+        let mut sorted = sequence.clone();
+        sorted.sort();
+        assert_eq!(sorted, Alphabet::LETTERS.to_vec());
+    }
+
+    #[test]
+    fn decode_runs_grouped_ciphertext_together() {
+        let mut app = EnigmaApp::new();
+        app.filter_policy = FilterPolicy::RejectNonLetters;
+        app.input = "Attackatdawn".to_string();
+        app.encode();
+        let cipher = app.output.clone();
+
+        let mut app = EnigmaApp::new();
+        app.filter_policy = FilterPolicy::RejectNonLetters;
+        app.input = format!("{} {}\n{}", &cipher[..5], &cipher[5..10], &cipher[10..]);
+        app.decode();
+        assert_eq!(app.output, "ATTACKATDAWN");
+        assert_eq!(app.detected_group_size, Some(5));
+    }
+
+    #[test]
+    fn decode_keeps_line_breaks_the_policy_keeps() {
+        let mut app = EnigmaApp::new();
+        app.filter_policy = FilterPolicy::KeepLineBreaks;
+        app.input = "Attack\nat dawn".to_string();
+        app.encode();
+        let cipher = app.output.clone();
+        assert_eq!(cipher.lines().count(), 2);
+
+        let mut app = EnigmaApp::new();
+        app.filter_policy = FilterPolicy::KeepLineBreaks;
+        app.input = cipher;
+        app.decode();
+        assert_eq!(app.output, "ATTACK\nATDAWN");
+        assert_eq!(app.detected_group_size, None);
+    }
+
+    #[test]
+    fn same_seed_draws_the_same_daily_key() {
+        let draw = |seed| {
+            let mut app = EnigmaApp::new();
+            app.reseed(seed);
+            app.randomize_daily_key(0.0);
+            app.randomize_plugboard(0.0);
+            assert!(app.output.ends_with(&format!("(seed {})", seed)));
+            (app.key_sheet_input, app.plugboard_input)
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42).0, draw(43).0);
+
+        // Unseeded, the first draw takes its seed from the clock and shows it
+        let mut app = EnigmaApp::new();
+        app.randomize_plugboard(123.0);
+        assert_eq!(app.seed_input, (123.0f64.to_bits() % 1_000_000).to_string());
+    }
+
+    #[test]
+    fn bulk_encode_matches_the_synchronous_path() {
+        let message = "Attack at dawn, hold the bridge until relieved. ".repeat(BULK_THRESHOLD / 40);
+        let mut expected = String::new();
+        EnigmaMachine::default().encode_decode(message.clone(), &mut expected);
+
+        let mut app = EnigmaApp::new();
+        app.input = message;
+        app.encode();
+        assert!(app.bulk.is_some());
+        while app.bulk.is_some() {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            app.poll_bulk();
+        }
+        assert_eq!(app.output, expected);
+        assert_eq!(app.history.len(), 1);
+
+        // A cancelled encode leaves the machine where it was
+        app.input = app.history[0].input.clone();
+        let before = app.enigma.rotors.iter().map(|rotor| rotor.position).collect::<Vec<_>>();
+        app.encode();
+        app.cancel_bulk();
+        assert_eq!(app.output, "Encoding cancelled.");
+        assert_eq!(app.enigma.rotors.iter().map(|rotor| rotor.position).collect::<Vec<_>>(), before);
+    }
+
+    #[test]
+    fn encode_stops_on_invalid_pending_input() {
+        let mut app = EnigmaApp::new();
+        app.plugboard_input = "AA".to_string();
+        app.input = "HELLO".to_string();
+        app.encode();
+
+        assert!(app.output.starts_with("Invalid plugboard configuration"));
+        assert!(app.history.is_empty());
+    }
+
+    #[test]
+    fn figure_shift_round_trips_digits() {
+        let mut app = EnigmaApp::new();
+        app.figure_shift = true;
+        app.input = "Convoy 42 at 0600".to_string();
+        app.encode();
+        assert!(app.output.chars().all(|c| c.is_ascii_uppercase()));
+
+        let mut receiver = EnigmaApp::new();
+        receiver.figure_shift = true;
+        receiver.input = app.output.clone();
+        receiver.decode();
+        assert_eq!(receiver.output, "CONVOY 42 AT 0600");
+    }
+
+    #[test]
+    fn window_reading_sets_the_ring_letter_not_the_core() {
+        let mut app = EnigmaApp::new();
+        app.apply_key_sheet_row("Walzenlage II IV V Ringstellung 02 21 12 Umkehrwalze B Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX");
+        app.set_rotor_order(RotorOrder::ReflectorFirst);
+
+        // Rotor V on the right has ring L: window C puts the core 11 letters back, at R
+        app.set_window_letter(2, 'C');
+        assert_eq!(app.enigma.rotors[0].window_letter(), 'C');
+        assert_eq!(app.enigma.rotors[0].core_letter(), 'R');
+        assert_eq!(app.listed_positions(), "AAC");
+
+        // A unit engraved one letter on shows D where a standard ring shows C
+        app.enigma.rotors[0].calibration = 1;
+        app.set_window_letter(2, 'D');
+        assert_eq!(app.enigma.rotors[0].window_letter(), 'C');
+        assert_eq!(app.enigma.rotors[0].core_letter(), 'R');
+    }
+
+    #[test]
+    fn layout_starts_in_operator_mode() {
+        assert_eq!(EnigmaApp::new().ui_mode, UiMode::Operator);
+        assert_eq!(UiMode::from_storage(None), UiMode::Operator);
+        assert_eq!(UiMode::from_storage(Some("Wizard".to_string())), UiMode::Operator);
+        assert_eq!(UiMode::from_storage(Some(UiMode::Expert.name().to_string())), UiMode::Expert);
+    }
+
+    #[test]
+    fn calibration_survives_a_new_key() {
+        let mut app = EnigmaApp::new();
+        app.enigma.set_calibrations(&[1, 0, 2]);
+        app.apply_key_sheet_row("Walzenlage II IV V Ringstellung 02 21 12 Umkehrwalze B Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX");
+        assert_eq!(app.enigma.calibrations(), vec![1, 0, 2]);
+        assert_eq!(app.enigma.rotors[0].engraved_letter(), 'B');
+        assert_eq!(app.listed_positions(), "AAA");
+    }
+
+    #[test]
+    fn encode_reports_dropped_characters() {
+        let mut app = EnigmaApp::new();
+        app.input = "No. 5, go".to_string();
+        app.encode();
+        assert_eq!(app.dropped_summary.as_deref(), Some("Dropped 5 non-letters at positions 3, 4, 5, 6, 7"));
+
+        app.input = "Clean".to_string();
+        app.encode();
+        assert_eq!(app.dropped_summary, None);
+
+        // ß enciphers as SS and é is dropped, and the report agrees with
+        // the letters that came out
+        let mut app = EnigmaApp::new();
+        app.input = "Straße café".to_string();
+        app.encode();
+        assert_eq!(app.dropped_summary.as_deref(), Some("Dropped 2 non-letters at positions 7, 11"));
+        assert_eq!(app.output.len(), "STRASSECAF".len());
+        assert_eq!(app.output, EnigmaMachine::default().encode_with_policy("STRASSECAF", FilterPolicy::DropNonLetters).unwrap());
+    }
+
+    #[test]
+    fn space_as_x_survives_the_round_trip() {
+        let mut app = EnigmaApp::new();
+        app.space_convention = SpaceConvention::X;
+        app.input = "MEET AT X".to_string();
+        app.encode();
+
+        let mut expected = String::new();
+        EnigmaMachine::default().encode_decode("MEETXATXX".to_string(), &mut expected);
+        assert_eq!(app.output, expected);
+
+        let mut receiver = EnigmaApp::new();
+        receiver.space_convention = SpaceConvention::X;
+        receiver.input = app.output.clone();
+        receiver.decode();
+        // The real X cannot be told apart from a filler
+        assert_eq!(receiver.output, "MEET AT  ");
+
+        let mut receiver = EnigmaApp::new();
+        receiver.space_convention = SpaceConvention::X;
+        receiver.restore_spaces = false;
+        receiver.input = app.output.clone();
+        receiver.decode();
+        assert_eq!(receiver.output, "MEETXATXX");
+    }
+
+    #[test]
+    fn stecker_warning_follows_the_threshold() {
+        let mut app = EnigmaApp::new();
+        assert_eq!(app.weak_plugboard_warning().as_deref(), Some("Weak plugboard: 2 pairs set"));
+        app.stecker_threshold = 2;
+        assert_eq!(app.weak_plugboard_warning(), None);
+        app.stecker_threshold = WEAK_PLUGBOARD_PAIRS;
+        app.randomize_plugboard(0.0);
+        assert_eq!(app.weak_plugboard_warning(), None);
+        app.clear_plugboard();
+        app.stecker_warning = false;
+        assert_eq!(app.weak_plugboard_warning(), None);
+    }
+
+    #[test]
+    fn live_typing_groups_and_backspace_keeps_the_rotors() {
+        let mut app = EnigmaApp::new();
+        let mut reference = app.enigma.clone();
+        for key in [egui::Key::H, egui::Key::E, egui::Key::L, egui::Key::L, egui::Key::O, egui::Key::W, egui::Key::Backspace, egui::Key::Num1] {
+            app.live_key(key);
+        }
+        let mut expected = String::new();
+        reference.encode_decode("HELLOW".to_string(), &mut expected);
+        assert_eq!(app.live_output.as_str(), &expected[..5]);
+
+        // W was enciphered, so the next letter carries on from there
+        app.live_key(egui::Key::O);
+        app.live_key(egui::Key::R);
+        let mut rest = String::new();
+        reference.encode_decode("OR".to_string(), &mut rest);
+        assert_eq!(app.live_output.as_str(), format!("{} {}", &expected[..5], rest));
+        assert_eq!(app.rotor_positions_input, app.listed_positions());
+    }
+
+    #[test]
+    fn bank_switches_reflectors_without_retyping() {
+        let mut app = EnigmaApp::new();
+        app.bank_name_input = "Standard".to_string();
+        app.load_into_bank();
+        app.bank_name_input = "Field".to_string();
+        app.reflector_input = "FVPJIAOYEDRZXWGCTKUQSBNMHL".to_string();
+        app.load_into_bank();
+        assert_eq!(app.reflector_bank.entries().len(), 2);
+
+        app.activate_bank_entry(1);
+        assert_eq!(app.output, "Reflector Field active.");
+        assert_eq!(app.reflector, Some(ReflectorId::C));
+        app.activate_bank_entry(0);
+        assert_eq!(app.reflector, Some(ReflectorId::B));
+    }
+
+    #[test]
+    fn wrong_settings_start_from_the_last_message() {
+        let mut app = EnigmaApp::new();
+        app.compare_wrong_settings();
+        assert!(app.wrong_settings.is_none());
+
+        app.input = "Attack at dawn".to_string();
+        app.encode();
+        let ciphertext = app.output.clone();
+        app.compare_wrong_settings();
+        let (correct, decodes) = app.wrong_settings.as_ref().unwrap();
+        assert_eq!(correct, &ciphertext);
+        assert_eq!(decodes.len(), enigma_machine::analysis::Perturbation::ALL.len());
+        assert!(decodes.iter().all(|decode| &decode.output != correct && decode.matching < correct.len()));
+    }
+
+    #[test]
+    fn batch_encipher_sends_one_transmission_per_line() {
+        let mut app = EnigmaApp::new();
+        app.reseed(3);
+        app.input = "Meet at the bridge\n\nWeather report follows\n".to_string();
+        app.batch_encipher(0.0);
+        let transmissions: Vec<&str> = app.output.split("\n\n").collect();
+        assert_eq!(transmissions.len(), 2, "{}", app.output);
+        assert!(transmissions[0].starts_with("18 = "));
+        assert!(transmissions[1].starts_with("22 = "));
+    }
+
+    #[test]
+    fn scrubber_previews_without_moving_the_machine() {
+        let mut app = EnigmaApp::new();
+        app.scrub_positions = vec![16, 4, 21];
+        let preview = app.enigma.mapping_at(&app.scrub_positions);
+        assert_eq!(app.enigma.rotors.iter().map(|rotor| rotor.position).collect::<Vec<_>>(), [0, 0, 0]);
+
+        let mut set = app.enigma.clone();
+        set.set_positions(&['Q', 'E', 'V']).unwrap();
+        assert_eq!(preview, set.current_mapping());
+
+        app.commit_scrub();
+        assert_eq!(app.enigma.current_mapping(), preview);
+        assert_eq!(app.rotor_positions_input, "QEV");
+    }
+
+    #[test]
+    fn transmission_view_leaves_the_machine_alone() {
+        let mut app = EnigmaApp::new();
+        app.reseed(1940);
+        app.input = "Meet at the bridge".to_string();
+        let before = app.enigma.rotors.iter().map(|rotor| rotor.position).collect::<Vec<_>>();
+        app.transmission_view(0.0);
+
+        let header = app.output.lines().next().unwrap();
+        assert!(header.starts_with("18 = "), "{}", app.output);
+        assert_eq!(header.split('=').nth(2).unwrap().trim().len(), 5);
+        assert_eq!(app.output.lines().nth(1).unwrap().split(' ').count(), 4);
+        assert_eq!(app.enigma.rotors.iter().map(|rotor| rotor.position).collect::<Vec<_>>(), before);
+        assert!(app.history.is_empty());
+    }
+
+    #[test]
+    fn slow_motion_lights_the_lamp_on_the_last_step() {
+        let mut app = EnigmaApp::new();
+        app.slow_key_input = "k".to_string();
+        app.start_slow_motion();
+        assert_eq!(app.listed_positions(), "BAA");
+
+        let stages = app.slow_motion.as_ref().unwrap().stages();
+        for _ in 1..stages {
+            app.slow_motion_step();
+        }
+        assert!(app.last_trace.is_none());
+        app.slow_motion_step();
+        let lamp = EnigmaMachine::default().encode_char('K');
+        assert_eq!(app.slow_motion.as_ref().unwrap().lamp(), Some(lamp));
+        assert_eq!(app.last_trace.as_ref().unwrap().last(), Some(&lamp));
+
+        app.slow_key_input = "?".to_string();
+        app.start_slow_motion();
+        assert_eq!(app.output, "Enter one letter to press.");
+    }
+
+    #[test]
+    fn receiving_finds_the_pinned_key_by_kenngruppe() {
+        let mut sender = EnigmaApp::new();
+        sender.reseed(1941);
+        sender.apply_key_sheet_row("Walzenlage II IV V Ringstellung 02 21 12 Umkehrwalze B Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX");
+        sender.input = "Meet at the bridge".to_string();
+        sender.transmission_view(0.0);
+
+        let mut receiver = EnigmaApp::new();
+        receiver.input = sender.output.clone();
+        receiver.receive_transmission();
+        assert!(receiver.output.starts_with("Kenngruppe"), "{}", receiver.output);
+        assert!(receiver.output.contains("does not match"));
+
+        receiver.pinboard.add("Day 17", sender.enigma.to_key().unwrap().to_string()).unwrap();
+        receiver.receive_transmission();
+        assert_eq!(receiver.output, "Kenngruppe matches pinned key \"Day 17\".\nMEET AT THE BRIDGE");
+        // Receiving uses copies of the keys
+        assert_eq!(receiver.enigma.to_key(), EnigmaApp::new().enigma.to_key());
+    }
+
+    #[test]
+    fn stored_seconds_fall_back_to_default() {
+        assert_eq!(parse_seconds(Some("1.5".to_string()), DEFAULT_LAMP_SECONDS, LAMP_SECONDS_RANGE), 1.5);
+        assert_eq!(parse_seconds(None, DEFAULT_LAMP_SECONDS, LAMP_SECONDS_RANGE), DEFAULT_LAMP_SECONDS);
+        assert_eq!(parse_seconds(Some("soon".to_string()), DEFAULT_LAMP_SECONDS, LAMP_SECONDS_RANGE), DEFAULT_LAMP_SECONDS);
+        assert_eq!(parse_seconds(Some("60".to_string()), DEFAULT_STEP_SECONDS, STEP_SECONDS_RANGE), DEFAULT_STEP_SECONDS);
+    }
+
+    #[test]
+    fn lamp_fades_out_over_its_duration() {
+        assert_eq!(lamp_brightness(0.0, 1.0), 1.0);
+        assert_eq!(lamp_brightness(0.5, 1.0), 0.5);
+        assert_eq!(lamp_brightness(2.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn empty_input_is_not_invalid() {
+        let reject = FilterPolicy::RejectNonLetters;
+        assert_eq!(EnigmaApp::classify_input("", reject), InputKind::Empty);
+        assert_eq!(EnigmaApp::classify_input("   ", reject), InputKind::Empty);
+        assert_eq!(EnigmaApp::classify_input("123", reject), InputKind::Invalid);
+        assert_eq!(EnigmaApp::classify_input("HELLO 1", reject), InputKind::Invalid);
+        assert_eq!(EnigmaApp::classify_input("Hello", reject), InputKind::Letters);
+
+        // Dropping non-letters leaves nothing to encode in "123"
+        let drop = FilterPolicy::DropNonLetters;
+        assert_eq!(EnigmaApp::classify_input("123", drop), InputKind::Empty);
+        assert_eq!(EnigmaApp::classify_input("Hello world 1", drop), InputKind::Letters);
+
+        let mut app = EnigmaApp::new();
+        app.encode();
+        assert_eq!(app.output, "Nothing to encode: enter a message first.");
+        assert!(app.history.is_empty());
+    }
+
+    #[test]
+    fn pinboard_adds_replaces_and_removes() {
+        let mut pinboard = Pinboard::default();
+        pinboard.add("Daily", "Walzenlage I II III Ringstellung 01 01 01 Steckerverbindungen AB".to_string()).unwrap();
+        pinboard.add("Navy", PRESETS[2].1.to_string()).unwrap();
+        pinboard.add(" Daily ", PRESETS[1].1.to_string()).unwrap();
+        assert!(pinboard.add("", String::new()).is_err());
+
+        assert_eq!(pinboard.pins.len(), 2);
+        assert_eq!(pinboard.get("Daily"), Some(PRESETS[1].1));
+
+        let restored = Pinboard::from_storage(&pinboard.to_storage());
+        assert_eq!(restored.pins, pinboard.pins);
+
+        assert!(pinboard.remove("Navy"));
+        assert!(!pinboard.remove("Navy"));
+        assert_eq!(pinboard.get("Navy"), None);
+    }
+
+    #[test]
+    fn reflector_position_goes_with_the_variant() {
+        let mut app = EnigmaApp::new();
+        app.select_variant(Variant::EnigmaK);
+        assert!(app.enigma.rotatable_reflector);
+        app.enigma.reflector_position = 7;
+
+        app.select_variant(Variant::Typex);
+        assert!(!app.enigma.rotatable_reflector);
+        assert_eq!(app.enigma.reflector_position, 0);
+    }
+
+    #[test]
+    fn applying_a_pin_restores_its_settings() {
+        let mut app = EnigmaApp::new();
+        app.enigma.set_positions(&['X', 'Y', 'Z']).unwrap();
+        app.pin_name_input = "Evening".to_string();
+        app.pin_current();
+
+        app.select_variant(Variant::EnigmaI);
+        app.apply_pin("Evening");
+        let positions: String = app.enigma.rotors.iter().map(|rotor| rotor.window_letter()).collect();
+        assert_eq!(positions, "XYZ");
+        assert_eq!(app.enigma.plugboard.to_string(), "AB CD");
+    }
+
+    #[test]
+    fn randomizing_positions_keeps_locked_rotors() {
+        let mut app = EnigmaApp::new();
+        app.enigma.set_positions(&['K', 'D', 'R']).unwrap();
+        app.position_locks = vec![false, true, false];
+
+        app.reseed(7);
+        for _ in 0..50 {
+            let before: Vec<char> = app.enigma.rotors.iter().map(|rotor| rotor.window_letter()).collect();
+            app.randomize_positions(0.0);
+            let after: Vec<char> = app.enigma.rotors.iter().map(|rotor| rotor.window_letter()).collect();
+
+            assert_ne!(after[0], before[0]);
+            assert_eq!(after[1], 'D');
+            assert_ne!(after[2], before[2]);
+            assert_eq!(app.rotor_positions_input, after.iter().collect::<String>());
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_the_encoded_message() {
+        let mut app = EnigmaApp::new();
+        app.input = "Attack at dawn".to_string();
+        app.encode();
+
+        let (machine, input) = app.last_start.clone().unwrap();
+        let mut replay = Replay::new(machine, &input);
+        let mut lamps = String::new();
+        while let Some(trace) = replay.tick() {
+            lamps.push(trace[trace.len() - 1]);
+        }
+
+        assert!(replay.finished());
+        assert_eq!(replay.output, app.output);
+        assert_eq!(lamps, app.output);
+        assert_eq!(replay.machine.rotors[0].position, app.enigma.rotors[0].position);
+    }
+
+    #[test]
+    fn only_single_letters_set_a_rotor() {
+        assert_eq!(position_entry_letter("q"), Some('Q'));
+        assert_eq!(position_entry_letter("Z"), Some('Z'));
+        assert_eq!(position_entry_letter("7"), None);
+        assert_eq!(position_entry_letter("ab"), None);
+        assert_eq!(position_entry_letter("ä"), None);
+        assert_eq!(position_entry_letter(""), None);
+    }
 }
@@ -0,0 +1,74 @@
+// User-facing explanations, kept in one table so they can be reviewed, and
+// translated, without hunting through the layout code
+
+pub const PLUGBOARD_HELP: &str = "The plugboard (Steckerbrett) swapped pairs of letters with cables before \
+and after the rotors. Up to ten cables were used, and it added far more keys than the rotors did.";
+
+pub const ROTOR_POSITIONS_HELP: &str = "The letters showing in the rotor windows. Operators set these to the \
+day's ground setting or the message key; the right-hand rotor steps with every keypress.";
+
+pub const ROTOR_ORDER_HELP: &str = "Which way the rotor slots are listed and typed. Key sheets and many \
+simulators go left to right from the reflector; others start at the entry side. Only the listing changes, \
+not the machine.";
+
+pub const FINGERPRINT_HELP: &str = "A short hash of the rotors, rings, reflector and plugboard. Two stations \
+with the same daily key see the same fingerprint, so they can compare it without reading the key aloud.";
+
+pub const TRANSMISSION_HELP: &str = "The message as sent by radio: letter count, ground setting, enciphered \
+message key and Kenngruppe in the header, then five-letter groups. The Kenngruppe is a simplified stand-in for \
+the naval Kenngruppenbuch that tells the receiver which key to use. Spaces go as X. A new ground setting and \
+message key are drawn each time.";
+
+pub const STECKER_WARNING_HELP: &str = "Each cable swaps two letters before and after the rotors. With only a few, \
+most letters go straight through and the key is much easier to break. Keys in service used ten.";
+
+pub const CALIBRATION_HELP: &str = "For matching a surviving machine whose rings are engraved out of step with the \
+standard alphabet. Each window shows its letter this many places on, and the rotor boxes take the engraved \
+letter. Wiring, ring settings, key sheets and the positions field stay in standard letters, so the ciphertext \
+is unchanged.";
+
+pub const UI_MODE_HELP: &str = "Operator shows what a signals operator worked with: the keyboard, the rotor \
+windows and the lampboard. Expert adds the key sheet, the settings as text, the machine's internals, the wiring \
+and tracing views, and the analysis tools.";
+
+pub const WINDOW_READING_HELP: &str = "Pick the letter each window shows, for example on a photo of a real machine. \
+That is the rotor's position; the wiring core underneath sits the ring setting further back, shown as core.";
+
+pub const REFLECTOR_HELP: &str = "The reflector (Umkehrwalze) sent the current back through the rotors by a \
+different path. It made the machine reciprocal, but also meant no letter could encipher to itself.";
+
+pub const OUTPUT_HELP: &str = "What the lamps lit, letter by letter. Operators wrote it down in groups of five \
+for the radio operator; typing it back in at the same settings gives the plaintext.";
+
+pub const ABOUT_TITLE: &str = "About the Enigma Machine";
+
+pub const ABOUT_INTRO: &str = "The Enigma machine was a cryptographic device used by the German military in \
+World War II for secure communication. It uses a combination of rotors, a plugboard, and a reflector to encrypt \
+and decrypt messages. Here's a brief overview of its components:";
+
+pub const ABOUT_COMPONENTS: &[&str] = &[
+    "Rotors: These are disks with wiring that scrambles the letters. Each rotor can be set to a starting position, \
+affecting the encryption. The historical Enigma machine had three rotors.",
+    "Plugboard: A panel used to swap pairs of letters before and after they pass through the rotors.",
+    "Reflector: A component that redirects the signal back through the rotors in a different path, ensuring that \
+the machine can both encrypt and decrypt messages using the same settings.",
+];
+
+pub const ABOUT_HISTORY: &str = "Historically, the rotor arrangement and plugboard configurations were changed \
+daily. Operators would receive codebooks with daily settings.";
+
+pub const ABOUT_VERSION: &str = "Version";
+
+pub const ABOUT_CREDITS: &str = "Written by rbnyng with egui and eframe. Rotor and reflector wirings are the \
+published Wehrmacht and Kriegsmarine ones.";
+
+pub const ABOUT_LEARN_MORE: &str = "Learn more";
+
+// (link text, URL)
+pub const ABOUT_LINKS: &[(&str, &str)] = &[
+    ("Enigma machine (Wikipedia)", "https://en.wikipedia.org/wiki/Enigma_machine"),
+    ("Cryptanalysis of the Enigma (Wikipedia)", "https://en.wikipedia.org/wiki/Cryptanalysis_of_the_Enigma"),
+    ("Enigma rotor details (Crypto Museum)", "https://www.cryptomuseum.com/crypto/enigma/wiring.htm"),
+];
+
+pub const ABOUT_DISMISS: &str = "Press Escape or click outside this window to close it.";
@@ -0,0 +1,280 @@
+// A message as the radio operator sent it: a header with the letter count,
+// the ground setting sent in clear, the enciphered message key and the
+// Kenngruppe, then the text in five-letter groups. The Kenngruppe is a
+// simplified stand-in for the naval Kenngruppenbuch: one of a few trigrams
+// derived from the daily key, padded to a group with random letters, so the
+// receiver can tell which key the message was sent under.
+
+use crate::key_sheet::kenngruppen;
+use crate::{Alphabet, EnigmaKey, Rng, SpaceConvention, sanitize};
+
+pub const GROUP_SIZE: usize = 5;
+
+// Groups per line, as they were written on the message form
+const GROUPS_PER_LINE: usize = 10;
+
+// Random letters ahead of the trigram, padding the Kenngruppe to a group
+const KENNGRUPPE_FILLERS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transmission {
+    // Chosen by the operator and sent in clear
+    pub ground: String,
+    // The message key enciphered at the ground setting
+    pub indicator: String,
+    // Two filler letters and one of the day's trigrams, sent in clear in
+    // the header
+    pub kenngruppe: String,
+    pub ciphertext: String,
+}
+
+// Splits letters into groups of `size`, the last one possibly shorter
+pub fn group_letters(letters: &str, size: usize) -> String {
+    letters.chars()
+        .collect::<Vec<_>>()
+        .chunks(size)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Ciphertext grouped as it is typed. Each letter is added to the end of the
+// grouped text, so a keystroke never regroups what came before.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LiveGroups {
+    text: String,
+    letters: usize,
+}
+
+impl LiveGroups {
+    pub fn push(&mut self, letter: char) {
+        if self.letters > 0 && self.letters.is_multiple_of(GROUP_SIZE) {
+            self.text.push(' ');
+        }
+        self.text.push(letter);
+        self.letters += 1;
+    }
+
+    // Removes the last letter, and the group break before it if it opened
+    // a group
+    pub fn pop(&mut self) -> Option<char> {
+        let letter = self.text.pop()?;
+        self.letters -= 1;
+        if self.text.ends_with(' ') {
+            self.text.pop();
+        }
+        Some(letter)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn letters(&self) -> usize {
+        self.letters
+    }
+}
+
+fn random_letters(rng: &mut Rng, count: usize) -> String {
+    (0..count).map(|_| Alphabet::index_to_char(rng.below(26))).collect()
+}
+
+impl Transmission {
+    // Enciphers `text` under the key the way an operator would: spaces sent
+    // as X, other characters left out, a random ground setting and message
+    // key for this message alone
+    pub fn prepare(key: &EnigmaKey, text: &str, rng: &mut Rng) -> Result<Self, String> {
+        let mut machine = key.build()?;
+        let rotors = machine.rotors.len();
+        let ground = random_letters(rng, rotors);
+        let message_key = random_letters(rng, rotors);
+
+        // Settings are read left to right; the machine keeps its rotors the
+        // other way round
+        let right_to_left = |letters: &str| letters.chars().rev().collect::<Vec<_>>();
+        machine.set_positions(&right_to_left(&ground))?;
+        let indicator: String = message_key.chars().map(|c| machine.encode_char(c)).collect();
+        machine.set_positions(&right_to_left(&message_key))?;
+
+        let fillers = random_letters(rng, KENNGRUPPE_FILLERS);
+        let trigrams = kenngruppen(key);
+        let kenngruppe = fillers + &trigrams[rng.below(trigrams.len())];
+
+        let ciphertext = sanitize(&SpaceConvention::X.apply(text)).0.chars()
+            .map(|c| machine.encode_char(c))
+            .collect();
+
+        Ok(Self { ground, indicator, kenngruppe, ciphertext })
+    }
+
+    // The receiving side: set the ground from the header, decipher the
+    // indicator to get the message key, and decipher the text from there
+    pub fn decode(&self, key: &EnigmaKey) -> Result<String, String> {
+        let mut machine = key.build()?;
+        machine.set_positions(&self.ground.chars().rev().collect::<Vec<_>>())?;
+        let message_key: Vec<char> = self.indicator.chars().map(|c| machine.encode_char(c)).collect();
+        machine.set_positions(&message_key.into_iter().rev().collect::<Vec<_>>())?;
+        let mut plaintext = String::new();
+        machine.encode_decode(self.ciphertext.clone(), &mut plaintext);
+        Ok(plaintext)
+    }
+
+    // Reads back what render writes: the header with the Kenngruppe, then
+    // the groups. Line breaks and spacing in the groups are not significant.
+    pub fn parse(sheet: &str) -> Result<Self, String> {
+        let mut lines = sheet.lines().filter(|line| !line.trim().is_empty());
+        let header = lines.next().ok_or("Nothing to receive: paste a transmission first.")?;
+        let bad_header = || format!("Expected a header like '45 = BPF SCY = VMGJL =', got '{}'.", header.trim());
+        let fields: Vec<&str> = header.split('=').map(str::trim).collect();
+        let [count, settings, kenngruppe, ""] = fields.as_slice() else {
+            return Err(bad_header());
+        };
+        let count: usize = count.parse().map_err(|_| bad_header())?;
+        let [ground, indicator] = settings.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(bad_header());
+        };
+        let letters: String = lines.flat_map(str::chars).filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase();
+
+        if !(ground.len() == indicator.len() && format!("{}{}{}{}", ground, indicator, kenngruppe, letters).chars().all(|c| c.is_ascii_alphabetic())) {
+            return Err("A transmission holds letters only, with the ground setting and indicator the same length.".to_string());
+        }
+        if kenngruppe.len() != GROUP_SIZE {
+            return Err(format!("The Kenngruppe should be {} letters, got '{}'.", GROUP_SIZE, kenngruppe));
+        }
+        if letters.len() != count {
+            return Err(format!("The header counts {} letters but {} were received.", count, letters.len()));
+        }
+        Ok(Self {
+            ground: ground.to_ascii_uppercase(),
+            indicator: indicator.to_ascii_uppercase(),
+            kenngruppe: kenngruppe.to_ascii_uppercase(),
+            ciphertext: letters,
+        })
+    }
+
+    // Which of `keys` the message was sent under: the one whose trigrams
+    // include the Kenngruppe's. Should two keys share a trigram, the first
+    // in the list wins.
+    pub fn identify_key(&self, keys: &[EnigmaKey]) -> Option<usize> {
+        let trigram = &self.kenngruppe[KENNGRUPPE_FILLERS..];
+        keys.iter().position(|key| kenngruppen(key).iter().any(|candidate| candidate == trigram))
+    }
+
+    // The letters in the groups; the Kenngruppe in the header is not counted
+    pub fn letter_count(&self) -> usize {
+        self.ciphertext.len()
+    }
+
+    pub fn render(&self) -> String {
+        let body = group_letters(&self.ciphertext, GROUP_SIZE);
+        let groups: Vec<&str> = body.split(' ').collect();
+        let mut sheet = format!("{} = {} {} = {} =\n", self.letter_count(), self.ground, self.indicator, self.kenngruppe);
+        for line in groups.chunks(GROUPS_PER_LINE) {
+            sheet += &line.join(" ");
+            sheet.push('\n');
+        }
+        sheet
+    }
+}
+
+// A day's traffic: every message under the same daily key, each with its
+// own ground setting and message key
+pub fn prepare_batch(key: &EnigmaKey, messages: &[&str], rng: &mut Rng) -> Result<Vec<Transmission>, String> {
+    messages.iter().map(|message| Transmission::prepare(key, message, rng)).collect()
+}
+
+// The receiving operator's work on a transmission: find which of the keys
+// in force it was sent under, then decipher it. Returns the key's index
+// with the plaintext.
+pub fn receive(sheet: &str, keys: &[EnigmaKey]) -> Result<(usize, String), String> {
+    let transmission = Transmission::parse(sheet)?;
+    let index = transmission.identify_key(keys).ok_or_else(|| {
+        format!("Kenngruppe {} does not match any key held.", &transmission.kenngruppe[KENNGRUPPE_FILLERS..])
+    })?;
+    Ok((index, transmission.decode(&keys[index])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_sheet::random_key;
+    use crate::parse_key_sheet_row;
+
+    const KEY: &str = "Walzenlage II IV V Ringstellung 02 21 12 Umkehrwalze B Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX";
+
+    #[test]
+    fn groups_letters_in_fives() {
+        assert_eq!(group_letters("ABCDEFGHIJKL", 5), "ABCDE FGHIJ KL");
+        assert_eq!(group_letters("", 5), "");
+    }
+
+    #[test]
+    fn live_groups_match_batch_grouping() {
+        let letters = "GCDSEAHUGWTQGRKVLFGXU";
+        let mut live = LiveGroups::default();
+        for i in 1..=letters.len() {
+            live.push(letters.as_bytes()[i - 1] as char);
+            assert_eq!(live.as_str(), group_letters(&letters[..i], GROUP_SIZE));
+        }
+        for i in (0..letters.len()).rev() {
+            assert_eq!(live.pop(), letters.chars().nth(i));
+            assert_eq!(live.as_str(), group_letters(&letters[..i], GROUP_SIZE));
+            assert_eq!(live.letters(), i);
+        }
+        assert_eq!(live.pop(), None);
+    }
+
+    #[test]
+    fn renders_a_known_transmission() {
+        let key = parse_key_sheet_row(KEY).unwrap();
+        let transmission = Transmission::prepare(&key, "Attack at dawn, hold the bridge until relieved", &mut Rng::new(1940)).unwrap();
+        assert_eq!(
+            transmission.render(),
+            "45 = BPF SCY = VMGJL =\nVGEGF OHQZB BFCDI VHNEF RNFQX YJRDH PLWLE BUFAT NMMBM\n",
+        );
+    }
+
+    #[test]
+    fn receiver_recovers_the_message() {
+        let key = parse_key_sheet_row(KEY).unwrap();
+        let transmission = Transmission::prepare(&key, "Meet at the bridge", &mut Rng::new(7)).unwrap();
+        assert!(kenngruppen(&key).contains(&transmission.kenngruppe[2..].to_string()));
+
+        assert_eq!(transmission.decode(&key).unwrap(), "MEETXATXTHEXBRIDGE");
+    }
+
+    #[test]
+    fn kenngruppe_picks_the_key_on_receipt() {
+        let mut rng = Rng::new(1942);
+        let month: Vec<EnigmaKey> = (0..31).map(|_| random_key(&mut rng)).collect();
+        let sent = Transmission::prepare(&month[17], "Convoy sighted north", &mut rng).unwrap();
+        let sheet = sent.render();
+
+        assert_eq!(Transmission::parse(&sheet).unwrap(), sent);
+        assert_eq!(receive(&sheet, &month).unwrap(), (17, "CONVOYXSIGHTEDXNORTH".to_string()));
+
+        let other_days: Vec<EnigmaKey> = month.iter().enumerate().filter(|&(day, _)| day != 17).map(|(_, key)| key.clone()).collect();
+        assert!(receive(&sheet, &other_days).unwrap_err().starts_with("Kenngruppe"));
+        assert!(Transmission::parse(&sheet.replacen(&sent.letter_count().to_string(), "99", 1)).is_err());
+    }
+
+    #[test]
+    fn every_batch_message_decodes_from_its_own_indicator() {
+        let key = parse_key_sheet_row(KEY).unwrap();
+        let messages = ["Meet at the bridge", "Weather report follows", "Convoy sighted north", "Meet at the bridge"];
+        let batch = prepare_batch(&key, &messages, &mut Rng::new(1941)).unwrap();
+        assert_eq!(batch.len(), messages.len());
+
+        for (transmission, message) in batch.iter().zip(messages) {
+            let expected = SpaceConvention::X.apply(message).to_uppercase();
+            assert_eq!(transmission.decode(&key).unwrap(), expected);
+        }
+        // The same text under a fresh message key gives different ciphertext
+        assert_ne!(batch[0].indicator, batch[3].indicator);
+        assert_ne!(batch[0].ciphertext, batch[3].ciphertext);
+    }
+}
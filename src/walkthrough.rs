@@ -0,0 +1,203 @@
+// One keypress taken through the machine a stage at a time, for teaching.
+// The rotors step when the key goes down, as on the real machine; after
+// that each step moves the signal through one part, with a sentence saying
+// what happened to the letter.
+
+use crate::EnigmaMachine;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowMotion {
+    // The letter after every stage, as EnigmaMachine::trace records it
+    trace: Vec<char>,
+    // Window letters once the rotors have stepped, machine order
+    windows: Vec<char>,
+    // Stages done so far
+    cursor: usize,
+    // The letter between the plugboard and the entry wheel on the way in,
+    // and between the entry wheel and the plugboard on the way out. The
+    // trace folds each pair into one stage.
+    plugged: char,
+    unplugged: char,
+    straight_entry: bool,
+    bypass_plugboard: bool,
+}
+
+impl SlowMotion {
+    // Presses `letter` on the machine, which steps it
+    pub fn new(machine: &mut EnigmaMachine, letter: char) -> Self {
+        let trace = machine.trace_char(letter.to_ascii_uppercase());
+        let windows = machine.rotors.iter().map(|rotor| rotor.window_letter()).collect();
+        Self {
+            plugged: machine.stecker(trace[0]),
+            unplugged: machine.exit(trace[trace.len() - 2]),
+            straight_entry: machine.entry_wheel == EnigmaMachine::STRAIGHT_ENTRY_WHEEL,
+            bypass_plugboard: machine.bypass_plugboard,
+            trace,
+            windows,
+            cursor: 0,
+        }
+    }
+
+    // Plugboard in, each rotor forward, the reflector, each rotor back and
+    // the plugboard out
+    pub fn stages(&self) -> usize {
+        self.trace.len() - 1
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn finished(&self) -> bool {
+        self.cursor == self.stages()
+    }
+
+    // The whole path, for drawing once the lamp is lit
+    pub fn trace(&self) -> &[char] {
+        &self.trace
+    }
+
+    // Where the signal is now
+    pub fn letter(&self) -> char {
+        self.trace[self.cursor]
+    }
+
+    // The lamp that lights, once the signal has reached it
+    pub fn lamp(&self) -> Option<char> {
+        self.finished().then(|| self.letter())
+    }
+
+    // Moves the signal through the next part and explains it; None once the
+    // lamp is lit
+    pub fn advance(&mut self) -> Option<String> {
+        if self.finished() {
+            return None;
+        }
+        self.cursor += 1;
+        Some(self.explain(self.cursor - 1))
+    }
+
+    // Explanations of the stages done so far, in order
+    pub fn explanations(&self) -> Vec<String> {
+        (0..self.cursor).map(|stage| self.explain(stage)).collect()
+    }
+
+    fn explain(&self, stage: usize) -> String {
+        let rotors = self.windows.len();
+        let (from, to) = (self.trace[stage], self.trace[stage + 1]);
+        // Rotors are numbered from the entry side, so rotor 1 is the fast one
+        let rotor = |number: usize| format!("Rotor {} (window {})", number, self.windows[number - 1]);
+        match stage {
+            0 => self.way_in(from, to),
+            s if s <= rotors => format!("{} carries {} to {} on the way in.", rotor(s), from, to),
+            s if s == rotors + 1 => format!("The reflector sends {} back as {}.", from, to),
+            s if s < self.stages() - 1 => format!("{} carries {} to {} on the way back.", rotor(2 * rotors + 2 - s), from, to),
+            _ => self.way_out(from, to),
+        }
+    }
+
+    // The first stage: the plugboard, then the entry wheel when it is not
+    // wired straight through
+    fn way_in(&self, key: char, contact: char) -> String {
+        let plugged = self.plugged;
+        let plugboard = if self.bypass_plugboard {
+            format!("The plugboard is switched off, so {} goes on unchanged.", key)
+        } else if plugged == key {
+            format!("It has no plugboard cable, so {} goes on unchanged.", key)
+        } else {
+            format!("The plugboard turns it into {}.", plugged)
+        };
+        let entry = if self.straight_entry {
+            String::new()
+        } else if plugged == contact {
+            format!(" The entry wheel leaves it as {}.", contact)
+        } else {
+            format!(" The entry wheel wires it to {}.", contact)
+        };
+        format!("Key {} is pressed. {}{}", key, plugboard, entry)
+    }
+
+    // The last stage, the first one mirrored: the entry wheel, then the
+    // plugboard and the lamp
+    fn way_out(&self, contact: char, lamp: char) -> String {
+        let unplugged = self.unplugged;
+        let entry = if self.straight_entry {
+            String::new()
+        } else if unplugged == contact {
+            format!("The entry wheel leaves {} as it is. ", contact)
+        } else {
+            format!("The entry wheel carries {} back to {}. ", contact, unplugged)
+        };
+        let plugboard = if self.bypass_plugboard {
+            format!("The plugboard is switched off, so lamp {} lights.", lamp)
+        } else if unplugged == lamp {
+            format!("{} has no plugboard cable on the way out, so lamp {} lights.", unplugged, lamp)
+        } else {
+            format!("The plugboard turns {} into {}, and lamp {} lights.", unplugged, lamp, lamp)
+        };
+        format!("{}{}", entry, plugboard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_through_every_stage_to_the_lamp() {
+        let mut machine = EnigmaMachine::default();
+        let mut slow = SlowMotion::new(&mut machine, 'a');
+        // Three rotors: plugboard, three rotors, reflector, three rotors, plugboard
+        assert_eq!(slow.stages(), 9);
+        assert_eq!(slow.letter(), 'A');
+
+        let mut steps = Vec::new();
+        while let Some(step) = slow.advance() {
+            steps.push(step);
+            assert_eq!(slow.lamp().is_some(), slow.cursor() == 9);
+        }
+        assert_eq!(steps.len(), 9);
+        assert_eq!(slow.explanations(), steps);
+        assert_eq!(slow.advance(), None);
+
+        // The same keypress on a fresh machine lights the same lamp
+        let lamp = EnigmaMachine::default().encode_char('A');
+        assert_eq!(slow.lamp(), Some(lamp));
+        assert!(steps[0].starts_with("Key A is pressed."));
+        assert!(steps[1].starts_with("Rotor 1 (window B)"));
+        assert!(steps[4].starts_with("The reflector"));
+        assert!(steps[5].starts_with("Rotor 3"));
+        assert!(steps[8].ends_with(&format!("lamp {} lights.", lamp)));
+
+        // The keypress stepped the real machine, and only once
+        assert_eq!(machine.rotors[0].window_letter(), 'B');
+    }
+
+    #[test]
+    fn credits_the_entry_wheel_and_a_switched_off_plugboard() {
+        let mut machine = EnigmaMachine {
+            entry_wheel: EnigmaMachine::QWERTZ_ENTRY_WHEEL,
+            plugboard: crate::Plugboard::new(&[('A', 'B')]).unwrap(),
+            bypass_plugboard: true,
+            ..EnigmaMachine::default()
+        };
+        let mut slow = SlowMotion::new(&mut machine, 'A');
+        while slow.advance().is_some() {}
+        let steps = slow.explanations();
+
+        // In keyboard order A is wired to the tenth contact, J
+        assert_eq!(steps[0], "Key A is pressed. The plugboard is switched off, so A goes on unchanged. The entry wheel wires it to J.");
+        assert!(steps[8].contains("The entry wheel"), "{}", steps[8]);
+        assert!(steps[8].ends_with(&format!("The plugboard is switched off, so lamp {} lights.", slow.lamp().unwrap())));
+
+        // With the plugboard on, the cable is credited and the entry wheel
+        // still gets its own sentence
+        let mut machine = EnigmaMachine {
+            entry_wheel: EnigmaMachine::QWERTZ_ENTRY_WHEEL,
+            plugboard: crate::Plugboard::new(&[('A', 'B')]).unwrap(),
+            ..EnigmaMachine::default()
+        };
+        let mut slow = SlowMotion::new(&mut machine, 'A');
+        assert_eq!(slow.advance().unwrap(), "Key A is pressed. The plugboard turns it into B. The entry wheel wires it to W.");
+    }
+}
@@ -0,0 +1,133 @@
+use enigma_machine::cli::{check_vectors, generate_vectors, parse_args, parse_repl_args, run, run_repl, Repl, VECTOR_COUNT, VECTOR_SEED};
+
+const PLAINTEXT: &str = "Attack at dawn, hold the bridge until relieved\n";
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn file_round_trips_with_same_settings() {
+    let dir = std::env::temp_dir().join(format!("enigma_cli_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let plain = dir.join("message.txt");
+    let cipher = dir.join("cipher.txt");
+    let decoded = dir.join("decoded.txt");
+    std::fs::write(&plain, PLAINTEXT).unwrap();
+
+    let settings = ["--rotors", "II I III", "--rings", "XMV", "--positions", "ABL", "--plugboard", "AM FI NV PS TU WZ", "--reflector", "A"];
+    let paths = |from: &std::path::Path, to: &std::path::Path| {
+        let mut list = args(&["--in", from.to_str().unwrap(), "--out", to.to_str().unwrap()]);
+        list.extend(args(&settings));
+        list
+    };
+
+    let count = run(&parse_args(&paths(&plain, &cipher)).unwrap()).unwrap();
+    run(&parse_args(&paths(&cipher, &decoded)).unwrap()).unwrap();
+
+    let expected: String = PLAINTEXT.to_uppercase().chars().filter(|c| c.is_ascii_alphabetic()).collect();
+    assert_eq!(count, expected.len());
+    assert_ne!(std::fs::read_to_string(&cipher).unwrap(), expected);
+    assert_eq!(std::fs::read_to_string(&decoded).unwrap(), expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn non_utf8_input_is_an_error() {
+    let dir = std::env::temp_dir().join(format!("enigma_cli_bad_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("binary.bin");
+    std::fs::write(&input, [0x48, 0x49, 0xFF, 0xFE]).unwrap();
+
+    let job = parse_args(&args(&["--in", input.to_str().unwrap(), "--out", dir.join("out.txt").to_str().unwrap()])).unwrap();
+    let error = run(&job).unwrap_err();
+    assert!(error.ends_with("Input is not valid UTF-8."), "{}", error);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_output_is_reported() {
+    let error = parse_args(&args(&["--in", "message.txt"])).err().unwrap();
+    assert!(error.starts_with("Missing --out."));
+}
+
+#[test]
+fn repl_steps_across_lines_and_obeys_commands() {
+    let key = parse_repl_args(&args(&["--positions", "AAZ"])).unwrap();
+    let mut expected_machine = key.build().unwrap();
+    let mut expected = String::new();
+    expected_machine.encode_decode("HELLOWORLD".to_string(), &mut expected);
+
+    let script = "hello\nworld\n:pos A A Z\nHello World\n:bogus\n:pos AAC\n:reset\nhelloworld\n:key\n:quit\nnever read\n";
+    let mut repl = Repl::new(key).unwrap();
+    let mut output = Vec::new();
+    run_repl(&mut repl, script.as_bytes(), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.split("> ").skip(1).map(str::trim_end).collect();
+    assert_eq!(lines, [
+        &expected[..5],
+        &expected[5..],
+        "Positions set to AAZ.",
+        &expected,
+        "Error: Unknown command ':bogus'. Type :help for the list.",
+        "Positions set to AAC.",
+        "Positions reset to AAZ.",
+        &expected,
+        // Ten letters on from AAZ
+        "Walzenlage III II I Ringstellung 01 01 01 Umkehrwalze B Grundstellung 01 01 10 Steckerverbindungen AB CD",
+        "",
+    ]);
+}
+
+#[test]
+fn spaced_settings_accept_lowercase_letters() {
+    let job = |rings: &str, positions: &str| {
+        parse_args(&args(&["--in", "in.txt", "--out", "out.txt", "--rings", rings, "--positions", positions])).unwrap()
+    };
+    let lower = job("a b c", "a b l");
+    let upper = job("A B C", "A B L");
+    assert_eq!(lower.key, upper.key);
+
+    let mut machine = lower.key.build().unwrap();
+    let mut output = String::new();
+    machine.encode_decode("ATTACKATDAWN".to_string(), &mut output);
+    let mut expected = String::new();
+    upper.key.build().unwrap().encode_decode("ATTACKATDAWN".to_string(), &mut expected);
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn non_letter_plugs_are_an_error() {
+    for plugs in ["é", "A1", "AB C"] {
+        let result = parse_args(&args(&["--in", "in.txt", "--out", "out.txt", "--plugboard", plugs]));
+        assert!(result.is_err(), "{}", plugs);
+    }
+}
+
+#[test]
+fn repl_rejects_file_paths() {
+    let error = parse_repl_args(&args(&["--in", "message.txt"])).err().unwrap();
+    assert!(error.starts_with("--in and --out do not apply"));
+}
+
+#[test]
+fn vectors_regenerate_byte_for_byte() {
+    let first = generate_vectors(VECTOR_SEED, VECTOR_COUNT).unwrap();
+    let second = generate_vectors(VECTOR_SEED, VECTOR_COUNT).unwrap();
+    assert_eq!(first.as_bytes(), second.as_bytes());
+    assert_eq!(check_vectors(&first, &second), Ok(VECTOR_COUNT));
+}
+
+// Regenerate with `--gen-vectors > tests/vectors.txt` only when the cipher
+// is meant to change
+#[test]
+fn vectors_match_the_golden_file() {
+    let generated = generate_vectors(VECTOR_SEED, VECTOR_COUNT).unwrap();
+    assert_eq!(check_vectors(include_str!("vectors.txt"), &generated), Ok(VECTOR_COUNT));
+
+    let tampered = include_str!("vectors.txt").replacen("cipher: ", "cipher: A", 1);
+    assert!(check_vectors(&tampered, &generated).unwrap_err().starts_with("Line 5 differs."));
+}
@@ -0,0 +1,66 @@
+// Published messages with their full settings. Each one exercises ring
+// settings, stepping order and the double step together, so a regression in
+// any of them shows up as a wrong letter here.
+use enigma_machine::parse_key_sheet_row;
+
+fn encipher(row: &str, text: &str) -> String {
+    let mut machine = parse_key_sheet_row(row).unwrap().build().unwrap();
+    let mut output = String::new();
+    machine.encode_decode(text.to_string(), &mut output);
+    output
+}
+
+fn assert_reciprocal(row: &str, plaintext: &str, ciphertext: &str) {
+    assert_eq!(encipher(row, plaintext), ciphertext.replace(' ', ""));
+    assert_eq!(encipher(row, ciphertext), plaintext);
+}
+
+// The worked example from the 1930 Reichswehr instruction manual
+// (Gebrauchsanleitung für die Chiffriermaschine Enigma)
+#[test]
+fn instruction_manual_1930() {
+    assert_reciprocal(
+        "Walzenlage II I III Ringstellung 24 13 22 Grundstellung A B L Umkehrwalze A \
+         Steckerverbindungen AM FI NV PS TU WZ",
+        "FEINDLIQEINFANTERIEKOLONNEBEOBAQTETXANFANGSUEDAUSGANGBAERWALDEXENDEDREIKMOSTWAERTSNEUSTADT",
+        "GCDSE AHUGW TQGRK VLFGX UCALX VYMIG MMNMF DXTGN VHVRM MEVOU YFZSL RHDRR XFJWC FHUHM UNZEF RDISI KBGPM YVXUZ",
+    );
+}
+
+// First part of a German Army message from 7 July 1941, during Operation
+// Barbarossa
+#[test]
+fn operation_barbarossa_1941() {
+    assert_reciprocal(
+        "Walzenlage II IV V Ringstellung 02 21 12 Grundstellung B L A Umkehrwalze B \
+         Steckerverbindungen AV BS CG DL FU HZ IN KM OW RX",
+        "AUFKLXABTEILUNGXVONXKURTINOWAXKURTINOWAXNORDWESTLXSEBEZXSEBEZXUAFFLIEGERSTRASZERIQTUNGXDUBROWKIXDUBROWKIXOPOTSCHKAXOPOTSCHKAXUMXEINSAQTDREINULLXUHRANGETRETENXANGRIFFXINFXRGTX",
+        "EDPUD NRGYS ZRCXN UYTPO MRMBO FKTBZ REZKM LXLVE FGUEY SIOZV EQMIK UBPMM YLKLT TDEIS MDICA GYKUA CTCDO MOHWX MUUIA UBSTS LRNBZ SZWNR FXWFY SSXJZ VIJHI DISHP RKLKA YUPAD TXQSP INQMA TLPIF SVKDA SCTAC DPBOP VHJK",
+    );
+}
+
+// Signal from U-264 (Kapitänleutnant Hartwig Looks), 25 November 1942, on a
+// four-rotor M4 with the fixed Greek wheel and the thin reflector
+#[test]
+fn u264_m4_1942() {
+    assert_reciprocal(
+        "Walzenlage Beta II IV I Ringstellung 01 01 01 22 Grundstellung V J N A Umkehrwalze B-thin \
+         Steckerverbindungen AT BL DF GJ HM NW OP QY RZ VX",
+        "VONVONJLOOKSJHFFTTTEINSEINSDREIZWOYYQNNSNEUNINHALTXXBEIANGRIFFUNTERWASSERGEDRUECKTYWABOSXLETZTERGEGNERSTANDNULACHTDREINULUHRMARQUANTONJOTANEUNACHTSEYHSDREIYZWOZWONULGRADYACHTSMYSTOSSENACHXEKNSVIERMBFAELLTYNNNNNNOOOVIERYSICHTEINSNULL",
+        "NCZW VUSX PNYM INHZ XMQX SFWX WLKJ AHSH NMCO CCAK UQPM KCSM HKSE INJU SBLK IOSX CKUB HMLL XCSJ USRR DVKO HULX WCCB GVLI YXEO AHXR HKKF VDRE WEZL XOBA FGYU JQUK GRTV UKAM EURB VEKS UHHV OYHA BCJW MAKL FKLM YFVN RIZR VVRT KOFD ANJM OLBG FFLE OPRG TFLV RHOW OPBE KVWM UQFM PWPA RMFH AGKX IIBG",
+    );
+}
+
+// The middle rotor steps twice in a row when it reaches its own notch:
+// ADU -> ADV -> AEW -> BFX on rotors I II III
+#[test]
+fn double_step_sequence() {
+    let row = "Walzenlage I II III Ringstellung 01 01 01 Grundstellung A D U Umkehrwalze B Steckerverbindungen";
+    let mut machine = parse_key_sheet_row(row).unwrap().build().unwrap();
+    let mut windows = Vec::new();
+    for _ in 0..4 {
+        machine.encode_char('A');
+        windows.push(machine.rotors.iter().rev().map(|rotor| rotor.window_letter()).collect::<String>());
+    }
+    assert_eq!(windows, ["ADV", "AEW", "BFX", "BFY"]);
+}
@@ -0,0 +1,37 @@
+use enigma_machine::EnigmaMachine;
+
+const PLAINTEXT: &str = "Attack at dawn, hold the bridge until relieved";
+
+fn filtered(text: &str) -> String {
+    text.to_uppercase().chars().filter(|c| c.is_ascii_alphabetic()).collect()
+}
+
+#[test]
+fn decode_reverses_encode() {
+    let mut sender = EnigmaMachine::default();
+    // An identical machine at the same start positions, as the receiver would set up
+    let mut receiver = sender.clone();
+
+    let mut ciphertext = String::new();
+    sender.encode_decode(PLAINTEXT.to_string(), &mut ciphertext);
+
+    let mut decoded = String::new();
+    receiver.encode_decode(ciphertext.clone(), &mut decoded);
+
+    assert_eq!(decoded, filtered(PLAINTEXT));
+    assert_ne!(ciphertext, filtered(PLAINTEXT));
+}
+
+#[test]
+fn no_letter_encodes_to_itself() {
+    let mut machine = EnigmaMachine::default();
+    let plaintext = filtered(PLAINTEXT);
+
+    let mut ciphertext = String::new();
+    machine.encode_decode(plaintext.clone(), &mut ciphertext);
+
+    assert_eq!(ciphertext.len(), plaintext.len());
+    for (i, (plain, cipher)) in plaintext.chars().zip(ciphertext.chars()).enumerate() {
+        assert_ne!(plain, cipher, "letter {} encoded to itself at position {}", plain, i);
+    }
+}